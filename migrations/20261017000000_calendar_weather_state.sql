@@ -0,0 +1,37 @@
+-- Last fetched state, so a restart doesn't blank the screen
+
+CREATE TABLE calendar_holidays (
+    date DATE NOT NULL,
+    source TEXT NOT NULL,
+    name TEXT NOT NULL,
+    color TEXT NOT NULL,
+    PRIMARY KEY (date, source)
+);
+
+CREATE TABLE calendar_events (
+    date DATE NOT NULL,
+    id TEXT NOT NULL,
+    source TEXT NOT NULL,
+    name TEXT NOT NULL,
+    start_at TIMESTAMPTZ NOT NULL,
+    end_at TIMESTAMPTZ NOT NULL,
+    all_day BOOLEAN NOT NULL,
+    color TEXT NOT NULL,
+    day INTEGER NOT NULL,
+    days INTEGER NOT NULL,
+    PRIMARY KEY (date, id)
+);
+
+CREATE INDEX calendar_events_source_idx ON calendar_events (source);
+
+CREATE TABLE weather_snapshots (
+    id BIGSERIAL PRIMARY KEY,
+    fetched_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    data JSONB NOT NULL
+);
+
+-- Single row table
+CREATE TABLE last_update (
+    id BOOLEAN PRIMARY KEY DEFAULT TRUE CHECK (id),
+    updated_at TIMESTAMP NOT NULL
+);
@@ -0,0 +1,6 @@
+-- Latest daily/hourly forecast returned by weather.get_forecasts
+CREATE TABLE IF NOT EXISTS weather_forecasts (
+    id BOOLEAN PRIMARY KEY DEFAULT TRUE CHECK (id),
+    fetched_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    data JSONB NOT NULL
+);
@@ -38,10 +38,10 @@ pub struct ApiErrorResp {
 //
 // To avoid exposing implementation details to API consumers, we separate
 // the message that we log from the API response message.
-impl IntoResponse for ApiError {
-    fn into_response(self) -> Response {
-        // Log detailed error for telemetry.
-        let error_to_log = match &self {
+impl ApiError {
+    /// Detailed message, for logs and operators only.
+    pub fn detail(&self) -> String {
+        match self {
             ApiError::InvalidJsonBody(err) => match err {
                 JsonRejection::JsonDataError(e) => e.body_text(),
                 JsonRejection::JsonSyntaxError(e) => e.body_text(),
@@ -53,9 +53,15 @@ impl IntoResponse for ApiError {
             },
             ApiError::InvalidRequest(_) => format!("{}", self),
             ApiError::DatabaseError(err) => format!("{}", err),
-            ApiError::InternalError(err) => format!("{}", err),
-        };
-        error!("{}", error_to_log);
+            ApiError::InternalError(err) => format!("{:#}", err),
+        }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        // Log detailed error for telemetry.
+        error!("{}", self.detail());
 
         // Create a generic response to hide specific implementation details.
         let resp = ApiErrorResp {
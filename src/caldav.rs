@@ -0,0 +1,113 @@
+//! Minimal CalDAV client: a `calendar-query` REPORT over a time range.
+//! See: https://datatracker.ietf.org/doc/html/rfc4791#section-7.8
+use std::io::Cursor;
+
+use ical::parser::ical::component::IcalEvent;
+use quick_xml::{Reader, events::Event};
+use time::{OffsetDateTime, macros::format_description};
+use time_tz::{OffsetDateTimeExt, timezones};
+
+use crate::{CalendarSource, api_error::ApiError, http, ics};
+
+fn utc_stamp(dt: OffsetDateTime) -> String {
+    dt.to_timezone(timezones::db::UTC)
+        .format(format_description!(
+            "[year][month][day]T[hour][minute][second]Z"
+        ))
+        .unwrap_or_default()
+}
+
+/// Body of a `calendar-query` for the VEVENTs overlapping `start`..`end`.
+pub fn calendar_query(start: OffsetDateTime, end: OffsetDateTime) -> String {
+    format!(
+        r#"<?xml version="1.0" encoding="utf-8" ?>
+<c:calendar-query xmlns:d="DAV:" xmlns:c="urn:ietf:params:xml:ns:caldav">
+  <d:prop>
+    <d:getetag/>
+    <c:calendar-data/>
+  </d:prop>
+  <c:filter>
+    <c:comp-filter name="VCALENDAR">
+      <c:comp-filter name="VEVENT">
+        <c:time-range start="{}" end="{}"/>
+      </c:comp-filter>
+    </c:comp-filter>
+  </c:filter>
+</c:calendar-query>"#,
+        utc_stamp(start),
+        utc_stamp(end)
+    )
+}
+
+/// Extracts every `calendar-data` of a `multistatus` response.
+pub fn calendar_data(xml: &str) -> Result<Vec<String>, ApiError> {
+    let mut reader = Reader::from_str(xml);
+    let mut res = Vec::new();
+    let mut current: Option<String> = None;
+
+    loop {
+        match reader
+            .read_event()
+            .map_err(|e| ApiError::InternalError(e.into()))?
+        {
+            Event::Start(e) if e.local_name().as_ref() == b"calendar-data" => {
+                current = Some(String::new());
+            }
+            Event::End(e) if e.local_name().as_ref() == b"calendar-data" => {
+                res.extend(current.take());
+            }
+            Event::Text(e) => {
+                if let Some(data) = current.as_mut() {
+                    data.push_str(
+                        &e.unescape()
+                            .map_err(|e| ApiError::InternalError(e.into()))?,
+                    );
+                }
+            }
+            Event::CData(e) => {
+                if let Some(data) = current.as_mut() {
+                    data.push_str(&String::from_utf8_lossy(&e.into_inner()));
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+
+    Ok(res)
+}
+
+/// Queries the calendar collection of `source` and returns the VEVENTs
+/// overlapping `start`..`end`.
+pub async fn fetch_events(
+    source: &CalendarSource,
+    start: OffsetDateTime,
+    end: OffsetDateTime,
+) -> Result<Vec<IcalEvent>, ApiError> {
+    let method =
+        reqwest::Method::from_bytes(b"REPORT").map_err(|e| ApiError::InternalError(e.into()))?;
+    let req = http::client()
+        .request(method, source.url.clone())
+        .header("Depth", "1")
+        .header(
+            reqwest::header::CONTENT_TYPE,
+            "application/xml; charset=utf-8",
+        )
+        .body(calendar_query(start, end));
+
+    let res = source
+        .authorize(req)
+        .send()
+        .await
+        .and_then(|r| r.error_for_status())
+        .map_err(|e| ApiError::InternalError(e.into()))?
+        .text()
+        .await
+        .map_err(|e| ApiError::InternalError(e.into()))?;
+    tracing::info!("Source {}: {} bytes", source.name, res.len());
+
+    Ok(calendar_data(&res)?
+        .into_iter()
+        .flat_map(|data| ics::parse_events(Cursor::new(data)))
+        .collect())
+}
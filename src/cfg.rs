@@ -1,13 +1,25 @@
-use serde::Deserialize;
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
 use std::{
+    collections::{BTreeMap, HashMap},
     net::{Ipv6Addr, SocketAddr},
+    path::PathBuf,
     str::FromStr,
     sync::Arc,
 };
+use time::{OffsetDateTime, PrimitiveDateTime, UtcOffset};
+use time_tz::{OffsetDateTimeExt, OffsetResult, PrimitiveDateTimeExt, Tz, timezones};
+use tokio_cron_scheduler::Job;
+
+use crate::{
+    cron,
+    panel::{self, PanelProfile},
+    retry::RetryPolicy,
+};
 
 pub type Config = Arc<Configuration>;
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Clone)]
 pub struct Configuration {
     /// The environment in which to run the application.
     pub env: Environment,
@@ -25,18 +37,310 @@ pub struct Configuration {
     pub tz: String,
 
     // * iCal list
-    pub ical_holiday: String,
-    pub ical_event: String,
+    pub calendar_sources: Vec<CalendarSource>,
+    /// Number of days, starting today, recurring events are expanded over.
+    pub calendar_window_days: i64,
 
     // * Home Assistant
     pub ha_url: String,
     pub ha_token: String,
+    /// Entity id of the weather entity shown on the page.
+    pub ha_weather_entity: String,
+    /// Follow entity changes over the WebSocket API instead of polling.
+    pub ha_websocket: bool,
+    /// Entities shown in the sensor grid, in order.
+    pub ha_sensors: Vec<HaSensor>,
+    /// `todo.*` entities whose open items are listed on the page.
+    pub ha_todo_entities: Vec<String>,
+    /// Retries and circuit breaker of the Home Assistant fetches.
+    pub ha_retry: RetryPolicy,
+
+    /// Data older than this, in seconds, is reported as stale.
+    pub stale_after: u64,
+
+    // * Page
+    /// Layout and theme of the page, instead of the built-in ones.
+    pub layout_file: Option<PathBuf>,
+    /// Panel profiles by name, the built-in ones and those of `PANEL_PROFILES`.
+    pub panel_profiles: BTreeMap<String, PanelProfile>,
+    /// Profile of requests that name neither a panel nor a known device.
+    pub panel_profile: String,
+    /// Profile names by device id.
+    pub panel_devices: HashMap<String, String>,
+
+    // * Schedules
+    /// Schedule of the sources without their own.
+    pub default_schedule: Schedule,
+    /// Schedules by source name, for sources without their own.
+    pub schedules: HashMap<String, Schedule>,
 
     // * Authentication
     pub access_token: String,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum CalendarSourceKind {
+    /// Each entry names the day, e.g. a public holiday.
+    Holiday,
+    /// Each entry is an event shown under its date.
+    Event,
+}
+
+/// Where a source is read from.
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum CalendarProvider {
+    /// An `ics` file at `url`.
+    #[default]
+    Ics,
+    /// A CalDAV calendar collection at `url`.
+    Caldav,
+    /// A Home Assistant calendar entity, read with the `HA_*` credentials.
+    HomeAssistant,
+}
+
+/// Which e-paper colour a source is drawn with.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum CalendarColor {
+    Red,
+    Black,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct CalendarSource {
+    /// Unique name, recorded on every entry fetched from this source.
+    pub name: String,
+    #[serde(default, rename = "type")]
+    pub provider: CalendarProvider,
+    /// Feed URL, for `ics` sources.
+    #[serde(default)]
+    pub url: String,
+    /// Calendar entity id, for `home-assistant` sources.
+    #[serde(default)]
+    pub entity_id: Option<String>,
+    pub kind: CalendarSourceKind,
+    /// Defaults to red for holidays and black for events.
+    #[serde(default)]
+    pub color: Option<CalendarColor>,
+    /// Value of the `Authorization` header sent with the request, e.g. `Bearer <token>`.
+    #[serde(default)]
+    pub auth_header: Option<String>,
+    /// Basic auth user name, used instead of `auth_header` when set.
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default)]
+    pub password: Option<String>,
+    /// Without it, the source is refreshed on `SCHEDULES` or the default schedule.
+    #[serde(default, alias = "refresh_interval")]
+    pub schedule: Option<Schedule>,
+    /// Retries and circuit breaker of the source, the default ones without it.
+    #[serde(default)]
+    pub retry: Option<RetryPolicy>,
+}
+
+/// When a source is fetched: every given number of seconds, or on a cron expression with
+/// seconds or an English phrase such as `every day at 4:00`.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+#[serde(untagged)]
+pub enum Schedule {
+    Interval(u64),
+    Cron(String),
+}
+
+/// A Home Assistant entity shown in the sensor grid.
+#[derive(Deserialize, Clone, Debug)]
+pub struct HaSensor {
+    pub entity_id: String,
+    pub label: String,
+    /// Material Design Icons codepoint in hex, e.g. `F050F`.
+    #[serde(default)]
+    pub icon: Option<String>,
+    /// See `HaEntityState::format`. Defaults to `{state}{unit}`.
+    #[serde(default)]
+    pub format: Option<String>,
+}
+
+impl HaSensor {
+    pub fn icon_char(&self) -> Option<char> {
+        let icon = self.icon.as_deref()?;
+        let hex = icon
+            .trim_start_matches("0x")
+            .trim_start_matches("U+")
+            .trim_start_matches("\\u");
+
+        u32::from_str_radix(hex, 16).ok().and_then(char::from_u32)
+    }
+
+    pub fn format(&self) -> &str {
+        self.format.as_deref().unwrap_or("{state}{unit}")
+    }
+}
+
+impl CalendarColor {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CalendarColor::Red => "red",
+            CalendarColor::Black => "black",
+        }
+    }
+}
+
+impl FromStr for CalendarColor {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "red" => Ok(CalendarColor::Red),
+            "black" => Ok(CalendarColor::Black),
+            _ => Err(format!(
+                "Invalid calendar color: {}. Please make sure it is either \"red\" or \"black\".",
+                s
+            )),
+        }
+    }
+}
+
+impl Schedule {
+    /// Cron expression with seconds, English phrases being translated. `None` for intervals.
+    pub fn cron(&self) -> Result<Option<String>, String> {
+        let Schedule::Cron(expr) = self else {
+            return Ok(None);
+        };
+        let cron = Job::schedule_to_cron(expr).map_err(|e| format!("{expr}: {e:?}"))?;
+        parse_cron(&cron)?;
+
+        Ok(Some(cron))
+    }
+
+    /// Checks intervals are not zero and cron expressions parse.
+    pub fn validate(&self) -> Result<(), String> {
+        match self {
+            Schedule::Interval(0) => Err("Interval must not be 0".to_string()),
+            schedule => schedule.cron().map(|_| ()),
+        }
+    }
+
+    /// First run after `after`, in `tz`. Cron expressions follow the wall
+    /// clock of `tz`, across its offset changes.
+    pub fn next_run(&self, after: OffsetDateTime, tz: &'static Tz) -> Option<OffsetDateTime> {
+        match self {
+            Schedule::Interval(secs) => {
+                Some((after + time::Duration::seconds(*secs as i64)).to_timezone(tz))
+            }
+            // Searched on the wall clock, then placed in `tz`
+            Schedule::Cron(_) => {
+                let cron = parse_cron(&self.cron().ok()??).ok()?;
+                let local = after.to_timezone(tz);
+                let mut wall = chrono::DateTime::from_timestamp(
+                    local.replace_offset(UtcOffset::UTC).unix_timestamp(),
+                    0,
+                )?;
+
+                // Each wall time after the one of `after` once, so a repeated
+                // hour is not run twice
+                for _ in 0..8 {
+                    wall = cron.find_next_occurrence(&wall, false).ok()?;
+                    let wall_t = OffsetDateTime::from_unix_timestamp(wall.timestamp()).ok()?;
+                    let wall_t = PrimitiveDateTime::new(wall_t.date(), wall_t.time());
+                    let next = match wall_t.assume_timezone(tz) {
+                        OffsetResult::Some(t) => Some(t),
+                        OffsetResult::Ambiguous(a, b) => [a, b].into_iter().find(|t| *t > after),
+                        // Skipped by the clock going forward: run as much later
+                        OffsetResult::None => {
+                            let before = (wall_t.assume_utc() - time::Duration::DAY)
+                                .to_timezone(tz)
+                                .offset();
+                            Some(wall_t.assume_offset(before).to_timezone(tz))
+                        }
+                    };
+                    if let Some(next) = next.filter(|t| *t > after) {
+                        return Some(next);
+                    }
+                }
+
+                None
+            }
+        }
+    }
+
+    /// Time between two runs, the next two for cron expressions.
+    pub fn period(&self, now: OffsetDateTime, tz: &'static Tz) -> time::Duration {
+        let next = self.next_run(now, tz);
+        let after = next.and_then(|t| self.next_run(t, tz));
+
+        match (next, after) {
+            (Some(next), Some(after)) => after - next,
+            _ => time::Duration::ZERO,
+        }
+    }
+}
+
+fn parse_cron(cron: &str) -> Result<croner::Cron, String> {
+    croner::Cron::new(cron)
+        .with_seconds_required()
+        .with_dom_and_dow()
+        .parse()
+        .map_err(|e| format!("{cron}: {e}"))
+}
+
+impl FromStr for Schedule {
+    type Err = String;
+    /// Seconds when numeric, a cron expression or English phrase otherwise.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let schedule = match s.trim().parse::<u64>() {
+            Ok(secs) => Schedule::Interval(secs),
+            Err(_) => Schedule::Cron(s.trim().to_string()),
+        };
+        schedule.validate()?;
+
+        Ok(schedule)
+    }
+}
+
+impl CalendarSource {
+    /// Applies the source credentials to a request.
+    pub fn authorize(&self, req: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        match (self.username.as_ref(), self.auth_header.as_ref()) {
+            (Some(username), _) => req.basic_auth(username, self.password.as_ref()),
+            (None, Some(auth_header)) => req.header(reqwest::header::AUTHORIZATION, auth_header),
+            (None, None) => req,
+        }
+    }
+
+    pub fn color(&self) -> CalendarColor {
+        self.color.unwrap_or(match self.kind {
+            CalendarSourceKind::Holiday => CalendarColor::Red,
+            CalendarSourceKind::Event => CalendarColor::Black,
+        })
+    }
+
+    /// Checks the name is not one of a Home Assistant fetch, which share the
+    /// schedules, retries and status of sources, and the source has a url or
+    /// entity id for its provider.
+    pub fn validate(&self) -> Result<(), String> {
+        let builtin = [
+            cron::HA_STATES_SOURCE,
+            cron::FORECAST_SOURCE,
+            cron::TODO_SOURCE,
+        ];
+        if builtin.contains(&self.name.as_str()) {
+            return Err(format!("{} is the name of a built-in source", self.name));
+        }
+
+        match self.provider {
+            CalendarProvider::Ics | CalendarProvider::Caldav if self.url.is_empty() => {
+                Err("No url for its provider".to_string())
+            }
+            CalendarProvider::HomeAssistant if self.entity_id.is_none() => {
+                Err("No entity_id for its provider".to_string())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Debug)]
 pub enum Environment {
     Development,
     Production,
@@ -63,27 +367,247 @@ impl Configuration {
 
         let tz = env_var("TZ");
 
-        let ical_holiday = env_var("ICAL_HOLIDAY");
-        let ical_event = env_var("ICAL_EVENT");
+        // Either a JSON list of sources, or the single holiday and event feeds
+        let calendar_sources = match env_var_opt("CALENDAR_SOURCES") {
+            Some(v) => serde_json::from_str::<Vec<CalendarSource>>(&v)
+                .expect("Unable to parse the value of the CALENDAR_SOURCES environment variable. Please make sure it is a JSON list of calendar sources."),
+            None => [
+                ("holiday", "ICAL_HOLIDAY", CalendarSourceKind::Holiday),
+                ("event", "ICAL_EVENT", CalendarSourceKind::Event),
+            ]
+            .into_iter()
+            .filter_map(|(name, var, kind)| {
+                Some(CalendarSource {
+                    name: name.to_string(),
+                    provider: CalendarProvider::Ics,
+                    url: env_var_opt(var)?,
+                    entity_id: None,
+                    kind,
+                    color: None,
+                    auth_header: None,
+                    username: None,
+                    password: None,
+                    schedule: None,
+                    retry: None,
+                })
+            })
+            .collect(),
+        };
+        if !calendar_sources.iter().map(|s| &s.name).all_unique() {
+            panic!("Calendar source names in CALENDAR_SOURCES must be unique.");
+        }
+        if let Some((name, e)) = calendar_sources
+            .iter()
+            .find_map(|s| s.validate().err().map(|e| (&s.name, e)))
+        {
+            panic!("Invalid calendar source {name} in CALENDAR_SOURCES: {e}.");
+        }
+        let calendar_window_days = env_var_opt("CALENDAR_WINDOW_DAYS")
+            .map(|v| v.parse::<i64>().expect("Unable to parse the value of the CALENDAR_WINDOW_DAYS environment variable. Please make sure it is a valid integer."))
+            .unwrap_or(60);
 
         let ha_url = env_var("HA_URL");
         let ha_token = env_var("HA_TOKEN");
+        let ha_weather_entity =
+            env_var_opt("HA_WEATHER_ENTITY").unwrap_or_else(|| "weather.forecast_home".to_string());
+
+        let ha_websocket = env_var_opt("HA_WEBSOCKET")
+            .map(|v| v.parse::<bool>().expect("Unable to parse the value of the HA_WEBSOCKET environment variable. Please make sure it is either \"true\" or \"false\"."))
+            .unwrap_or(true);
+
+        let ha_sensors = env_var_opt("HA_SENSORS")
+            .map(|v| serde_json::from_str::<Vec<HaSensor>>(&v).expect("Unable to parse the value of the HA_SENSORS environment variable. Please make sure it is a JSON list of sensors."))
+            .unwrap_or_default();
+        if let Some(sensor) = ha_sensors
+            .iter()
+            .find(|s| s.icon.is_some() && s.icon_char().is_none())
+        {
+            panic!(
+                "Invalid icon of the {} sensor in HA_SENSORS. Please make sure it is a hex codepoint.",
+                sensor.entity_id
+            );
+        }
+
+        let ha_todo_entities = env_var_opt("HA_TODO_ENTITIES")
+            .map(|v| {
+                v.split(',')
+                    .map(|e| e.trim().to_string())
+                    .filter(|e| !e.is_empty())
+                    .collect_vec()
+            })
+            .unwrap_or_default();
+
+        let ha_retry = env_var_opt("HA_RETRY")
+            .map(|v| serde_json::from_str::<RetryPolicy>(&v).expect("Unable to parse the value of the HA_RETRY environment variable. Please make sure it is a JSON object of retry settings."))
+            .unwrap_or_default();
+        let invalid_retry = calendar_sources
+            .iter()
+            .filter_map(|s| Some((s.name.as_str(), s.retry.as_ref()?)))
+            .chain([("Home Assistant", &ha_retry)])
+            .find_map(|(name, r)| r.validate().err().map(|e| (name, e)));
+        if let Some((name, e)) = invalid_retry {
+            panic!("Invalid retry settings of the {name} source: {e}.");
+        }
+
+        let stale_after = env_var_opt("STALE_AFTER")
+            .map(|v| v.parse::<u64>().expect("Unable to parse the value of the STALE_AFTER environment variable. Please make sure it is a valid number of seconds."))
+            .unwrap_or(3600);
+
+        let layout_file = env_var_opt("LAYOUT_FILE").map(PathBuf::from);
+
+        let mut panel_profiles = panel::builtin_profiles();
+        panel_profiles.extend(
+            env_var_opt("PANEL_PROFILES")
+                .map(|v| serde_json::from_str::<BTreeMap<String, PanelProfile>>(&v).expect("Unable to parse the value of the PANEL_PROFILES environment variable. Please make sure it is a JSON object of panel profiles by name."))
+                .unwrap_or_default(),
+        );
+        if let Some((name, e)) = panel_profiles
+            .iter()
+            .find_map(|(name, p)| p.validate().err().map(|e| (name, e)))
+        {
+            panic!("Invalid panel profile {name}: {e}.");
+        }
+        let panel_profile =
+            env_var_opt("PANEL_PROFILE").unwrap_or_else(|| panel::DEFAULT_PANEL.to_string());
+        if !panel_profiles.contains_key(&panel_profile) {
+            panic!(
+                "Unknown panel profile {panel_profile} in PANEL_PROFILE. Please make sure it is a built-in profile or one of PANEL_PROFILES."
+            );
+        }
+        let panel_devices = env_var_opt("PANEL_DEVICES")
+            .map(|v| serde_json::from_str::<HashMap<String, String>>(&v).expect("Unable to parse the value of the PANEL_DEVICES environment variable. Please make sure it is a JSON object of panel profile names by device id."))
+            .unwrap_or_default();
+        if let Some((device, name)) = panel_devices
+            .iter()
+            .find(|(_, name)| !panel_profiles.contains_key(*name))
+        {
+            panic!("Unknown panel profile {name} of the device {device} in PANEL_DEVICES.");
+        }
+
+        let default_schedule = env_var_opt("DEFAULT_SCHEDULE")
+            .map(|v| v.parse::<Schedule>().unwrap_or_else(|e| panic!("Unable to parse the value of the DEFAULT_SCHEDULE environment variable: {e}. Please make sure it is a number of seconds, a cron expression with seconds or an English phrase.")))
+            .unwrap_or_else(|| Schedule::Cron("0 */5 * * * *".to_string()));
+        let schedules = env_var_opt("SCHEDULES")
+            .map(|v| serde_json::from_str::<HashMap<String, Schedule>>(&v).expect("Unable to parse the value of the SCHEDULES environment variable. Please make sure it is a JSON object of schedules by source name."))
+            .unwrap_or_default();
+        let source_names = calendar_sources
+            .iter()
+            .map(|s| s.name.as_str())
+            .chain([
+                cron::HA_STATES_SOURCE,
+                cron::FORECAST_SOURCE,
+                cron::TODO_SOURCE,
+            ])
+            .collect_vec();
+        if let Some(name) = schedules
+            .keys()
+            .find(|n| !source_names.contains(&n.as_str()))
+        {
+            panic!("Unknown source {name} in SCHEDULES.");
+        }
+        let invalid_schedule = calendar_sources
+            .iter()
+            .filter_map(|s| Some((s.name.as_str(), s.schedule.as_ref()?)))
+            .chain(schedules.iter().map(|(n, s)| (n.as_str(), s)))
+            .find_map(|(name, s)| s.validate().err().map(|e| (name, e)));
+        if let Some((name, e)) = invalid_schedule {
+            panic!(
+                "Invalid schedule of the {name} source: {e}. Please make sure it is a number of seconds, a cron expression with seconds or an English phrase."
+            );
+        }
 
         let access_token = env_var("ACCESS_TOKEN");
 
-        Arc::new(Configuration {
+        let cfg = Configuration {
             env,
             listen_address,
             app_port,
             db_dsn,
             db_pool_max_size,
             tz,
-            ical_holiday,
-            ical_event,
+            calendar_sources,
+            calendar_window_days,
             ha_url,
             ha_token,
+            ha_weather_entity,
+            ha_websocket,
+            ha_sensors,
+            ha_todo_entities,
+            ha_retry,
+            stale_after,
+            layout_file,
+            panel_profiles,
+            panel_profile,
+            panel_devices,
+            default_schedule,
+            schedules,
             access_token,
-        })
+        };
+        if let Err(e) = cfg.validate() {
+            panic!("{e}");
+        }
+
+        Arc::new(cfg)
+    }
+
+    /// Checks settings that parse but cannot be used.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.calendar_window_days < 0 {
+            return Err(format!(
+                "Invalid CALENDAR_WINDOW_DAYS {}. Please make sure it is 0 or more.",
+                self.calendar_window_days
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Schedule of the source `name`: its own, then `SCHEDULES`, then the default one.
+    pub fn schedule(&self, name: &str) -> &Schedule {
+        self.calendar_sources
+            .iter()
+            .find(|s| s.name == name)
+            .and_then(|s| s.schedule.as_ref())
+            .or_else(|| self.schedules.get(name))
+            .unwrap_or(&self.default_schedule)
+    }
+
+    /// Retries of the source `name`: its own for calendar sources, `ha_retry`
+    /// for the Home Assistant fetches.
+    pub fn retry(&self, name: &str) -> RetryPolicy {
+        match self.calendar_sources.iter().find(|s| s.name == name) {
+            Some(source) => source.retry.clone().unwrap_or_default(),
+            None => self.ha_retry.clone(),
+        }
+    }
+
+    /// Age after which the data of the source `name` is stale. Sources refreshed less often
+    /// than `stale_after` get three periods of their schedule.
+    pub fn stale_after(&self, name: &str) -> time::Duration {
+        let tz = timezones::get_by_name(&self.tz).unwrap_or(timezones::db::UTC);
+        let period = self.schedule(name).period(OffsetDateTime::now_utc(), tz);
+
+        time::Duration::max(time::Duration::seconds(self.stale_after as i64), period * 3)
+    }
+
+    /// Name and profile of the panel a request is drawn for: the one it names,
+    /// then the one of its device, then `panel_profile`. `None` for an
+    /// unknown `panel`.
+    pub fn panel(
+        &self,
+        panel: Option<&str>,
+        device: Option<&str>,
+    ) -> Option<(&str, &PanelProfile)> {
+        let name = match panel {
+            Some(name) => name,
+            None => device
+                .and_then(|d| self.panel_devices.get(d))
+                .unwrap_or(&self.panel_profile),
+        };
+
+        self.panel_profiles
+            .get_key_value(name)
+            .map(|(name, profile)| (name.as_str(), profile))
     }
 
     /// Sets the database DSN.
@@ -112,3 +636,7 @@ pub fn env_var(name: &str) -> String {
         .map_err(|e| format!("{}: {}", name, e))
         .expect("Missing environment variable")
 }
+
+pub fn env_var_opt(name: &str) -> Option<String> {
+    std::env::var(name).ok().filter(|v| !v.is_empty())
+}
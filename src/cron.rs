@@ -1,289 +1,1127 @@
 use crate::{
-    Config,
+    CalendarProvider, CalendarSource, CalendarSourceKind, Config, Db, Schedule,
     api_error::ApiError,
+    caldav, http, ics,
     model::{
-        CalendarMapArc, DateInfo, DateInfoEventMode, LastUpdateArc, WeatherInfo, WeatherInfoArc,
+        CalendarMap, DateInfo, DateInfoEventMode, DateInfoHoliday, FeedCache, FeedCacheArc,
+        Generation, HaCalendarEvent, HaCalendarTime, HaEntityState, HaForecastResponse,
+        HaServiceResponse, HaTodoResponse, LastUpdateArc, RefreshResult, ScheduledJob,
+        SensorMapArc, SharedState, SourceStatusArc, TodoMapArc, WeatherForecast,
+        WeatherForecastArc, WeatherForecastEntry, WeatherInfo, WeatherInfoArc,
     },
+    retry::{self, Outcome, RetryPolicy},
 };
-use ical::parser::Component;
+use futures_util::future::{BoxFuture, FutureExt, join_all};
+use ical::parser::{Component, ical::component::IcalEvent};
 use itertools::Itertools;
-use std::io::Cursor;
-use time::{Date, OffsetDateTime, PrimitiveDateTime, format_description::well_known::Iso8601};
-use time_tz::{OffsetResult, PrimitiveDateTimeExt, timezones};
+use serde_json::json;
+use std::{
+    collections::{BTreeMap, HashMap},
+    future::Future,
+    io::Cursor,
+    pin::Pin,
+    time::Instant,
+};
+use time::{
+    Date, Duration, OffsetDateTime, PrimitiveDateTime, format_description::well_known::Rfc3339,
+    macros::format_description,
+};
+use time_tz::{OffsetDateTimeExt, PrimitiveDateTimeExt, Tz, timezones};
 use tokio::task::JoinSet;
 use tokio_cron_scheduler::{Job, JobScheduler, JobSchedulerError};
+use uuid::Uuid;
 
-async fn fetch_holiday(
-    cfg: Config,
-    calendar: CalendarMapArc,
-    last_update: LastUpdateArc,
-) -> Result<(), ApiError> {
-    let res = reqwest::Client::new()
-        .get(cfg.ical_holiday.clone())
+/// Status names of the fetches that are not calendar sources.
+pub const HA_STATES_SOURCE: &str = "home-assistant";
+pub const FORECAST_SOURCE: &str = "forecast";
+pub const TODO_SOURCE: &str = "todo";
+
+/// Downloads a source and returns its VEVENTs. Unchanged feeds are not
+/// downloaded nor parsed again.
+async fn fetch_ical(
+    source: &CalendarSource,
+    feeds: &FeedCacheArc,
+) -> Result<Vec<IcalEvent>, ApiError> {
+    let cached = feeds.read().await.get(&source.name).cloned();
+    let validators = cached
+        .as_ref()
+        .map(|c| c.validators.clone())
+        .unwrap_or_default();
+    let req = source.authorize(http::client().get(source.url.clone()));
+
+    match (http::send_conditional(req, &validators).await?, cached) {
+        (http::Conditional::NotModified, Some(cached)) => {
+            tracing::info!("Source {}: not modified", source.name);
+            Ok(cached.events)
+        }
+        (http::Conditional::NotModified, None) => Err(ApiError::InternalError(anyhow::anyhow!(
+            "Source {}: not modified, but nothing is cached",
+            source.name
+        ))),
+        (http::Conditional::Modified { body, validators }, _) => {
+            tracing::info!("Source {}: {} bytes", source.name, body.len());
+            let events = ics::parse_events(Cursor::new(body));
+
+            feeds.write().await.insert(
+                source.name.clone(),
+                FeedCache {
+                    validators,
+                    events: events.clone(),
+                },
+            );
+
+            Ok(events)
+        }
+    }
+}
+
+/// Queries a CalDAV collection for the VEVENTs overlapping the event window.
+async fn fetch_caldav(cfg: &Config, source: &CalendarSource) -> Result<Vec<IcalEvent>, ApiError> {
+    let tz = timezones::get_by_name(&cfg.tz).unwrap_or(timezones::db::UTC);
+    let (window_start, window_end) = event_window(cfg, tz);
+
+    caldav::fetch_events(source, window_start, window_end).await
+}
+
+/// Queries a Home Assistant calendar entity over the event window.
+async fn fetch_ha_calendar(
+    cfg: &Config,
+    source: &CalendarSource,
+) -> Result<Vec<ics::EventOccurrence>, ApiError> {
+    let tz = timezones::get_by_name(&cfg.tz).unwrap_or(timezones::db::UTC);
+    let (window_start, window_end) = event_window(cfg, tz);
+    let to_rfc3339 = |dt: OffsetDateTime| dt.format(&Rfc3339).unwrap_or_default();
+
+    let res = http::client()
+        .get(format! {
+            "{}/api/calendars/{}",
+            cfg.ha_url,
+            source.entity_id.as_deref().unwrap_or_default()
+        })
+        .query(&[
+            ("start", to_rfc3339(window_start)),
+            ("end", to_rfc3339(window_end)),
+        ])
+        .bearer_auth(cfg.ha_token.clone())
         .send()
         .await
+        .and_then(|r| r.error_for_status())
         .map_err(|e| ApiError::InternalError(e.into()))?
         .bytes()
         .await
         .map_err(|e| ApiError::InternalError(e.into()))?;
+    tracing::info!("Source {}: {} bytes", source.name, res.len());
+    let res = serde_json::from_slice::<Vec<HaCalendarEvent>>(&res)
+        .map_err(|e| ApiError::InternalError(e.into()))?;
 
-    let holiday_icals = ical::IcalParser::new(Cursor::new(res))
-        .flat_map(|cr| {
-            let Ok(c) = cr else {
-                return None;
-            };
+    Ok(occurrences_from_ha(res, tz))
+}
 
-            Some(c.events)
-        })
-        .collect_vec()
+/// Maps Home Assistant calendar events, whose recurrences are already expanded,
+/// into occurrences. All-day dates start at midnight in `tz`.
+pub fn occurrences_from_ha(events: Vec<HaCalendarEvent>, tz: &Tz) -> Vec<ics::EventOccurrence> {
+    let date_format = format_description!("[year]-[month]-[day]");
+    let resolve = |t: &HaCalendarTime| match (t.date_time, t.date.as_deref()) {
+        (Some(dt), _) => Some(dt),
+        (None, Some(d)) => Date::parse(d, date_format)
+            .ok()?
+            .midnight()
+            .assume_timezone(tz)
+            .take_first(),
+        (None, None) => None,
+    };
+
+    events
         .into_iter()
-        .flatten()
-        .collect_vec();
+        .filter_map(|evnt| {
+            let start = resolve(&evnt.start)?;
+            let end = resolve(&evnt.end).unwrap_or(start);
+            let uid = evnt.uid.unwrap_or_else(|| evnt.summary.clone());
 
-    let mut calendar = calendar.write().await;
-    let mut is_update = false;
+            Some(ics::EventOccurrence {
+                id: ics::occurrence_id(&uid, start),
+                uid,
+                start,
+                end,
+                all_day: evnt.start.date_time.is_none(),
+                summary: evnt.summary,
+            })
+        })
+        .collect()
+}
+
+fn holidays_from_ical(
+    source: &CalendarSource,
+    holiday_icals: Vec<IcalEvent>,
+) -> BTreeMap<Date, DateInfoHoliday> {
+    let mut holidays = BTreeMap::new();
 
     holiday_icals.into_iter().for_each(|evnt| {
         let (Some(dtstart), Some(summary)) = (
             evnt.get_property("DTSTART")
-                .and_then(|p| p.value.to_owned()),
+                .and_then(ics::IcalDateTime::parse_property),
             evnt.get_property("SUMMARY")
                 .and_then(|p| p.value.to_owned()),
         ) else {
             return;
         };
 
-        let Ok(date) = Date::parse(&dtstart, &Iso8601::DATE) else {
-            return;
-        };
+        holidays.insert(
+            dtstart.date(),
+            DateInfoHoliday {
+                name: summary,
+                source: source.name.clone(),
+                color: source.color(),
+            },
+        );
+    });
 
-        let c_nty = calendar.entry(date).or_insert_with(|| DateInfo {
-            date,
-            holiday: Default::default(),
-            events: Default::default(),
-        });
+    holidays
+}
 
-        if c_nty
-            .holiday
-            .as_ref()
-            .map(|smr| *smr != summary)
-            .unwrap_or(true)
-        {
+/// Replaces the holidays `source` contributed to `calendar` with `holidays`,
+/// the latest state of the feed. Returns whether anything changed.
+pub fn reconcile_holidays(
+    calendar: &mut CalendarMap,
+    source: &str,
+    mut holidays: BTreeMap<Date, DateInfoHoliday>,
+) -> bool {
+    let mut is_update = false;
+
+    calendar.values_mut().for_each(|c_nty| {
+        let holiday = holidays.remove(&c_nty.date);
+        let current = c_nty.holidays.iter().find(|h| h.source == source);
+
+        if current != holiday.as_ref() {
             is_update = true;
+            c_nty.holidays.retain(|h| h.source != source);
+            c_nty.holidays.extend(holiday);
         }
+    });
 
-        c_nty.holiday.replace(summary);
+    holidays.into_iter().for_each(|(date, holiday)| {
+        is_update = true;
+        calendar.insert(
+            date,
+            DateInfo {
+                date,
+                holidays: vec![holiday],
+                events: Default::default(),
+            },
+        );
     });
 
-    if is_update {
-        let now_odt = OffsetDateTime::now_utc();
-        *(last_update.write().await) = PrimitiveDateTime::new(now_odt.date(), now_odt.time());
-    }
+    calendar.retain(|_, c_nty| !c_nty.holidays.is_empty() || !c_nty.events.is_empty());
 
-    Ok(())
+    is_update
 }
 
-async fn fetch_event(
-    cfg: Config,
-    calendar: CalendarMapArc,
-    last_update: LastUpdateArc,
-) -> Result<(), ApiError> {
-    let res = reqwest::Client::new()
-        .get(cfg.ical_event.clone())
-        .send()
-        .await
-        .map_err(|e| ApiError::InternalError(e.into()))?
-        .bytes()
-        .await
-        .map_err(|e| ApiError::InternalError(e.into()))?;
-
-    let event_icals = ical::IcalParser::new(Cursor::new(res))
-        .flat_map(|cr| {
-            let Ok(c) = cr else {
-                return None;
-            };
+fn holidays_from_occurrences(
+    cfg: &Config,
+    source: &CalendarSource,
+    occurrences: Vec<ics::EventOccurrence>,
+) -> BTreeMap<Date, DateInfoHoliday> {
+    let tz = timezones::get_by_name(&cfg.tz).unwrap_or(timezones::db::UTC);
 
-            Some(c.events)
-        })
-        .collect_vec()
+    occurrences
         .into_iter()
-        .flatten()
-        .collect_vec();
+        .map(|occ| {
+            (
+                occ.start.to_timezone(tz).date(),
+                DateInfoHoliday {
+                    name: occ.summary,
+                    source: source.name.clone(),
+                    color: source.color(),
+                },
+            )
+        })
+        .collect()
+}
 
-    let mut calendar = calendar.write().await;
-    let mut is_update = false;
+fn events_from_ical(
+    cfg: &Config,
+    source: &CalendarSource,
+    event_icals: Vec<IcalEvent>,
+) -> BTreeMap<Date, HashMap<String, DateInfoEventMode>> {
+    let tz = timezones::get_by_name(&cfg.tz).unwrap_or(timezones::db::UTC);
+    let (window_start, window_end) = event_window(cfg, tz);
 
-    event_icals.into_iter().for_each(|evnt| {
-        let (Some(dtstart), Some(summary), Some(uid)) = (
-            evnt.get_property("DTSTART"),
-            evnt.get_property("SUMMARY")
-                .and_then(|p| p.value.to_owned()),
-            evnt.get_property("UID").and_then(|p| p.value.to_owned()),
-        ) else {
-            return;
-        };
+    events_from_occurrences(
+        cfg,
+        source,
+        ics::expand_events(event_icals, tz, window_start, window_end),
+    )
+}
 
-        let Some(destart_val) = dtstart.value.as_ref() else {
-            return;
-        };
+fn events_from_occurrences(
+    cfg: &Config,
+    source: &CalendarSource,
+    occurrences: Vec<ics::EventOccurrence>,
+) -> BTreeMap<Date, HashMap<String, DateInfoEventMode>> {
+    let tz = timezones::get_by_name(&cfg.tz).unwrap_or(timezones::db::UTC);
+    let mut events = BTreeMap::<Date, HashMap<String, DateInfoEventMode>>::new();
 
-        let dstart_tzid = dtstart
-            .params
-            .as_ref()
-            .and_then(|prm| {
-                prm.iter().find_map(|(p_name, p_vals)| {
-                    if p_name != "TZID" {
-                        return None;
-                    }
-
-                    p_vals.first()
-                })
-            })
-            .unwrap_or(&cfg.tz);
-        let tz = timezones::get_by_name(dstart_tzid).unwrap_or(timezones::db::UTC);
-        let Ok(dtstart_pdt) = PrimitiveDateTime::parse(destart_val, &Iso8601::DATE) else {
-            return;
+    occurrences.into_iter().for_each(|occ| {
+        let event = DateInfoEventMode {
+            time: occ.start.to_timezone(tz),
+            end: occ.end.to_timezone(tz),
+            all_day: occ.all_day,
+            name: occ.summary,
+            source: source.name.clone(),
+            color: source.color(),
+            day: 1,
+            days: 1,
         };
-        let dtstart_odt = match dtstart_pdt.assume_timezone(tz) {
-            OffsetResult::Some(t) => t,
-            OffsetResult::Ambiguous(t, _) => t,
-            OffsetResult::None => {
-                return;
-            }
-        };
-        let dstart_date = dtstart_odt.date();
+        let dates = event.span_dates(tz);
+        let days = dates.len() as u32;
 
-        let c_nty = calendar.entry(dstart_date).or_insert_with(|| DateInfo {
-            date: dstart_date,
-            holiday: Default::default(),
-            events: Default::default(),
-        });
+        // Multi-day events are attached to every date they cover
+        for (idx, c_date) in dates.into_iter().enumerate() {
+            events.entry(c_date).or_default().insert(
+                format! {"{}/{}", source.name, occ.id},
+                DateInfoEventMode {
+                    day: idx as u32 + 1,
+                    days,
+                    ..event.clone()
+                },
+            );
+        }
+    });
 
-        if !c_nty.events.contains_key(&uid) {
+    events
+}
+
+/// Replaces the events `source` contributed to `calendar` with `events`, the
+/// latest state of the feed, so removed events disappear and moved ones
+/// change date. Returns whether anything changed.
+pub fn reconcile_events(
+    calendar: &mut CalendarMap,
+    source: &str,
+    mut events: BTreeMap<Date, HashMap<String, DateInfoEventMode>>,
+) -> bool {
+    let mut is_update = false;
+
+    calendar.values_mut().for_each(|c_nty| {
+        let mut events = events.remove(&c_nty.date).unwrap_or_default();
+        let current = c_nty
+            .events
+            .iter()
+            .filter(|(_, e)| e.source == source)
+            .map(|(id, e)| (id.clone(), e.clone()))
+            .collect::<HashMap<_, _>>();
+
+        if current != events {
             is_update = true;
+            c_nty.events.retain(|_, e| e.source != source);
+            c_nty.events.extend(events.drain());
         }
+    });
 
-        c_nty.events.insert(
-            uid,
-            DateInfoEventMode {
-                time: dtstart_odt,
-                name: summary,
+    events.into_iter().for_each(|(date, events)| {
+        is_update = true;
+        calendar.insert(
+            date,
+            DateInfo {
+                date,
+                holidays: Default::default(),
+                events,
             },
         );
     });
 
-    if is_update {
-        let now_odt = OffsetDateTime::now_utc();
-        *(last_update.write().await) = PrimitiveDateTime::new(now_odt.date(), now_odt.time());
-    }
+    calendar.retain(|_, c_nty| !c_nty.holidays.is_empty() || !c_nty.events.is_empty());
+
+    is_update
+}
+
+/// First date kept in the calendar: yesterday, so per-request zone overrides
+/// still see "today".
+fn calendar_start(cfg: &Config) -> Date {
+    let tz = timezones::get_by_name(&cfg.tz).unwrap_or(timezones::db::UTC);
+    let today = OffsetDateTime::now_utc().to_timezone(tz).date();
+
+    today.previous_day().unwrap_or(today)
+}
+
+/// Range of time recurring events are expanded over: from the start of
+/// [`calendar_start`] for `calendar_window_days` days.
+fn event_window(cfg: &Config, tz: &Tz) -> (OffsetDateTime, OffsetDateTime) {
+    let start = calendar_start(cfg)
+        .midnight()
+        .assume_timezone(tz)
+        .take_first()
+        .unwrap_or_else(OffsetDateTime::now_utc);
+
+    (start, start + Duration::days(cfg.calendar_window_days + 1))
+}
+
+/// Bumps `last_update` to now, in memory and in the database.
+async fn mark_updated(db: &Db, last_update: &LastUpdateArc) -> Result<(), ApiError> {
+    let now_odt = OffsetDateTime::now_utc();
+    let now_pdt = PrimitiveDateTime::new(now_odt.date(), now_odt.time());
+
+    *(last_update.write().await) = now_pdt;
+    db.save_last_update(now_pdt).await?;
 
     Ok(())
 }
 
-async fn fetch_weather(
+/// Fetches a single calendar source and reconciles its entries.
+async fn fetch_calendar(
     cfg: Config,
+    db: Db,
+    source: CalendarSource,
+    shared: SharedState,
+) -> Result<(), ApiError> {
+    let from = calendar_start(&cfg);
+    let started = Instant::now();
+    let SharedState {
+        calendar,
+        feeds,
+        last_update,
+        generation,
+        ..
+    } = shared;
+
+    match source.kind {
+        CalendarSourceKind::Holiday => {
+            let holidays = match source.provider {
+                CalendarProvider::Ics => {
+                    holidays_from_ical(&source, fetch_ical(&source, &feeds).await?)
+                }
+                CalendarProvider::Caldav => {
+                    holidays_from_ical(&source, fetch_caldav(&cfg, &source).await?)
+                }
+                CalendarProvider::HomeAssistant => holidays_from_occurrences(
+                    &cfg,
+                    &source,
+                    fetch_ha_calendar(&cfg, &source).await?,
+                ),
+            }
+            .split_off(&from);
+            // Saved before it is shown, so a failed save is tried again by the next fetch
+            let mut calendar = calendar.write().await;
+            let mut reconciled = calendar.clone();
+
+            if reconcile_holidays(&mut reconciled, &source.name, holidays.clone()) {
+                db.save_holidays(&source.name, &holidays).await?;
+                *calendar = reconciled;
+                drop(calendar);
+                generation.bump();
+                mark_updated(&db, &last_update).await?;
+            }
+        }
+        CalendarSourceKind::Event => {
+            let events = match source.provider {
+                CalendarProvider::Ics => {
+                    events_from_ical(&cfg, &source, fetch_ical(&source, &feeds).await?)
+                }
+                CalendarProvider::Caldav => {
+                    events_from_ical(&cfg, &source, fetch_caldav(&cfg, &source).await?)
+                }
+                CalendarProvider::HomeAssistant => {
+                    events_from_occurrences(&cfg, &source, fetch_ha_calendar(&cfg, &source).await?)
+                }
+            }
+            .split_off(&from);
+            let mut calendar = calendar.write().await;
+            let mut reconciled = calendar.clone();
+
+            if reconcile_events(&mut reconciled, &source.name, events.clone()) {
+                db.save_events(&source.name, &events).await?;
+                *calendar = reconciled;
+                drop(calendar);
+                generation.bump();
+                mark_updated(&db, &last_update).await?;
+            }
+        }
+    };
+    tracing::info!("Source {}: done in {:?}", source.name, started.elapsed());
+
+    Ok(())
+}
+
+pub(crate) async fn fetch_weather(
+    cfg: Config,
+    db: Db,
     weather: WeatherInfoArc,
     last_update: LastUpdateArc,
+    generation: Generation,
 ) -> Result<(), ApiError> {
-    let res = reqwest::Client::new()
-        .get(format! {"{}/api/states/weather.forecast_home", cfg.ha_url})
+    let res = http::client()
+        .get(format! {"{}/api/states/{}", cfg.ha_url, cfg.ha_weather_entity})
         .bearer_auth(cfg.ha_token.clone())
         .send()
         .await
+        .and_then(|r| r.error_for_status())
         .map_err(|e| ApiError::InternalError(e.into()))?
         .json::<WeatherInfo>()
         .await
         .map_err(|e| ApiError::InternalError(e.into()))?;
 
-    let is_update = weather
+    update_weather(&db, &weather, &last_update, &generation, res).await
+}
+
+/// Stores the latest weather. Any change is drawn, but it is only persisted
+/// and counts as an update when the state or the temperature changed.
+pub(crate) async fn update_weather(
+    db: &Db,
+    weather: &WeatherInfoArc,
+    last_update: &LastUpdateArc,
+    generation: &Generation,
+    res: WeatherInfo,
+) -> Result<(), ApiError> {
+    let (is_change, is_update) = weather
         .read()
         .await
         .as_ref()
         .map(|wth| {
-            wth.state != res.state || wth.attributes.temperature != res.attributes.temperature
+            (
+                *wth != res,
+                wth.state != res.state || wth.attributes.temperature != res.attributes.temperature,
+            )
         })
-        .unwrap_or(true);
+        .unwrap_or((true, true));
 
-    weather.write().await.replace(res);
+    weather.write().await.replace(res.clone());
 
+    if is_change {
+        generation.bump();
+    }
     if is_update {
-        let now_odt = OffsetDateTime::now_utc();
-        *(last_update.write().await) = PrimitiveDateTime::new(now_odt.date(), now_odt.time());
+        mark_updated(db, last_update).await?;
+        db.save_weather(&res).await?;
     }
 
     Ok(())
 }
 
-async fn fetch(
+/// Calls `weather.get_forecasts` for one forecast type.
+async fn fetch_forecast_type(
+    cfg: &Config,
+    forecast_type: &str,
+) -> Result<Vec<WeatherForecastEntry>, ApiError> {
+    let mut res = http::client()
+        .post(format! {"{}/api/services/weather/get_forecasts?return_response", cfg.ha_url})
+        .bearer_auth(cfg.ha_token.clone())
+        .json(&json!({ "entity_id": cfg.ha_weather_entity, "type": forecast_type }))
+        .send()
+        .await
+        .and_then(|r| r.error_for_status())
+        .map_err(|e| ApiError::InternalError(e.into()))?
+        .json::<HaServiceResponse<HaForecastResponse>>()
+        .await
+        .map_err(|e| ApiError::InternalError(e.into()))?;
+
+    Ok(res
+        .service_response
+        .remove(&cfg.ha_weather_entity)
+        .map(|r| r.forecast)
+        .unwrap_or_default())
+}
+
+async fn fetch_forecast(
     cfg: Config,
-    calendar: CalendarMapArc,
-    weather: WeatherInfoArc,
+    db: Db,
+    forecast: WeatherForecastArc,
+    last_update: LastUpdateArc,
+    generation: Generation,
+) -> Result<(), ApiError> {
+    // Not every integration provides hourly forecasts
+    let (daily, hourly) = tokio::join!(
+        fetch_forecast_type(&cfg, "daily"),
+        fetch_forecast_type(&cfg, "hourly")
+    );
+    let res = WeatherForecast {
+        daily: daily?,
+        hourly: hourly.unwrap_or_else(|e| {
+            tracing::debug!("No hourly forecast: {:?}", e);
+            Vec::new()
+        }),
+    };
+
+    let is_update = *forecast.read().await != res;
+
+    if is_update {
+        forecast.write().await.clone_from(&res);
+        generation.bump();
+        mark_updated(&db, &last_update).await?;
+        db.save_forecast(&res).await?;
+    }
+
+    Ok(())
+}
+
+/// Stores the latest state of a sensor. Any change is drawn, as formats can
+/// show attributes, but only a changed state counts as an update.
+pub(crate) async fn update_sensor(
+    db: &Db,
+    sensors: &SensorMapArc,
+    last_update: &LastUpdateArc,
+    generation: &Generation,
+    entity_id: &str,
+    res: HaEntityState,
+) -> Result<(), ApiError> {
+    let (is_change, is_update) = sensors
+        .read()
+        .await
+        .get(entity_id)
+        .map(|s| (*s != res, s.state != res.state))
+        .unwrap_or((true, true));
+
+    sensors.write().await.insert(entity_id.to_string(), res);
+
+    if is_change {
+        generation.bump();
+    }
+    if is_update {
+        mark_updated(db, last_update).await?;
+    }
+
+    Ok(())
+}
+
+async fn fetch_sensor(
+    cfg: &Config,
+    db: &Db,
+    sensors: &SensorMapArc,
+    last_update: &LastUpdateArc,
+    generation: &Generation,
+    entity_id: &str,
+) -> Result<(), ApiError> {
+    let res = http::client()
+        .get(format! {"{}/api/states/{}", cfg.ha_url, entity_id})
+        .bearer_auth(cfg.ha_token.clone())
+        .send()
+        .await
+        .and_then(|r| r.error_for_status())
+        .map_err(|e| ApiError::InternalError(e.into()))?
+        .json::<HaEntityState>()
+        .await
+        .map_err(|e| ApiError::InternalError(e.into()))?;
+
+    update_sensor(db, sensors, last_update, generation, entity_id, res).await
+}
+
+/// Polls every configured sensor. One failing sensor does not stop the others.
+pub(crate) async fn fetch_sensors(
+    cfg: Config,
+    db: Db,
+    sensors: SensorMapArc,
+    last_update: LastUpdateArc,
+    generation: Generation,
+) -> Result<(), ApiError> {
+    let results = join_all(
+        cfg.ha_sensors
+            .iter()
+            .map(|s| fetch_sensor(&cfg, &db, &sensors, &last_update, &generation, &s.entity_id)),
+    )
+    .await;
+
+    results.into_iter().collect()
+}
+
+/// Reads the open items of every configured to-do list with `todo.get_items`.
+pub(crate) async fn fetch_todos(
+    cfg: Config,
+    db: Db,
+    todos: TodoMapArc,
     last_update: LastUpdateArc,
+    generation: Generation,
 ) -> Result<(), ApiError> {
+    if cfg.ha_todo_entities.is_empty() {
+        return Ok(());
+    }
+
+    let res = http::client()
+        .post(format! {"{}/api/services/todo/get_items?return_response", cfg.ha_url})
+        .bearer_auth(cfg.ha_token.clone())
+        .json(&json!({ "entity_id": cfg.ha_todo_entities, "status": ["needs_action"] }))
+        .send()
+        .await
+        .and_then(|r| r.error_for_status())
+        .map_err(|e| ApiError::InternalError(e.into()))?
+        .json::<HaServiceResponse<HaTodoResponse>>()
+        .await
+        .map_err(|e| ApiError::InternalError(e.into()))?;
+    let res = res
+        .service_response
+        .into_iter()
+        .map(|(entity_id, r)| (entity_id, r.items))
+        .collect::<HashMap<_, _>>();
+
+    let is_update = *todos.read().await != res;
+
+    if is_update {
+        *todos.write().await = res;
+        generation.bump();
+        mark_updated(&db, &last_update).await?;
+    }
+
+    Ok(())
+}
+
+/// Polls the Home Assistant entities the WebSocket client follows.
+pub(crate) async fn fetch_ha_states(
+    cfg: Config,
+    db: Db,
+    shared: SharedState,
+) -> Result<(), ApiError> {
+    let (weather, sensors) = tokio::join!(
+        fetch_weather(
+            cfg.clone(),
+            db.clone(),
+            shared.weather.clone(),
+            shared.last_update.clone(),
+            shared.generation.clone(),
+        ),
+        fetch_sensors(
+            cfg,
+            db,
+            shared.sensors,
+            shared.last_update,
+            shared.generation
+        )
+    );
+
+    weather.and(sensors)
+}
+
+/// Spawns `f` on `set` as the source `name`, with retries and a circuit breaker
+/// per `policy`.
+fn spawn_guarded<F>(
+    set: &mut JoinSet<(String, Result<Outcome, ApiError>)>,
+    name: &str,
+    status: &SourceStatusArc,
+    policy: RetryPolicy,
+    f: F,
+) where
+    F: Fn() -> BoxFuture<'static, Result<(), ApiError>> + Send + 'static,
+{
+    let name = name.to_string();
+    let status = status.clone();
+
+    set.spawn(async move {
+        let res = retry::run(&name, &status, &policy, f).await;
+        (name, res)
+    });
+}
+
+/// Home Assistant fetches of the default schedule.
+fn ha_sources(cfg: &Config) -> Vec<&'static str> {
+    // The WebSocket client keeps the current states up to date on its own
+    let states = (!cfg.ha_websocket).then_some(HA_STATES_SOURCE);
+
+    states
+        .into_iter()
+        .chain([FORECAST_SOURCE, TODO_SOURCE])
+        .collect()
+}
+
+/// Fetches the given calendar sources and Home Assistant fetches (`ha` names of
+/// [`HA_STATES_SOURCE`], [`FORECAST_SOURCE`] and [`TODO_SOURCE`]).
+///
+/// Every source is fetched to the end even if others fail, the first error is returned.
+async fn fetch(
+    cfg: Config,
+    db: Db,
+    sources: Vec<CalendarSource>,
+    ha: &[&str],
+    shared: SharedState,
+) -> Result<(), ApiError> {
+    // Drop dates that can no longer be rendered
+    {
+        let from = calendar_start(&cfg);
+        let mut calendar = shared.calendar.write().await;
+        *calendar = calendar.split_off(&from);
+        // Only leaves old rows behind, which the next run prunes
+        if let Err(e) = db.prune_calendar(from).await {
+            tracing::error!("Unable to prune the calendar before {}: {:?}", from, e);
+        }
+    }
+
     let mut set = JoinSet::new();
+    let status = shared.status.clone();
 
-    set.spawn(fetch_holiday(
-        cfg.clone(),
-        calendar.clone(),
-        last_update.clone(),
-    ));
-    set.spawn(fetch_event(
-        cfg.clone(),
-        calendar.clone(),
-        last_update.clone(),
-    ));
-    set.spawn(fetch_weather(
-        cfg.clone(),
-        weather.clone(),
-        last_update.clone(),
-    ));
+    sources.into_iter().for_each(|source| {
+        let (cfg, db, shared) = (cfg.clone(), db.clone(), shared.clone());
+        let name = source.name.clone();
+        spawn_guarded(&mut set, &name, &status, cfg.retry(&name), move || {
+            fetch_calendar(cfg.clone(), db.clone(), source.clone(), shared.clone()).boxed()
+        });
+    });
+    if ha.contains(&HA_STATES_SOURCE) {
+        let (cfg, db, shared) = (cfg.clone(), db.clone(), shared.clone());
+        spawn_guarded(
+            &mut set,
+            HA_STATES_SOURCE,
+            &status,
+            cfg.retry(HA_STATES_SOURCE),
+            move || fetch_ha_states(cfg.clone(), db.clone(), shared.clone()).boxed(),
+        );
+    }
+    if ha.contains(&FORECAST_SOURCE) {
+        let (cfg, db, shared) = (cfg.clone(), db.clone(), shared.clone());
+        spawn_guarded(
+            &mut set,
+            FORECAST_SOURCE,
+            &status,
+            cfg.retry(FORECAST_SOURCE),
+            move || {
+                fetch_forecast(
+                    cfg.clone(),
+                    db.clone(),
+                    shared.forecast.clone(),
+                    shared.last_update.clone(),
+                    shared.generation.clone(),
+                )
+                .boxed()
+            },
+        );
+    }
+    if ha.contains(&TODO_SOURCE) {
+        let (cfg, db, shared) = (cfg.clone(), db.clone(), shared.clone());
+        spawn_guarded(
+            &mut set,
+            TODO_SOURCE,
+            &status,
+            cfg.retry(TODO_SOURCE),
+            move || {
+                fetch_todos(
+                    cfg.clone(),
+                    db.clone(),
+                    shared.todos.clone(),
+                    shared.last_update.clone(),
+                    shared.generation.clone(),
+                )
+                .boxed()
+            },
+        );
+    }
 
+    let mut first_err = None;
     while let Some(res) = set.join_next().await {
-        if let Ok(Err(e)) = res {
-            return Err(e);
+        match res {
+            Ok((_, Ok(Outcome::Fetched))) => {}
+            Ok((name, Ok(Outcome::Skipped { open_until }))) => {
+                tracing::warn!(
+                    "Source {}: skipped, circuit open until {}",
+                    name,
+                    open_until
+                )
+            }
+            Ok((name, Err(e))) => {
+                tracing::error!("Source {}: {:?}", name, e);
+                first_err.get_or_insert(e);
+            }
+            Err(e) => tracing::error!("Fetch task failed: {:?}", e),
+        }
+    }
+
+    first_err.map_or(Ok(()), Err)
+}
+
+/// Minimum time between two on-demand refreshes of a source.
+pub const REFRESH_DEBOUNCE: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// Names of every source, calendars first.
+fn source_names(cfg: &Config) -> Vec<&str> {
+    cfg.calendar_sources
+        .iter()
+        .map(|s| s.name.as_str())
+        .chain([HA_STATES_SOURCE, FORECAST_SOURCE, TODO_SOURCE])
+        .collect()
+}
+
+/// Sources that read the Home Assistant entity `entity_id`.
+pub fn entity_sources(cfg: &Config, entity_id: &str) -> Vec<String> {
+    let calendars = cfg
+        .calendar_sources
+        .iter()
+        .filter(|s| s.entity_id.as_deref() == Some(entity_id))
+        .map(|s| s.name.as_str());
+    let weather = (cfg.ha_weather_entity == entity_id)
+        .then_some([HA_STATES_SOURCE, FORECAST_SOURCE])
+        .into_iter()
+        .flatten();
+    let sensors = cfg
+        .ha_sensors
+        .iter()
+        .any(|s| s.entity_id == entity_id)
+        .then_some(HA_STATES_SOURCE);
+    let todos = cfg
+        .ha_todo_entities
+        .iter()
+        .any(|e| e == entity_id)
+        .then_some(TODO_SOURCE);
+
+    calendars
+        .chain(weather)
+        .chain(sensors)
+        .chain(todos)
+        .unique()
+        .map(str::to_string)
+        .collect()
+}
+
+/// Starts fetching the named sources in the background, or every source when
+/// `names` is empty. Their outcome is recorded in the status of each source.
+///
+/// Sources refreshed less than [`REFRESH_DEBOUNCE`] ago are skipped, so a burst
+/// of calls fetches each source once.
+pub async fn refresh(
+    cfg: Config,
+    db: Db,
+    shared: SharedState,
+    names: &[String],
+) -> Result<RefreshResult, ApiError> {
+    let all = source_names(&cfg);
+    if let Some(unknown) = names.iter().find(|n| !all.contains(&n.as_str())) {
+        return Err(ApiError::InvalidRequest(format!(
+            "Unknown source: {unknown}"
+        )));
+    }
+
+    let mut refreshed = shared.refreshed.lock().await;
+    let (wanted, debounced): (Vec<_>, Vec<_>) = all
+        .into_iter()
+        .filter(|n| names.is_empty() || names.iter().any(|m| m == n))
+        .partition(|n| {
+            refreshed
+                .get(*n)
+                .is_none_or(|t| t.elapsed() >= REFRESH_DEBOUNCE)
+        });
+    // A refresh does not close a circuit, the source would only be skipped
+    let (wanted, open): (Vec<_>, Vec<_>) = {
+        let now = OffsetDateTime::now_utc();
+        let status = shared.status.read().await;
+        wanted
+            .into_iter()
+            .partition(|n| status.get(*n).is_none_or(|s| !s.is_open(now)))
+    };
+    let res = RefreshResult {
+        refreshed: wanted.iter().map(|n| n.to_string()).collect(),
+        debounced: debounced.iter().map(|n| n.to_string()).collect(),
+        open: open.iter().map(|n| n.to_string()).collect(),
+    };
+    if wanted.is_empty() {
+        return Ok(res);
+    }
+    // Before the fetch starts, so calls made meanwhile are debounced
+    refreshed.extend(wanted.iter().map(|n| (n.to_string(), Instant::now())));
+    drop(refreshed);
+
+    let sources = cfg
+        .calendar_sources
+        .iter()
+        .filter(|s| wanted.contains(&s.name.as_str()))
+        .cloned()
+        .collect();
+    let ha = [HA_STATES_SOURCE, FORECAST_SOURCE, TODO_SOURCE]
+        .into_iter()
+        .filter(|n| wanted.contains(n))
+        .collect::<Vec<_>>();
+    tracing::info!("Refresh: {}", wanted.iter().join(", "));
+    // Out of the request, which would time out on slow sources
+    tokio::spawn(async move {
+        // Failures are in the status of each source
+        let _ = fetch(cfg, db, sources, &ha, shared).await;
+    });
+
+    Ok(res)
+}
+
+/// Fetches the source `name` from its cron job.
+async fn run_scheduled(cfg: Config, db: Db, shared: SharedState, name: String) {
+    tracing::debug!("Cron job {}: start", name);
+    let started = OffsetDateTime::now_utc();
+    let res = match cfg.calendar_sources.iter().find(|s| s.name == name) {
+        Some(source) => fetch(cfg.clone(), db, vec![source.clone()], &[], shared.clone()).await,
+        None => {
+            fetch(
+                cfg.clone(),
+                db,
+                Vec::new(),
+                &[name.as_str()],
+                shared.clone(),
+            )
+            .await
         }
+    };
+    match res {
+        Ok(()) => tracing::info!("Cron job {}: Run success", name),
+        Err(e) => tracing::error!("Cron job {}: Unable to fetch: {:?}", name, e),
     }
 
+    let tz = timezones::get_by_name(&cfg.tz).unwrap_or(timezones::db::UTC);
+    if let Some(job) = shared.schedules.write().await.get_mut(&name) {
+        // Not before the run that was due, jobs can start a moment early
+        let now = OffsetDateTime::now_utc();
+        let after = job.next_run.map_or(now, |t| t.max(now));
+        job.last_run = Some(started);
+        job.next_run = job.schedule.next_run(after, tz);
+    }
+}
+
+/// Fetches the source `name` from the one-shot job `job_id`, then adds the job
+/// of its next run. Cron jobs of the scheduler keep the UTC offset they were
+/// added at, so cron schedules are a chain of one-shot jobs instead.
+fn run_cron(
+    cfg: Config,
+    db: Db,
+    shared: SharedState,
+    name: String,
+    job_id: Uuid,
+    sched: JobScheduler,
+) -> BoxFuture<'static, ()> {
+    async move {
+        run_scheduled(cfg.clone(), db.clone(), shared.clone(), name.clone()).await;
+
+        let mut schedules = shared.schedules.write().await;
+        // Unless registered again meanwhile
+        let Some(job) = schedules.get_mut(&name).filter(|j| j.job_id == job_id) else {
+            return;
+        };
+        let Some(next_run) = job.next_run else {
+            tracing::error!("Cron job {}: no next run", name);
+            return;
+        };
+        let next = match cron_job(cfg, db, shared.clone(), name.clone(), next_run) {
+            Ok(next) => next,
+            Err(e) => {
+                tracing::error!("Cron job {}: unable to schedule: {:?}", name, e);
+                return;
+            }
+        };
+        // Known before it can run
+        job.job_id = next.guid();
+        if let Err(e) = sched.add(next).await {
+            tracing::error!("Cron job {}: unable to schedule: {:?}", name, e);
+        }
+    }
+    .boxed()
+}
+
+/// One-shot job fetching the source `name` at `at`.
+fn cron_job(
+    cfg: Config,
+    db: Db,
+    shared: SharedState,
+    name: String,
+    at: OffsetDateTime,
+) -> Result<Job, JobSchedulerError> {
+    let wait = (at - OffsetDateTime::now_utc())
+        .try_into()
+        .unwrap_or_default();
+
+    Job::new_one_shot_async(wait, move |job_id, sched| {
+        run_cron(
+            cfg.clone(),
+            db.clone(),
+            shared.clone(),
+            name.clone(),
+            job_id,
+            sched,
+        )
+    })
+}
+
+/// Registers the job fetching the source `name` on `schedule`, replacing its previous one.
+pub async fn register(
+    sched: &JobScheduler,
+    cfg: Config,
+    db: Db,
+    shared: SharedState,
+    name: String,
+    schedule: Schedule,
+) -> Result<(), JobSchedulerError> {
+    let previous = shared.schedules.read().await.get(&name).map(|j| j.job_id);
+    if let Some(job_id) = previous {
+        sched.remove(&job_id).await?;
+    }
+
+    let cron = schedule
+        .cron()
+        .map_err(|_| JobSchedulerError::ParseSchedule)?;
+    let tz = timezones::get_by_name(&cfg.tz).unwrap_or(timezones::db::UTC);
+    let next_run = schedule.next_run(OffsetDateTime::now_utc(), tz);
+    let job = match (&schedule, next_run) {
+        (Schedule::Interval(secs), _) => {
+            let (shared, name) = (shared.clone(), name.clone());
+            let run = move |_uuid, _l| -> Pin<Box<dyn Future<Output = ()> + Send>> {
+                Box::pin(run_scheduled(
+                    cfg.clone(),
+                    db.clone(),
+                    shared.clone(),
+                    name.clone(),
+                ))
+            };
+
+            Job::new_repeated_async(std::time::Duration::from_secs(*secs), run)?
+        }
+        (Schedule::Cron(_), Some(next_run)) => {
+            cron_job(cfg, db, shared.clone(), name.clone(), next_run)?
+        }
+        (Schedule::Cron(_), None) => return Err(JobSchedulerError::ParseSchedule),
+    };
+
+    // Before the job is added, which may run it at once
+    shared.schedules.write().await.insert(
+        name.clone(),
+        ScheduledJob {
+            next_run,
+            schedule: schedule.clone(),
+            cron,
+            job_id: job.guid(),
+            last_run: None,
+        },
+    );
+    sched.add(job).await?;
+    tracing::info!("Cron job {}: scheduled {:?}", name, schedule);
+
     Ok(())
 }
 
 pub async fn setup(
     cfg: Config,
-    calendar: CalendarMapArc,
-    weather: WeatherInfoArc,
-    last_update: LastUpdateArc,
+    db: Db,
+    shared: SharedState,
 ) -> Result<JobScheduler, JobSchedulerError> {
     let mut sched = JobScheduler::new().await?;
 
     // Run init job
-    if let Err(e) = fetch(
+    match fetch(
         cfg.clone(),
-        calendar.clone(),
-        weather.clone(),
-        last_update.clone(),
+        db.clone(),
+        cfg.calendar_sources.clone(),
+        &ha_sources(&cfg),
+        shared.clone(),
     )
     .await
     {
-        tracing::error!("Cron init: Unable to fetch holiday: {:?}", e);
+        Ok(()) => tracing::info!("Cron init: Run success"),
+        Err(e) => tracing::error!("Cron init: Unable to fetch: {:?}", e),
     }
-    tracing::info!("Cron init: Run success");
-
-    // Add async job
-    sched
-        .add(Job::new_async("0 */5 * * * *", move |_uuid, _l| {
-            let clnd = calendar.clone();
-            let wth = weather.clone();
-            let lu_c = last_update.clone();
-            let cfg_c = cfg.clone();
-            Box::pin(async move {
-                tracing::debug!("Cron job: start");
-                if let Err(e) = fetch(cfg_c, clnd.clone(), wth.clone(), lu_c.clone()).await {
-                    tracing::error!("Cron init: Unable to fetch holiday: {:?}", e);
-                }
-                tracing::info!("Cron job: Run success");
-            })
-        })?)
+
+    // Every source gets its own job
+    let names = cfg
+        .calendar_sources
+        .iter()
+        .map(|s| s.name.clone())
+        .chain(ha_sources(&cfg).into_iter().map(str::to_string))
+        .collect_vec();
+    for name in names {
+        let schedule = cfg.schedule(&name).clone();
+        register(
+            &sched,
+            cfg.clone(),
+            db.clone(),
+            shared.clone(),
+            name,
+            schedule,
+        )
         .await?;
+    }
 
     // Feature 'signal' must be enabled
     sched.shutdown_on_ctrl_c();
@@ -1,5 +1,15 @@
 use ::anyhow::Result;
-use sqlx::{postgres::PgPoolOptions, PgPool};
+use sqlx::{PgPool, Row, postgres::PgPoolOptions, types::Json};
+use std::collections::{BTreeMap, HashMap};
+use time::{Date, PrimitiveDateTime};
+use time_tz::{OffsetDateTimeExt, Tz};
+
+use crate::{
+    CalendarColor,
+    model::{
+        CalendarMap, DateInfo, DateInfoEventMode, DateInfoHoliday, WeatherForecast, WeatherInfo,
+    },
+};
 
 #[derive(Clone)]
 pub struct Db {
@@ -27,4 +37,216 @@ impl Db {
         sqlx::migrate!("./migrations").run(&self.pool).await?;
         Ok(())
     }
+
+    /// Replaces every holiday stored for `source`.
+    pub async fn save_holidays(
+        &self,
+        source: &str,
+        holidays: &BTreeMap<Date, DateInfoHoliday>,
+    ) -> Result<(), sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query("DELETE FROM calendar_holidays WHERE source = $1")
+            .bind(source)
+            .execute(&mut *tx)
+            .await?;
+
+        for (date, holiday) in holidays {
+            sqlx::query(
+                "INSERT INTO calendar_holidays (date, source, name, color) VALUES ($1, $2, $3, $4)",
+            )
+            .bind(date)
+            .bind(&holiday.source)
+            .bind(&holiday.name)
+            .bind(holiday.color.as_str())
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        tx.commit().await
+    }
+
+    /// Replaces every event stored for `source`.
+    pub async fn save_events(
+        &self,
+        source: &str,
+        events: &BTreeMap<Date, HashMap<String, DateInfoEventMode>>,
+    ) -> Result<(), sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query("DELETE FROM calendar_events WHERE source = $1")
+            .bind(source)
+            .execute(&mut *tx)
+            .await?;
+
+        for (date, events) in events {
+            for (id, event) in events {
+                sqlx::query(
+                    "INSERT INTO calendar_events (date, id, source, name, start_at, end_at, all_day, color, day, days) \
+                     VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)",
+                )
+                .bind(date)
+                .bind(id)
+                .bind(&event.source)
+                .bind(&event.name)
+                .bind(event.time)
+                .bind(event.end)
+                .bind(event.all_day)
+                .bind(event.color.as_str())
+                .bind(event.day as i32)
+                .bind(event.days as i32)
+                .execute(&mut *tx)
+                .await?;
+            }
+        }
+
+        tx.commit().await
+    }
+
+    /// Removes calendar entries before `from`.
+    pub async fn prune_calendar(&self, from: Date) -> Result<(), sqlx::Error> {
+        sqlx::query("DELETE FROM calendar_holidays WHERE date < $1")
+            .bind(from)
+            .execute(&self.pool)
+            .await?;
+        sqlx::query("DELETE FROM calendar_events WHERE date < $1")
+            .bind(from)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Loads the stored calendar, with event times in `tz`.
+    pub async fn load_calendar(&self, tz: &Tz) -> Result<CalendarMap, sqlx::Error> {
+        let mut calendar = CalendarMap::new();
+
+        let holidays = sqlx::query(
+            "SELECT date, source, name, color FROM calendar_holidays ORDER BY date, source",
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        for row in holidays {
+            date_entry(&mut calendar, row.try_get("date")?)
+                .holidays
+                .push(DateInfoHoliday {
+                    name: row.try_get("name")?,
+                    source: row.try_get("source")?,
+                    color: parse_color(row.try_get("color")?),
+                });
+        }
+
+        let events = sqlx::query(
+            "SELECT date, id, source, name, start_at, end_at, all_day, color, day, days FROM calendar_events",
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        for row in events {
+            let start_at: time::OffsetDateTime = row.try_get("start_at")?;
+            let end_at: time::OffsetDateTime = row.try_get("end_at")?;
+            let day: i32 = row.try_get("day")?;
+            let days: i32 = row.try_get("days")?;
+            date_entry(&mut calendar, row.try_get("date")?)
+                .events
+                .insert(
+                    row.try_get("id")?,
+                    DateInfoEventMode {
+                        time: start_at.to_timezone(tz),
+                        end: end_at.to_timezone(tz),
+                        all_day: row.try_get("all_day")?,
+                        name: row.try_get("name")?,
+                        source: row.try_get("source")?,
+                        color: parse_color(row.try_get("color")?),
+                        day: day as u32,
+                        days: days as u32,
+                    },
+                );
+        }
+
+        Ok(calendar)
+    }
+
+    /// Stores a weather snapshot, keeping only the latest one.
+    pub async fn save_weather(&self, weather: &WeatherInfo) -> Result<(), sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query("DELETE FROM weather_snapshots")
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("INSERT INTO weather_snapshots (data) VALUES ($1)")
+            .bind(Json(weather))
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await
+    }
+
+    pub async fn load_weather(&self) -> Result<Option<WeatherInfo>, sqlx::Error> {
+        let row =
+            sqlx::query("SELECT data FROM weather_snapshots ORDER BY fetched_at DESC LIMIT 1")
+                .fetch_optional(&self.pool)
+                .await?;
+
+        row.map(|row| row.try_get::<Json<WeatherInfo>, _>("data").map(|j| j.0))
+            .transpose()
+    }
+
+    pub async fn save_forecast(&self, forecast: &WeatherForecast) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "INSERT INTO weather_forecasts (id, fetched_at, data) VALUES (TRUE, now(), $1) \
+             ON CONFLICT (id) DO UPDATE SET fetched_at = EXCLUDED.fetched_at, data = EXCLUDED.data",
+        )
+        .bind(Json(forecast))
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn load_forecast(&self) -> Result<Option<WeatherForecast>, sqlx::Error> {
+        let row = sqlx::query("SELECT data FROM weather_forecasts")
+            .fetch_optional(&self.pool)
+            .await?;
+
+        row.map(|row| row.try_get::<Json<WeatherForecast>, _>("data").map(|j| j.0))
+            .transpose()
+    }
+
+    pub async fn save_last_update(
+        &self,
+        last_update: PrimitiveDateTime,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "INSERT INTO last_update (id, updated_at) VALUES (TRUE, $1) \
+             ON CONFLICT (id) DO UPDATE SET updated_at = EXCLUDED.updated_at",
+        )
+        .bind(last_update)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn load_last_update(&self) -> Result<Option<PrimitiveDateTime>, sqlx::Error> {
+        let row = sqlx::query("SELECT updated_at FROM last_update")
+            .fetch_optional(&self.pool)
+            .await?;
+
+        row.map(|row| row.try_get("updated_at")).transpose()
+    }
+}
+
+fn date_entry(calendar: &mut CalendarMap, date: Date) -> &mut DateInfo {
+    calendar.entry(date).or_insert_with(|| DateInfo {
+        date,
+        holidays: Default::default(),
+        events: Default::default(),
+    })
+}
+
+// Unknown colours, e.g. from a newer schema, fall back to black.
+fn parse_color(color: String) -> CalendarColor {
+    color.parse().unwrap_or(CalendarColor::Black)
 }
@@ -0,0 +1,164 @@
+//! How fills and images are brought down to the few inks of a panel. Text and
+//! lines are not dithered, see [`crate::layout::Surface`].
+use std::sync::LazyLock;
+
+use serde::Deserialize;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Dither {
+    /// The nearest ink, a hard threshold.
+    None,
+    /// Ordered with an 8×8 Bayer matrix: a regular cross-hatch.
+    Bayer,
+    /// Error diffusion to four neighbours.
+    #[default]
+    FloydSteinberg,
+    /// Error diffusion of three quarters of the error to six neighbours:
+    /// lighter tints and more contrast than Floyd–Steinberg.
+    Atkinson,
+    /// Ordered with a blue-noise threshold map: no visible pattern nor worms.
+    BlueNoise,
+}
+
+/// Neighbours `(dx, dy, share)` taking a share of the error of a pixel.
+type Diffusion = &'static [(isize, usize, f32)];
+
+const FLOYD_STEINBERG: Diffusion = &[
+    (1, 0, 7.0 / 16.0),
+    (-1, 1, 3.0 / 16.0),
+    (0, 1, 5.0 / 16.0),
+    (1, 1, 1.0 / 16.0),
+];
+
+const ATKINSON: Diffusion = &[
+    (1, 0, 1.0 / 8.0),
+    (2, 0, 1.0 / 8.0),
+    (-1, 1, 1.0 / 8.0),
+    (0, 1, 1.0 / 8.0),
+    (1, 1, 1.0 / 8.0),
+    (0, 2, 1.0 / 8.0),
+];
+
+const BAYER_SIZE: u32 = 8;
+
+/// Side of the blue-noise tile, repeated over the page.
+const BLUE_NOISE_SIZE: usize = 64;
+
+static BLUE_NOISE: LazyLock<Vec<f32>> = LazyLock::new(void_and_cluster);
+
+impl Dither {
+    /// Threshold within 0..1 at `x`, `y` for ordered methods.
+    pub fn threshold(&self, x: u32, y: u32) -> Option<f32> {
+        match self {
+            Self::Bayer => Some((bayer(x % BAYER_SIZE, y % BAYER_SIZE) as f32 + 0.5) / 64.0),
+            Self::BlueNoise => {
+                let (x, y) = (x as usize % BLUE_NOISE_SIZE, y as usize % BLUE_NOISE_SIZE);
+                Some(BLUE_NOISE[y * BLUE_NOISE_SIZE + x])
+            }
+            _ => None,
+        }
+    }
+
+    /// How error diffusion methods spread the error of a pixel.
+    pub fn diffusion(&self) -> Diffusion {
+        match self {
+            Self::FloydSteinberg => FLOYD_STEINBERG,
+            Self::Atkinson => ATKINSON,
+            _ => &[],
+        }
+    }
+}
+
+/// Rank of `x`, `y` in the 8×8 Bayer matrix. Each bit of the coordinates
+/// picks a cell of the 2×2 matrix `[[0, 2], [3, 1]]`, lowest bits weighing most.
+fn bayer(x: u32, y: u32) -> u32 {
+    (0..3).fold(0, |rank, bit| {
+        let (x, y) = ((x >> bit) & 1, (y >> bit) & 1);
+        rank + (2 * (x ^ y) + y) * 4_u32.pow(2 - bit)
+    })
+}
+
+/// Blue-noise thresholds by Ulichney's void-and-cluster method: points are
+/// ranked by taking them out of the tightest clusters, then putting them into
+/// the largest voids, of a pattern that starts evenly spread.
+fn void_and_cluster() -> Vec<f32> {
+    const N: usize = BLUE_NOISE_SIZE;
+    const LEN: usize = N * N;
+    const SIGMA: f32 = 1.5;
+
+    // Energy a point adds to the points around it, wrapping around the tile
+    let kernel = (0..LEN)
+        .map(|i| {
+            let (dx, dy) = (i % N, i / N);
+            let (dx, dy) = (dx.min(N - dx) as f32, dy.min(N - dy) as f32);
+            (-(dx * dx + dy * dy) / (2.0 * SIGMA * SIGMA)).exp()
+        })
+        .collect::<Vec<_>>();
+    let set = |ones: &mut [bool], energy: &mut [f32], p: usize, on: bool| {
+        ones[p] = on;
+        let sign = if on { 1.0 } else { -1.0 };
+        let (px, py) = (p % N, p / N);
+        energy.iter_mut().enumerate().for_each(|(q, e)| {
+            let (dx, dy) = ((q % N + N - px) % N, (q / N + N - py) % N);
+            *e += sign * kernel[dy * N + dx];
+        });
+    };
+    let tightest_cluster = |ones: &[bool], energy: &[f32]| {
+        (0..LEN)
+            .filter(|&p| ones[p])
+            .max_by(|&a, &b| energy[a].total_cmp(&energy[b]))
+    };
+    let largest_void = |ones: &[bool], energy: &[f32]| {
+        (0..LEN)
+            .filter(|&p| !ones[p])
+            .min_by(|&a, &b| energy[a].total_cmp(&energy[b]))
+    };
+
+    // A tenth of the points, at fixed pseudo-random places
+    let mut ones = vec![false; LEN];
+    let mut energy = vec![0.0; LEN];
+    let initial = LEN / 10;
+    let mut seed = 0x2545_f491_u32;
+    let mut count = 0;
+    while count < initial {
+        seed ^= seed << 13;
+        seed ^= seed >> 17;
+        seed ^= seed << 5;
+        let p = seed as usize % LEN;
+        if !ones[p] {
+            set(&mut ones, &mut energy, p, true);
+            count += 1;
+        }
+    }
+    // Moved from clusters to voids until they are evenly spread
+    while let Some(cluster) = tightest_cluster(&ones, &energy) {
+        set(&mut ones, &mut energy, cluster, false);
+        match largest_void(&ones, &energy) {
+            Some(void) if void != cluster => set(&mut ones, &mut energy, void, true),
+            _ => {
+                set(&mut ones, &mut energy, cluster, true);
+                break;
+            }
+        }
+    }
+
+    let mut rank = vec![0; LEN];
+    let (mut phase_ones, mut phase_energy) = (ones.clone(), energy.clone());
+    for r in (0..initial).rev() {
+        if let Some(cluster) = tightest_cluster(&phase_ones, &phase_energy) {
+            set(&mut phase_ones, &mut phase_energy, cluster, false);
+            rank[cluster] = r;
+        }
+    }
+    for r in initial..LEN {
+        if let Some(void) = largest_void(&ones, &energy) {
+            set(&mut ones, &mut energy, void, true);
+            rank[void] = r;
+        }
+    }
+
+    rank.into_iter()
+        .map(|r| (r as f32 + 0.5) / LEN as f32)
+        .collect()
+}
@@ -0,0 +1,137 @@
+//! The page as the frame buffers of an e-paper controller, so a device can
+//! send it to the panel as it is instead of decoding an image.
+//!
+//! A body is a 16-byte header, little-endian:
+//!
+//! | Offset | Size | Field                                                  |
+//! |--------|------|--------------------------------------------------------|
+//! | 0      | 4    | `EPD1`                                                 |
+//! | 4      | 2    | width in pixels                                        |
+//! | 6      | 2    | height in pixels                                       |
+//! | 8      | 2    | bytes per row, `ceil(width / 8)`                       |
+//! | 10     | 1    | number of planes                                       |
+//! | 11     | 1    | flags, bit 0 when the planes are RLE-compressed        |
+//! | 12     | 4    | CRC-32 (IEEE) of everything after the header           |
+//!
+//! then one byte per plane naming its ink (see [`ink_code`]), then the planes
+//! one after the other. A plane has one bit per pixel, the leftmost pixel in
+//! the highest bit, and each row padded to whole bytes. As in the Waveshare
+//! drivers, a 0 bit puts the ink on the pixel and a 1 bit leaves it white.
+//!
+//! Compressed planes are PackBits as a whole: a control byte `n` below 128 is
+//! followed by `n + 1` bytes to copy, one above 128 by a byte to repeat
+//! `257 - n` times, and 128 is skipped. The planes are joined before they are
+//! compressed, so a run or literal may start in one plane and end in the
+//! next: decompress the whole payload, then split it every `stride * height`
+//! bytes.
+//!
+//! The CRC is of the body as sent: the ink bytes followed by the planes,
+//! compressed when the flag says so. It is not of the decompressed planes, so
+//! a device can check the body before it decodes it.
+use crate::layout::Color;
+
+pub const MAGIC: &[u8; 4] = b"EPD1";
+pub const HEADER_LEN: usize = 16;
+/// The planes are PackBits-compressed.
+pub const FLAG_RLE: u8 = 1;
+
+/// Number of the ink of a plane in the body.
+pub fn ink_code(ink: Color) -> u8 {
+    match ink {
+        Color::Black => 0,
+        Color::Red => 1,
+        Color::Yellow => 2,
+        Color::Green => 3,
+        Color::Blue => 4,
+        Color::Orange => 5,
+        Color::Gray => 6,
+        Color::White => 7,
+    }
+}
+
+/// Bytes of one row of a plane `width` pixels wide.
+pub fn stride(width: u32) -> usize {
+    width.div_ceil(8) as usize
+}
+
+/// One plane of `width` × `height` pixels, with the ink where `is_ink` is.
+pub fn pack_plane(width: u32, height: u32, is_ink: impl Fn(u32, u32) -> bool) -> Vec<u8> {
+    let stride = stride(width);
+    let mut plane = vec![0xff; stride * height as usize];
+
+    for y in 0..height {
+        for x in (0..width).filter(|&x| is_ink(x, y)) {
+            plane[y as usize * stride + x as usize / 8] &= !(0x80 >> (x % 8));
+        }
+    }
+
+    plane
+}
+
+/// PackBits: runs of 2 to 128 equal bytes and literals of 1 to 128 bytes.
+pub fn pack_bits(data: &[u8]) -> Vec<u8> {
+    let mut packed = Vec::with_capacity(data.len() / 2);
+    let mut literal = Vec::with_capacity(128);
+    let mut idx = 0;
+
+    let flush = |packed: &mut Vec<u8>, literal: &mut Vec<u8>| {
+        if !literal.is_empty() {
+            packed.push(literal.len() as u8 - 1);
+            packed.append(literal);
+        }
+    };
+
+    while idx < data.len() {
+        let run = data[idx..]
+            .iter()
+            .take(128)
+            .take_while(|b| **b == data[idx])
+            .count();
+
+        if run >= 2 {
+            flush(&mut packed, &mut literal);
+            packed.extend([(257 - run) as u8, data[idx]]);
+            idx += run;
+        } else {
+            literal.push(data[idx]);
+            idx += 1;
+            if literal.len() == 128 {
+                flush(&mut packed, &mut literal);
+            }
+        }
+    }
+    flush(&mut packed, &mut literal);
+
+    packed
+}
+
+/// The body for `planes` of `width` × `height` pixels, each with its ink.
+/// With `rle`, the planes are joined then compressed as one, and the CRC is of
+/// the ink bytes and the compressed planes.
+pub fn encode(width: u32, height: u32, planes: &[(Color, Vec<u8>)], rle: bool) -> Vec<u8> {
+    let data = planes
+        .iter()
+        .flat_map(|(_, plane)| plane)
+        .copied()
+        .collect::<Vec<_>>();
+    let mut payload = planes
+        .iter()
+        .map(|(ink, _)| ink_code(*ink))
+        .collect::<Vec<_>>();
+    match rle {
+        true => payload.extend(pack_bits(&data)),
+        false => payload.extend(data),
+    }
+
+    let mut body = Vec::with_capacity(HEADER_LEN + payload.len());
+    body.extend(MAGIC);
+    body.extend((width as u16).to_le_bytes());
+    body.extend((height as u16).to_le_bytes());
+    body.extend((stride(width) as u16).to_le_bytes());
+    body.push(planes.len() as u8);
+    body.push(if rle { FLAG_RLE } else { 0 });
+    body.extend(crc32fast::hash(&payload).to_le_bytes());
+    body.extend(payload);
+
+    body
+}
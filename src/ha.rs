@@ -0,0 +1,298 @@
+//! Home Assistant WebSocket client.
+//!
+//! Keeps the followed entities up to date from state triggers instead of polling.
+//! See: https://developers.home-assistant.io/docs/api/websocket
+use std::time::{Duration, Instant};
+
+use futures_util::{SinkExt, StreamExt};
+use itertools::Itertools;
+use serde::Deserialize;
+use serde_json::json;
+use time::OffsetDateTime;
+use tokio_tungstenite::{connect_async, tungstenite::Message};
+
+use crate::{
+    Config, Db,
+    api_error::ApiError,
+    cron,
+    model::{HaEntityState, SharedState, WeatherInfo},
+};
+
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(300);
+const SUBSCRIBE_ID: u64 = 1;
+/// How often a live connection refreshes the status of Home Assistant.
+const STATUS_INTERVAL: Duration = Duration::from_secs(60);
+/// Silence after which the connection is pinged, then given up on when the
+/// ping is not answered within as long again.
+const PING_INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum HaMessage {
+    AuthRequired,
+    AuthOk,
+    AuthInvalid {
+        #[serde(default)]
+        message: Option<String>,
+    },
+    Result {
+        id: u64,
+        success: bool,
+        #[serde(default)]
+        error: Option<serde_json::Value>,
+    },
+    Event {
+        event: HaEvent,
+    },
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Deserialize, Debug)]
+struct HaEvent {
+    variables: HaTriggerVariables,
+}
+
+#[derive(Deserialize, Debug)]
+struct HaTriggerVariables {
+    trigger: HaStateTrigger,
+}
+
+/// A state trigger firing, on any change of the state or attributes.
+#[derive(Deserialize, Debug)]
+struct HaStateTrigger {
+    entity_id: String,
+    #[serde(default)]
+    to_state: Option<serde_json::Value>,
+}
+
+/// WebSocket endpoint of the Home Assistant instance at `ha_url`.
+pub fn websocket_url(ha_url: &str) -> String {
+    let base = match ha_url.split_once("://") {
+        Some(("https", rest)) => format! {"wss://{rest}"},
+        Some((_, rest)) => format! {"ws://{rest}"},
+        None => format! {"ws://{ha_url}"},
+    };
+
+    format! {"{}/api/websocket", base.trim_end_matches('/')}
+}
+
+/// Records the outcome of following Home Assistant in the status of its source.
+async fn record_status(shared: &SharedState, res: &Result<(), ApiError>) {
+    let now = OffsetDateTime::now_utc();
+    let mut status = shared.status.write().await;
+    let entry = status
+        .entry(cron::HA_STATES_SOURCE.to_string())
+        .or_default();
+
+    match res {
+        Ok(()) => entry.record_success(now),
+        Err(e) => entry.record_failure(now, e.detail()),
+    }
+}
+
+/// Follows Home Assistant until the process stops.
+///
+/// Reconnects with an exponential backoff. While the socket is down the weather and
+/// sensors are polled over REST on every attempt.
+pub async fn run(cfg: Config, db: Db, shared: SharedState) {
+    let mut backoff = MIN_BACKOFF;
+
+    loop {
+        match listen(&cfg, &db, &shared, &mut backoff).await {
+            Ok(()) => tracing::warn!("HA WebSocket: connection closed"),
+            Err(e) => tracing::error!("HA WebSocket: {:?}", e),
+        }
+
+        // REST fallback while disconnected
+        let res = cron::fetch_ha_states(cfg.clone(), db.clone(), shared.clone()).await;
+        if let Err(e) = &res {
+            tracing::error!("HA WebSocket: REST fallback failed: {:?}", e);
+        }
+        record_status(&shared, &res).await;
+
+        tracing::debug!("HA WebSocket: reconnect in {:?}", backoff);
+        tokio::time::sleep(backoff).await;
+        backoff = Duration::min(backoff * 2, MAX_BACKOFF);
+    }
+}
+
+/// Runs one connection until it closes or stops answering. `backoff` is reset
+/// once subscribed. Entities that cannot be saved are logged and left out,
+/// the connection is kept.
+async fn listen(
+    cfg: &Config,
+    db: &Db,
+    shared: &SharedState,
+    backoff: &mut Duration,
+) -> Result<(), ApiError> {
+    let (mut ws, _res) = connect_async(websocket_url(&cfg.ha_url))
+        .await
+        .map_err(|e| ApiError::InternalError(e.into()))?;
+    let mut status_at: Option<Instant> = None;
+    let mut next_id = SUBSCRIBE_ID + 1;
+    let mut pinged = false;
+
+    loop {
+        let msg = match tokio::time::timeout(PING_INTERVAL, ws.next()).await {
+            Ok(Some(msg)) => msg.map_err(|e| ApiError::InternalError(e.into()))?,
+            Ok(None) => break,
+            Err(_) if pinged => {
+                return Err(ApiError::InternalError(anyhow::anyhow!(
+                    "No answer to a ping in {:?}",
+                    PING_INTERVAL
+                )));
+            }
+            Err(_) => {
+                let ping = json!({ "id": next_id, "type": "ping" });
+                ws.send(Message::text(ping.to_string()))
+                    .await
+                    .map_err(|e| ApiError::InternalError(e.into()))?;
+                next_id += 1;
+                pinged = true;
+                continue;
+            }
+        };
+        // Any message, the pong included, shows the connection alive
+        pinged = false;
+        // and once subscribed, that the followed entities are up to date
+        if status_at.is_some_and(|t| t.elapsed() >= STATUS_INTERVAL) {
+            record_status(shared, &Ok(())).await;
+            status_at = Some(Instant::now());
+        }
+        let text = match msg {
+            Message::Text(text) => text,
+            Message::Close(_) => break,
+            _ => continue,
+        };
+        let msg = match serde_json::from_str::<HaMessage>(&text) {
+            Ok(msg) => msg,
+            Err(e) => {
+                tracing::warn!("HA WebSocket: unreadable message: {:?}", e);
+                continue;
+            }
+        };
+
+        match msg {
+            HaMessage::AuthRequired => {
+                let auth = json!({ "type": "auth", "access_token": cfg.ha_token });
+                ws.send(Message::text(auth.to_string()))
+                    .await
+                    .map_err(|e| ApiError::InternalError(e.into()))?;
+            }
+            HaMessage::AuthOk => {
+                // Only the followed entities are sent
+                let entity_ids = std::iter::once(&cfg.ha_weather_entity)
+                    .chain(&cfg.ha_todo_entities)
+                    .chain(cfg.ha_sensors.iter().map(|s| &s.entity_id))
+                    .unique()
+                    .collect_vec();
+                let subscribe = json!({
+                    "id": SUBSCRIBE_ID,
+                    "type": "subscribe_trigger",
+                    "trigger": {"platform": "state", "entity_id": entity_ids},
+                });
+                ws.send(Message::text(subscribe.to_string()))
+                    .await
+                    .map_err(|e| ApiError::InternalError(e.into()))?;
+            }
+            HaMessage::AuthInvalid { message } => {
+                return Err(ApiError::InternalError(anyhow::anyhow!(
+                    "Authentication failed: {}",
+                    message.unwrap_or_default()
+                )));
+            }
+            HaMessage::Result {
+                id: SUBSCRIBE_ID,
+                success,
+                error,
+            } => {
+                if !success {
+                    return Err(ApiError::InternalError(anyhow::anyhow!(
+                        "Unable to subscribe: {:?}",
+                        error
+                    )));
+                }
+                tracing::info!("HA WebSocket: subscribed");
+                *backoff = MIN_BACKOFF;
+
+                // Catch up on what changed while disconnected
+                let res = cron::fetch_ha_states(cfg.clone(), db.clone(), shared.clone()).await;
+                if let Err(e) = &res {
+                    tracing::warn!("HA WebSocket: unable to catch up: {:?}", e);
+                }
+                record_status(shared, &res).await;
+                status_at = Some(Instant::now());
+            }
+            HaMessage::Event { event } => {
+                let entity_id = event.variables.trigger.entity_id;
+                // Removed entities have no state
+                let Some(new_state) = event.variables.trigger.to_state else {
+                    continue;
+                };
+
+                if entity_id == cfg.ha_weather_entity {
+                    let res = match serde_json::from_value::<WeatherInfo>(new_state.clone()) {
+                        Ok(res) => {
+                            cron::update_weather(
+                                db,
+                                &shared.weather,
+                                &shared.last_update,
+                                &shared.generation,
+                                res,
+                            )
+                            .await
+                        }
+                        Err(e) => {
+                            tracing::warn!("HA WebSocket: unreadable weather: {:?}", e);
+                            Ok(())
+                        }
+                    };
+                    if let Err(e) = res {
+                        tracing::error!("HA WebSocket: unable to save the weather: {:?}", e);
+                    }
+                }
+                // The state of a to-do entity is its item count, items are read again
+                if cfg.ha_todo_entities.contains(&entity_id) {
+                    let res = cron::fetch_todos(
+                        cfg.clone(),
+                        db.clone(),
+                        shared.todos.clone(),
+                        shared.last_update.clone(),
+                        shared.generation.clone(),
+                    )
+                    .await;
+                    if let Err(e) = res {
+                        tracing::error!("HA WebSocket: unable to update the to-dos: {:?}", e);
+                    }
+                }
+                if cfg.ha_sensors.iter().any(|s| s.entity_id == entity_id) {
+                    let res = match serde_json::from_value::<HaEntityState>(new_state) {
+                        Ok(res) => {
+                            cron::update_sensor(
+                                db,
+                                &shared.sensors,
+                                &shared.last_update,
+                                &shared.generation,
+                                &entity_id,
+                                res,
+                            )
+                            .await
+                        }
+                        Err(e) => {
+                            tracing::warn!("HA WebSocket: unreadable {}: {:?}", entity_id, e);
+                            Ok(())
+                        }
+                    };
+                    if let Err(e) = res {
+                        tracing::error!("HA WebSocket: unable to save {}: {:?}", entity_id, e);
+                    }
+                }
+            }
+            HaMessage::Result { .. } | HaMessage::Other => {}
+        }
+    }
+
+    Ok(())
+}
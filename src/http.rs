@@ -0,0 +1,79 @@
+//! Shared HTTP client and conditional requests.
+use std::{sync::LazyLock, time::Duration};
+
+use axum::body::Bytes;
+use reqwest::{RequestBuilder, StatusCode, header};
+
+use crate::api_error::ApiError;
+
+static CLIENT: LazyLock<reqwest::Client> = LazyLock::new(|| {
+    reqwest::Client::builder()
+        .user_agent(concat!(
+            env!("CARGO_PKG_NAME"),
+            "/",
+            env!("CARGO_PKG_VERSION")
+        ))
+        .connect_timeout(Duration::from_secs(10))
+        .timeout(Duration::from_secs(60))
+        .build()
+        .expect("Failed to build the HTTP client")
+});
+
+/// Client shared by every outgoing request, so connections are reused.
+pub fn client() -> &'static reqwest::Client {
+    &CLIENT
+}
+
+/// Cache validators of a response.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Validators {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+}
+
+pub enum Conditional {
+    Modified { body: Bytes, validators: Validators },
+    NotModified,
+}
+
+/// Sends `req` with `If-None-Match`/`If-Modified-Since` from `validators`.
+pub async fn send_conditional(
+    req: RequestBuilder,
+    validators: &Validators,
+) -> Result<Conditional, ApiError> {
+    let mut req = req;
+
+    if let Some(etag) = validators.etag.as_ref() {
+        req = req.header(header::IF_NONE_MATCH, etag);
+    }
+    if let Some(last_modified) = validators.last_modified.as_ref() {
+        req = req.header(header::IF_MODIFIED_SINCE, last_modified);
+    }
+
+    let res = req
+        .send()
+        .await
+        .and_then(|r| r.error_for_status())
+        .map_err(|e| ApiError::InternalError(e.into()))?;
+
+    if res.status() == StatusCode::NOT_MODIFIED {
+        return Ok(Conditional::NotModified);
+    }
+
+    let header_value = |name| {
+        res.headers()
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string)
+    };
+    let validators = Validators {
+        etag: header_value(header::ETAG),
+        last_modified: header_value(header::LAST_MODIFIED),
+    };
+    let body = res
+        .bytes()
+        .await
+        .map_err(|e| ApiError::InternalError(e.into()))?;
+
+    Ok(Conditional::Modified { body, validators })
+}
@@ -0,0 +1,745 @@
+use ical::{
+    parser::{Component, ical::component::IcalEvent},
+    property::Property,
+};
+use itertools::Itertools;
+use std::{collections::HashMap, io::BufRead, str::FromStr};
+use time::{
+    Date, Duration, Month, OffsetDateTime, PrimitiveDateTime, Weekday,
+    format_description::well_known::Iso8601, macros::format_description,
+};
+use time_tz::{OffsetDateTimeExt, PrimitiveDateTimeExt, Tz, timezones};
+
+/// Upper bound of recurrence periods walked for a single rule, so a broken
+/// feed cannot spin the fetcher forever.
+const MAX_PERIODS: i64 = 100_000;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Frequency {
+    Daily,
+    Weekly,
+    Monthly,
+    Yearly,
+}
+
+/// Subset of RFC 5545 `RRULE` used by calendar feeds.
+///
+/// `BYHOUR`, `BYMINUTE`, `BYWEEKNO` and `BYYEARDAY` are ignored.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RecurrenceRule {
+    pub freq: Frequency,
+    pub interval: u32,
+    pub count: Option<u32>,
+    pub until: Option<String>,
+    pub by_day: Vec<(Option<i32>, Weekday)>,
+    pub by_month_day: Vec<i32>,
+    pub by_month: Vec<u8>,
+    pub by_set_pos: Vec<i32>,
+}
+
+/// A single, expanded occurrence of a VEVENT.
+#[derive(Clone, Debug, PartialEq)]
+pub struct EventOccurrence {
+    /// Stable per-occurrence id: `UID` plus the original start of the occurrence.
+    pub id: String,
+    pub uid: String,
+    pub start: OffsetDateTime,
+    /// Exclusive end, from `DTEND` or `DURATION`.
+    pub end: OffsetDateTime,
+    /// Whether `DTSTART` is a `VALUE=DATE`.
+    pub all_day: bool,
+    pub summary: String,
+}
+
+fn parse_weekday(s: &str) -> Option<Weekday> {
+    Some(match s {
+        "MO" => Weekday::Monday,
+        "TU" => Weekday::Tuesday,
+        "WE" => Weekday::Wednesday,
+        "TH" => Weekday::Thursday,
+        "FR" => Weekday::Friday,
+        "SA" => Weekday::Saturday,
+        "SU" => Weekday::Sunday,
+        _ => return None,
+    })
+}
+
+fn parse_list<T: FromStr>(val: &str) -> Result<Vec<T>, String> {
+    val.split(',')
+        .map(|v| {
+            v.trim()
+                .parse::<T>()
+                .map_err(|_| format!("Invalid list value: {v}"))
+        })
+        .collect()
+}
+
+impl FromStr for RecurrenceRule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut freq = None;
+        let mut rule = RecurrenceRule {
+            freq: Frequency::Daily,
+            interval: 1,
+            count: None,
+            until: None,
+            by_day: Vec::new(),
+            by_month_day: Vec::new(),
+            by_month: Vec::new(),
+            by_set_pos: Vec::new(),
+        };
+
+        for part in s.split(';').filter(|p| !p.is_empty()) {
+            let Some((key, val)) = part.split_once('=') else {
+                return Err(format!("Invalid RRULE part: {part}"));
+            };
+
+            match key.to_uppercase().as_str() {
+                "FREQ" => {
+                    freq = Some(match val.to_uppercase().as_str() {
+                        "DAILY" => Frequency::Daily,
+                        "WEEKLY" => Frequency::Weekly,
+                        "MONTHLY" => Frequency::Monthly,
+                        "YEARLY" => Frequency::Yearly,
+                        _ => return Err(format!("Unsupported FREQ: {val}")),
+                    })
+                }
+                "INTERVAL" => {
+                    rule.interval = val
+                        .parse::<u32>()
+                        .ok()
+                        .filter(|i| *i > 0)
+                        .ok_or_else(|| format!("Invalid INTERVAL: {val}"))?
+                }
+                "COUNT" => {
+                    rule.count = Some(
+                        val.parse::<u32>()
+                            .map_err(|_| format!("Invalid COUNT: {val}"))?,
+                    )
+                }
+                "UNTIL" => rule.until = Some(val.to_string()),
+                "BYDAY" => {
+                    rule.by_day = val
+                        .split(',')
+                        .map(|d| {
+                            let d = d.trim().to_uppercase();
+                            let (ord, wd) = d.split_at(d.len().saturating_sub(2));
+                            let wd =
+                                parse_weekday(wd).ok_or_else(|| format!("Invalid BYDAY: {d}"))?;
+                            let ord = match ord {
+                                "" => None,
+                                ord => Some(
+                                    ord.trim_start_matches('+')
+                                        .parse::<i32>()
+                                        .map_err(|_| format!("Invalid BYDAY: {d}"))?,
+                                ),
+                            };
+
+                            Ok((ord, wd))
+                        })
+                        .collect::<Result<_, String>>()?
+                }
+                "BYMONTHDAY" => rule.by_month_day = parse_list(val)?,
+                "BYMONTH" => rule.by_month = parse_list(val)?,
+                "BYSETPOS" => rule.by_set_pos = parse_list(val)?,
+                _ => {}
+            }
+        }
+
+        rule.freq = freq.ok_or_else(|| "Missing FREQ".to_string())?;
+
+        Ok(rule)
+    }
+}
+
+fn add_months(date: Date, months: i64) -> Option<(i32, Month)> {
+    let idx = date.year() as i64 * 12 + (date.month() as i64 - 1) + months;
+    let year = i32::try_from(idx.div_euclid(12)).ok()?;
+    let month = Month::try_from((idx.rem_euclid(12) + 1) as u8).ok()?;
+
+    Some((year, month))
+}
+
+fn days_between(first: Date, last: Date) -> Vec<Date> {
+    let mut days = Vec::new();
+    let mut day = first;
+
+    while day <= last {
+        days.push(day);
+        let Some(next) = day.next_day() else {
+            break;
+        };
+        day = next;
+    }
+
+    days
+}
+
+fn month_days(year: i32, month: Month) -> Vec<Date> {
+    let (Ok(first), Ok(last)) = (
+        Date::from_calendar_date(year, month, 1),
+        Date::from_calendar_date(year, month, month.length(year)),
+    ) else {
+        return Vec::new();
+    };
+
+    days_between(first, last)
+}
+
+/// Pick the `(ordinal, weekday)` pairs out of `days`, counting ordinals
+/// inside `days` (a month or a year).
+fn select_by_day(days: &[Date], by_day: &[(Option<i32>, Weekday)]) -> Vec<Date> {
+    by_day
+        .iter()
+        .flat_map(|(ord, wd)| {
+            let matching = days.iter().filter(|d| d.weekday() == *wd).copied();
+
+            match ord {
+                None => matching.collect_vec(),
+                Some(n) => {
+                    let matching = matching.collect_vec();
+                    let idx = match *n {
+                        n if n > 0 => n as usize - 1,
+                        n => match matching.len().checked_sub(n.unsigned_abs() as usize) {
+                            Some(idx) => idx,
+                            None => return Vec::new(),
+                        },
+                    };
+
+                    matching.get(idx).copied().into_iter().collect_vec()
+                }
+            }
+        })
+        .collect_vec()
+}
+
+impl RecurrenceRule {
+    fn match_month_day(&self, date: Date) -> bool {
+        let days_in_month = date.month().length(date.year()) as i32;
+
+        self.by_month_day.iter().any(|md| {
+            let md = if *md < 0 { days_in_month + md + 1 } else { *md };
+            md == date.day() as i32
+        })
+    }
+
+    fn month_candidates(&self, year: i32, month: Month, default_day: u8) -> Vec<Date> {
+        let days = month_days(year, month);
+
+        match (self.by_day.is_empty(), self.by_month_day.is_empty()) {
+            (true, true) => days
+                .into_iter()
+                .filter(|d| d.day() == default_day)
+                .collect_vec(),
+            (true, false) => days
+                .into_iter()
+                .filter(|d| self.match_month_day(*d))
+                .collect_vec(),
+            (false, true) => select_by_day(&days, &self.by_day),
+            (false, false) => select_by_day(&days, &self.by_day)
+                .into_iter()
+                .filter(|d| self.match_month_day(*d))
+                .collect_vec(),
+        }
+    }
+
+    /// Returns the first day a period could cover, and the candidate dates of
+    /// that period.
+    fn period(&self, dtstart: Date, k: i64) -> Option<(Date, Vec<Date>)> {
+        let step = k * self.interval as i64;
+        let (anchor, mut dates) = match self.freq {
+            Frequency::Daily => {
+                let day = dtstart.checked_add(Duration::days(step))?;
+                let keep = (self.by_day.is_empty()
+                    || self.by_day.iter().any(|(_, wd)| *wd == day.weekday()))
+                    && (self.by_month_day.is_empty() || self.match_month_day(day));
+
+                (day, if keep { vec![day] } else { Vec::new() })
+            }
+            Frequency::Weekly => {
+                let week_start = dtstart
+                    .checked_sub(Duration::days(
+                        dtstart.weekday().number_days_from_monday() as i64
+                    ))?
+                    .checked_add(Duration::weeks(step))?;
+                let week = days_between(week_start, week_start.checked_add(Duration::days(6))?);
+                let dates = if self.by_day.is_empty() {
+                    week.into_iter()
+                        .filter(|d| d.weekday() == dtstart.weekday())
+                        .collect_vec()
+                } else {
+                    select_by_day(&week, &self.by_day)
+                };
+
+                (week_start, dates)
+            }
+            Frequency::Monthly => {
+                let (year, month) = add_months(dtstart, step)?;
+
+                (
+                    Date::from_calendar_date(year, month, 1).ok()?,
+                    self.month_candidates(year, month, dtstart.day()),
+                )
+            }
+            Frequency::Yearly => {
+                let year = dtstart.year().checked_add(i32::try_from(step).ok()?)?;
+                let anchor = Date::from_calendar_date(year, Month::January, 1).ok()?;
+                let dates = if !self.by_month.is_empty() {
+                    self.by_month
+                        .iter()
+                        .filter_map(|m| Month::try_from(*m).ok())
+                        .flat_map(|m| self.month_candidates(year, m, dtstart.day()))
+                        .collect_vec()
+                } else if !self.by_month_day.is_empty() {
+                    (1..=12)
+                        .filter_map(|m| Month::try_from(m).ok())
+                        .flat_map(|m| self.month_candidates(year, m, dtstart.day()))
+                        .collect_vec()
+                } else if !self.by_day.is_empty() {
+                    let last = Date::from_calendar_date(year, Month::December, 31).ok()?;
+                    select_by_day(&days_between(anchor, last), &self.by_day)
+                } else {
+                    Date::from_calendar_date(year, dtstart.month(), dtstart.day())
+                        .ok()
+                        .into_iter()
+                        .collect_vec()
+                };
+
+                (anchor, dates)
+            }
+        };
+
+        if !self.by_month.is_empty() {
+            dates.retain(|d| self.by_month.contains(&(d.month() as u8)));
+        }
+        dates.sort();
+        dates.dedup();
+
+        if !self.by_set_pos.is_empty() {
+            let len = dates.len() as i32;
+            dates = self
+                .by_set_pos
+                .iter()
+                .filter_map(|pos| {
+                    let idx = if *pos > 0 { pos - 1 } else { len + pos };
+                    usize::try_from(idx)
+                        .ok()
+                        .and_then(|idx| dates.get(idx).copied())
+                })
+                .sorted()
+                .dedup()
+                .collect_vec();
+        }
+
+        Some((anchor, dates))
+    }
+
+    /// Local start times of the occurrences from `dtstart` up to `end`
+    /// (inclusive), honouring `COUNT` and the already resolved `UNTIL`.
+    ///
+    /// `dtstart` is always the first occurrence, as required by RFC 5545.
+    pub fn occurrences(
+        &self,
+        dtstart: PrimitiveDateTime,
+        until: Option<PrimitiveDateTime>,
+        end: PrimitiveDateTime,
+    ) -> Vec<PrimitiveDateTime> {
+        let limit = until.map(|u| u.min(end)).unwrap_or(end);
+        let mut res = vec![dtstart];
+
+        'periods: for k in 1..MAX_PERIODS {
+            if self.count.is_some_and(|c| res.len() >= c as usize) {
+                break;
+            }
+
+            let Some((anchor, dates)) = self.period(dtstart.date(), k) else {
+                break;
+            };
+
+            if anchor > limit.date() {
+                break;
+            }
+
+            for date in dates {
+                let occ = PrimitiveDateTime::new(date, dtstart.time());
+
+                if occ <= dtstart {
+                    continue;
+                }
+
+                if occ > limit || self.count.is_some_and(|c| res.len() >= c as usize) {
+                    break 'periods;
+                }
+
+                res.push(occ);
+            }
+        }
+
+        res
+    }
+}
+
+/// A DATE or DATE-TIME property value (RFC 5545 section 3.3.4 and 3.3.5).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum IcalDateTime {
+    /// `VALUE=DATE`, an all-day value.
+    Date(Date),
+    /// DATE-TIME without `Z` or `TZID`, read in the default zone.
+    Floating(PrimitiveDateTime),
+    /// DATE-TIME with a `Z` suffix.
+    Utc(PrimitiveDateTime),
+    /// DATE-TIME qualified by a known `TZID`.
+    Zoned(PrimitiveDateTime, &'static Tz),
+}
+
+fn property_param<'a>(prop: &'a Property, name: &str) -> Option<&'a str> {
+    prop.params.as_ref().and_then(|prm| {
+        prm.iter().find_map(|(p_name, p_vals)| {
+            if !p_name.eq_ignore_ascii_case(name) {
+                return None;
+            }
+
+            p_vals.first().map(String::as_str)
+        })
+    })
+}
+
+impl IcalDateTime {
+    /// Parses a single value. `is_date` comes from a `VALUE=DATE` parameter,
+    /// but bare `YYYYMMDD` values are read as dates either way.
+    ///
+    /// Unknown `TZID`s (e.g. Windows zone names) are read as floating.
+    pub fn parse_value(val: &str, is_date: bool, tzid: Option<&str>) -> Option<Self> {
+        let val = val.trim();
+
+        if is_date || val.len() == 8 {
+            return Date::parse(val, format_description!("[year][month][day]"))
+                .or_else(|_| Date::parse(val, &Iso8601::DATE))
+                .ok()
+                .map(IcalDateTime::Date);
+        }
+
+        let (val, is_utc) = match val.strip_suffix(['Z', 'z']) {
+            Some(v) => (v, true),
+            None => (val, false),
+        };
+        let pdt = PrimitiveDateTime::parse(
+            val,
+            format_description!("[year][month][day]T[hour][minute][second]"),
+        )
+        .or_else(|_| PrimitiveDateTime::parse(val, &Iso8601::DATE_TIME))
+        .ok()?;
+
+        Some(match (is_utc, tzid.and_then(timezones::get_by_name)) {
+            (true, _) => IcalDateTime::Utc(pdt),
+            (false, Some(tz)) => IcalDateTime::Zoned(pdt, tz),
+            (false, None) => IcalDateTime::Floating(pdt),
+        })
+    }
+
+    /// Parses every comma separated value of a property (EXDATE, RDATE).
+    pub fn parse_property_list(prop: &Property) -> Vec<Self> {
+        let is_date = property_param(prop, "VALUE").is_some_and(|v| v.eq_ignore_ascii_case("DATE"));
+        let tzid = property_param(prop, "TZID");
+
+        prop.value
+            .as_deref()
+            .unwrap_or_default()
+            .split(',')
+            .filter_map(|v| IcalDateTime::parse_value(v, is_date, tzid))
+            .collect_vec()
+    }
+
+    /// Parses a single valued property (DTSTART, DTEND, RECURRENCE-ID).
+    pub fn parse_property(prop: &Property) -> Option<Self> {
+        IcalDateTime::parse_property_list(prop).into_iter().next()
+    }
+
+    pub fn is_all_day(&self) -> bool {
+        matches!(self, IcalDateTime::Date(_))
+    }
+
+    /// Zone the wall time of this value is expressed in.
+    pub fn tz(&self, default_tz: &'static Tz) -> &'static Tz {
+        match self {
+            IcalDateTime::Date(_) | IcalDateTime::Floating(_) => default_tz,
+            IcalDateTime::Utc(_) => timezones::db::UTC,
+            IcalDateTime::Zoned(_, tz) => tz,
+        }
+    }
+
+    /// Wall time of this value; dates start at midnight.
+    pub fn local(&self) -> PrimitiveDateTime {
+        match self {
+            IcalDateTime::Date(d) => d.midnight(),
+            IcalDateTime::Floating(pdt) | IcalDateTime::Utc(pdt) | IcalDateTime::Zoned(pdt, _) => {
+                *pdt
+            }
+        }
+    }
+
+    /// Calendar date of this value in its own zone.
+    pub fn date(&self) -> Date {
+        self.local().date()
+    }
+
+    /// Resolves the value to an instant; floating values and dates are read
+    /// in `default_tz`.
+    pub fn resolve(&self, default_tz: &'static Tz) -> Option<OffsetDateTime> {
+        self.local()
+            .assume_timezone(self.tz(default_tz))
+            .take_first()
+    }
+}
+
+/// Parses every value of every `name` property (EXDATE, RDATE) of `evnt`.
+fn parse_date_time_list(
+    evnt: &IcalEvent,
+    name: &str,
+    default_tz: &'static Tz,
+) -> Vec<OffsetDateTime> {
+    evnt.properties
+        .iter()
+        .filter(|p| p.name == name)
+        .flat_map(IcalDateTime::parse_property_list)
+        .filter_map(|v| v.resolve(default_tz))
+        .collect_vec()
+}
+
+/// Reads the VEVENTs of every calendar in `reader`, skipping unreadable ones.
+pub fn parse_events<B: BufRead>(reader: B) -> Vec<IcalEvent> {
+    ical::IcalParser::new(reader)
+        .flat_map(|cr| {
+            let Ok(c) = cr else {
+                return None;
+            };
+
+            Some(c.events)
+        })
+        .collect_vec()
+        .into_iter()
+        .flatten()
+        .collect_vec()
+}
+
+pub fn occurrence_id(uid: &str, recurrence_id: OffsetDateTime) -> String {
+    let recurrence_id = recurrence_id
+        .to_timezone(timezones::db::UTC)
+        .format(format_description!(
+            "[year][month][day]T[hour][minute][second]Z"
+        ))
+        .unwrap_or_default();
+
+    format!("{uid}/{recurrence_id}")
+}
+
+/// Parses an RFC 5545 `DURATION` value such as `P1D`, `PT1H30M` or `P2W`.
+pub fn parse_duration(val: &str) -> Option<Duration> {
+    let val = val.trim();
+    let (sign, val) = match val.strip_prefix('-') {
+        Some(v) => (-1, v),
+        None => (1, val.strip_prefix('+').unwrap_or(val)),
+    };
+    let val = val.strip_prefix(['P', 'p'])?;
+    let mut res = Duration::ZERO;
+    let mut num = String::new();
+    let mut is_time = false;
+
+    for c in val.chars() {
+        match c.to_ascii_uppercase() {
+            '0'..='9' => num.push(c),
+            'T' => is_time = true,
+            unit => {
+                let n = num.parse::<i64>().ok()?;
+                num.clear();
+                res += match (unit, is_time) {
+                    ('W', false) => Duration::weeks(n),
+                    ('D', false) => Duration::days(n),
+                    ('H', true) => Duration::hours(n),
+                    ('M', true) => Duration::minutes(n),
+                    ('S', true) => Duration::seconds(n),
+                    _ => return None,
+                };
+            }
+        }
+    }
+
+    if !num.is_empty() {
+        return None;
+    }
+
+    Some(res * sign)
+}
+
+struct ParsedEvent {
+    uid: String,
+    summary: String,
+    dtstart: OffsetDateTime,
+    tz: &'static Tz,
+    all_day: bool,
+    duration: Duration,
+    recurrence_id: Option<OffsetDateTime>,
+    rrule: Option<RecurrenceRule>,
+    rdates: Vec<OffsetDateTime>,
+    exdates: Vec<OffsetDateTime>,
+    cancelled: bool,
+}
+
+fn parse_event(evnt: &IcalEvent, default_tz: &'static Tz) -> Option<ParsedEvent> {
+    let (Some(dtstart), Some(summary), Some(uid)) = (
+        evnt.get_property("DTSTART")
+            .and_then(IcalDateTime::parse_property),
+        evnt.get_property("SUMMARY")
+            .and_then(|p| p.value.to_owned()),
+        evnt.get_property("UID").and_then(|p| p.value.to_owned()),
+    ) else {
+        return None;
+    };
+
+    let recurrence_id = evnt
+        .get_property("RECURRENCE-ID")
+        .and_then(IcalDateTime::parse_property)
+        .and_then(|v| v.resolve(default_tz));
+    let rrule = evnt
+        .get_property("RRULE")
+        .and_then(|p| p.value.as_deref())
+        .and_then(|v| match v.parse::<RecurrenceRule>() {
+            Ok(r) => Some(r),
+            Err(e) => {
+                tracing::warn!("Ignoring RRULE of {}: {}", uid, e);
+                None
+            }
+        });
+    let cancelled = evnt
+        .get_property("STATUS")
+        .and_then(|p| p.value.as_deref())
+        .is_some_and(|s| s.eq_ignore_ascii_case("CANCELLED"));
+    let dtstart_odt = dtstart.resolve(default_tz)?;
+    // Without DTEND or DURATION, dates last a day and date-times are instants
+    let duration = evnt
+        .get_property("DTEND")
+        .and_then(IcalDateTime::parse_property)
+        .and_then(|v| v.resolve(default_tz))
+        .map(|dtend| dtend - dtstart_odt)
+        .or_else(|| {
+            evnt.get_property("DURATION")
+                .and_then(|p| p.value.as_deref())
+                .and_then(parse_duration)
+        })
+        .unwrap_or(match dtstart.is_all_day() {
+            true => Duration::DAY,
+            false => Duration::ZERO,
+        })
+        .max(Duration::ZERO);
+
+    Some(ParsedEvent {
+        rdates: parse_date_time_list(evnt, "RDATE", default_tz),
+        exdates: parse_date_time_list(evnt, "EXDATE", default_tz),
+        uid,
+        summary,
+        dtstart: dtstart_odt,
+        tz: dtstart.tz(default_tz),
+        all_day: dtstart.is_all_day(),
+        duration,
+        recurrence_id,
+        rrule,
+        cancelled,
+    })
+}
+
+impl ParsedEvent {
+    fn occurrence(&self, id: String, start: OffsetDateTime) -> EventOccurrence {
+        EventOccurrence {
+            id,
+            uid: self.uid.clone(),
+            start,
+            end: start + self.duration,
+            all_day: self.all_day,
+            summary: self.summary.clone(),
+        }
+    }
+}
+
+/// Expands VEVENTs into the occurrences overlapping `[start, end)`.
+///
+/// `RRULE` and `RDATE` add occurrences, `EXDATE` removes them, and VEVENTs
+/// carrying a `RECURRENCE-ID` replace (or cancel) the matching occurrence.
+/// Floating values and dates are read in `default_tz`.
+pub fn expand_events(
+    events: Vec<IcalEvent>,
+    default_tz: &'static Tz,
+    start: OffsetDateTime,
+    end: OffsetDateTime,
+) -> Vec<EventOccurrence> {
+    let (overrides, masters): (Vec<_>, Vec<_>) = events
+        .iter()
+        .filter_map(|evnt| parse_event(evnt, default_tz))
+        .partition(|evnt| evnt.recurrence_id.is_some());
+    let mut overrides = overrides
+        .into_iter()
+        .filter_map(|evnt| Some((occurrence_id(&evnt.uid, evnt.recurrence_id?), evnt)))
+        .collect::<HashMap<_, _>>();
+    let in_window =
+        |t: &OffsetDateTime, duration: Duration| *t < end && (*t + duration > start || *t >= start);
+    let mut res = Vec::new();
+
+    for master in masters {
+        let mut instants = match master.rrule.as_ref() {
+            None => vec![master.dtstart],
+            Some(rule) => {
+                let local = |t: OffsetDateTime| {
+                    let t = t.to_timezone(master.tz);
+                    PrimitiveDateTime::new(t.date(), t.time())
+                };
+                // A date-only UNTIL includes the whole day
+                let until = rule
+                    .until
+                    .as_deref()
+                    .and_then(|u| IcalDateTime::parse_value(u, false, None))
+                    .and_then(|u| match u {
+                        IcalDateTime::Date(d) => d
+                            .with_hms(23, 59, 59)
+                            .ok()
+                            .and_then(|pdt| pdt.assume_timezone(master.tz).take_first()),
+                        u => u.resolve(master.tz),
+                    })
+                    .map(local);
+
+                rule.occurrences(local(master.dtstart), until, local(end))
+                    .into_iter()
+                    .filter_map(|pdt| pdt.assume_timezone(master.tz).take_first())
+                    .collect_vec()
+            }
+        };
+
+        instants.extend(master.rdates.iter().copied());
+        instants.retain(|t| !master.exdates.contains(t));
+
+        for instant in instants.into_iter().sorted().dedup() {
+            let id = occurrence_id(&master.uid, instant);
+
+            match overrides.remove(&id) {
+                Some(ovr) => {
+                    if !ovr.cancelled && in_window(&ovr.dtstart, ovr.duration) {
+                        res.push(ovr.occurrence(id, ovr.dtstart));
+                    }
+                }
+                None => {
+                    if !master.cancelled && in_window(&instant, master.duration) {
+                        res.push(master.occurrence(id, instant));
+                    }
+                }
+            }
+        }
+    }
+
+    // Overrides of occurrences outside the expanded range, moved into the window
+    overrides.into_iter().for_each(|(id, ovr)| {
+        if !ovr.cancelled && in_window(&ovr.dtstart, ovr.duration) {
+            res.push(ovr.occurrence(id, ovr.dtstart));
+        }
+    });
+
+    res
+}
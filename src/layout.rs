@@ -0,0 +1,678 @@
+//! A small box layout for the e-paper page: rows and columns of boxes with
+//! padding, alignment and overflow clipping, filled in by widgets.
+use std::{collections::BTreeMap, fmt};
+
+use image::{GrayImage, Luma, Rgb, RgbImage};
+use imageproc::{
+    drawing::{self, Canvas},
+    rect::Rect,
+};
+use serde::{Deserialize, Deserializer, de};
+
+use crate::widgets::WidgetKind;
+
+/// A rectangle in page pixels.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Area {
+    pub x: u32,
+    pub y: u32,
+    pub w: u32,
+    pub h: u32,
+}
+
+impl Area {
+    pub fn new(x: u32, y: u32, w: u32, h: u32) -> Self {
+        Self { x, y, w, h }
+    }
+
+    pub fn right(&self) -> u32 {
+        self.x + self.w
+    }
+
+    pub fn bottom(&self) -> u32 {
+        self.y + self.h
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.w == 0 || self.h == 0
+    }
+
+    pub fn contains(&self, x: u32, y: u32) -> bool {
+        (self.x..self.right()).contains(&x) && (self.y..self.bottom()).contains(&y)
+    }
+
+    /// The area left inside `padding`, empty when the padding does not fit.
+    pub fn inset(&self, padding: &Padding) -> Self {
+        Self {
+            x: self.x + padding.left,
+            y: self.y + padding.top,
+            w: self.w.saturating_sub(padding.horizontal()),
+            h: self.h.saturating_sub(padding.vertical()),
+        }
+    }
+
+    pub fn intersect(&self, other: &Self) -> Self {
+        let x = self.x.max(other.x);
+        let y = self.y.max(other.y);
+
+        Self {
+            x,
+            y,
+            w: self.right().min(other.right()).saturating_sub(x),
+            h: self.bottom().min(other.bottom()).saturating_sub(y),
+        }
+    }
+
+    /// The same area for imageproc, which does not take empty rectangles.
+    pub fn rect(&self) -> Option<Rect> {
+        (!self.is_empty()).then(|| Rect::at(self.x as i32, self.y as i32).of_size(self.w, self.h))
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Padding {
+    pub top: u32,
+    pub right: u32,
+    pub bottom: u32,
+    pub left: u32,
+}
+
+impl Padding {
+    pub fn all(px: u32) -> Self {
+        Self {
+            top: px,
+            right: px,
+            bottom: px,
+            left: px,
+        }
+    }
+
+    pub fn horizontal(&self) -> u32 {
+        self.left + self.right
+    }
+
+    pub fn vertical(&self) -> u32 {
+        self.top + self.bottom
+    }
+
+    fn scaled(&self, scale: Scale) -> Self {
+        Self {
+            top: scale.px(self.top),
+            right: scale.px(self.right),
+            bottom: scale.px(self.bottom),
+            left: scale.px(self.left),
+        }
+    }
+}
+
+/// Factor from the pixels a layout is written in to the pixels of the panel
+/// it is drawn on.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Scale(pub f32);
+
+impl Default for Scale {
+    fn default() -> Self {
+        Self(1.0)
+    }
+}
+
+impl Scale {
+    /// `px` layout pixels in panel pixels.
+    pub fn px(&self, px: u32) -> u32 {
+        (px as f32 * self.0).round() as u32
+    }
+}
+
+/// Width or height of a box, padding included. Written as pixels, `"fill"`
+/// or `"auto"` in a layout file.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Size {
+    Fixed(u32),
+    /// An equal share of the room the other boxes leave along the parent's
+    /// axis, or all of it across.
+    #[default]
+    Fill,
+    /// What the content asks for, see [`Widget::measure`].
+    Auto,
+}
+
+impl<'de> Deserialize<'de> for Size {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct SizeVisitor;
+
+        impl de::Visitor<'_> for SizeVisitor {
+            type Value = Size;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a size in pixels, \"fill\" or \"auto\"")
+            }
+
+            fn visit_u64<E: de::Error>(self, v: u64) -> Result<Size, E> {
+                u32::try_from(v)
+                    .map(Size::Fixed)
+                    .map_err(|_| E::invalid_value(de::Unexpected::Unsigned(v), &self))
+            }
+
+            fn visit_i64<E: de::Error>(self, v: i64) -> Result<Size, E> {
+                u32::try_from(v)
+                    .map(Size::Fixed)
+                    .map_err(|_| E::invalid_value(de::Unexpected::Signed(v), &self))
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<Size, E> {
+                match v {
+                    "fill" => Ok(Size::Fill),
+                    "auto" => Ok(Size::Auto),
+                    _ => Err(E::invalid_value(de::Unexpected::Str(v), &self)),
+                }
+            }
+        }
+
+        deserializer.deserialize_any(SizeVisitor)
+    }
+}
+
+impl Size {
+    fn scaled(&self, scale: Scale) -> Self {
+        match self {
+            Self::Fixed(px) => Self::Fixed(scale.px(*px)),
+            size => *size,
+        }
+    }
+}
+
+/// Where children smaller than their parent go across its axis.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Align {
+    #[default]
+    Start,
+    Center,
+    End,
+}
+
+impl Align {
+    /// Offset of `size` within `room`.
+    pub fn offset(&self, room: u32, size: u32) -> u32 {
+        match self {
+            Self::Start => 0,
+            Self::Center => room.saturating_sub(size) / 2,
+            Self::End => room.saturating_sub(size),
+        }
+    }
+}
+
+/// Whether a box cuts what its content draws outside of it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Overflow {
+    #[default]
+    Visible,
+    Clip,
+}
+
+/// The colours a page is drawn with, and the inks of a panel palette. Each
+/// ink goes to its own layer of the panel, other colours are drawn as a mix
+/// of the nearest inks.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Color {
+    Black,
+    White,
+    Red,
+    Gray,
+    Yellow,
+    Green,
+    Blue,
+    Orange,
+}
+
+impl Color {
+    pub fn rgb(&self) -> Rgb<u8> {
+        match self {
+            Self::Black => Rgb([0, 0, 0]),
+            Self::White => Rgb([255, 255, 255]),
+            Self::Red => Rgb([255, 0, 0]),
+            Self::Gray => Rgb([137, 136, 136]),
+            Self::Yellow => Rgb([255, 255, 0]),
+            Self::Green => Rgb([0, 255, 0]),
+            Self::Blue => Rgb([0, 0, 255]),
+            Self::Orange => Rgb([255, 128, 0]),
+        }
+    }
+}
+
+impl fmt::Display for Color {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Self::Black => "black",
+            Self::White => "white",
+            Self::Red => "red",
+            Self::Gray => "gray",
+            Self::Yellow => "yellow",
+            Self::Green => "green",
+            Self::Blue => "blue",
+            Self::Orange => "orange",
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum NodeKind {
+    /// Children side by side, from the left.
+    Row(Vec<Node>),
+    /// Children stacked, from the top.
+    Column(Vec<Node>),
+    Widget(WidgetKind),
+}
+
+/// A box of the layout tree. In a layout file, a table with exactly one of
+/// `row`, `column` or `widget` and the optional fields below.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(try_from = "NodeFile")]
+pub struct Node {
+    pub kind: NodeKind,
+    pub width: Size,
+    pub height: Size,
+    pub padding: Padding,
+    /// Alignment of the children across the axis of a row or column.
+    pub align: Align,
+    /// Filled over the whole box, padding included, before the content.
+    pub background: Option<Color>,
+    /// Clipping is at the box edge, padding included.
+    pub overflow: Overflow,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct NodeFile {
+    row: Option<Vec<Node>>,
+    column: Option<Vec<Node>>,
+    widget: Option<WidgetKind>,
+    #[serde(default)]
+    width: Size,
+    #[serde(default)]
+    height: Size,
+    #[serde(default)]
+    padding: Padding,
+    #[serde(default)]
+    align: Align,
+    background: Option<Color>,
+    #[serde(default)]
+    overflow: Overflow,
+}
+
+impl TryFrom<NodeFile> for Node {
+    type Error = &'static str;
+
+    fn try_from(file: NodeFile) -> Result<Self, Self::Error> {
+        let kind = match (file.row, file.column, file.widget) {
+            (Some(children), None, None) => NodeKind::Row(children),
+            (None, Some(children), None) => NodeKind::Column(children),
+            (None, None, Some(kind)) => NodeKind::Widget(kind),
+            _ => return Err("a box needs exactly one of `row`, `column` or `widget`"),
+        };
+
+        Ok(Self {
+            kind,
+            width: file.width,
+            height: file.height,
+            padding: file.padding,
+            align: file.align,
+            background: file.background,
+            overflow: file.overflow,
+        })
+    }
+}
+
+/// Something drawn in a box of the layout.
+pub trait Widget {
+    /// Content size wanted within `width` × `height`, for boxes sized
+    /// [`Size::Auto`], in pixels already multiplied by `scale`. Takes all of
+    /// it by default.
+    fn measure(&self, width: u32, height: u32, _scale: Scale) -> (u32, u32) {
+        (width, height)
+    }
+
+    /// Draws within `area`, the box without its padding.
+    fn draw(&self, surface: &mut Surface<'_>, area: Area);
+}
+
+/// The widgets of a page, by the kind the layout refers to them with.
+pub type WidgetMap<'a> = BTreeMap<WidgetKind, Box<dyn Widget + Send + Sync + 'a>>;
+
+/// An image that only takes the pixels drawn within `clip`. It keeps track
+/// of which pixels are fills and images, to be dithered, and which are text
+/// and lines, to stay crisp.
+pub struct Surface<'a> {
+    image: &'a mut RgbImage,
+    clip: Area,
+    scale: Scale,
+    mask: GrayImage,
+    dithered: bool,
+}
+
+impl<'a> Surface<'a> {
+    pub fn new(image: &'a mut RgbImage, scale: Scale) -> Self {
+        let (w, h) = image.dimensions();
+
+        Self {
+            image,
+            clip: Area::new(0, 0, w, h),
+            scale,
+            mask: GrayImage::new(w, h),
+            dithered: false,
+        }
+    }
+
+    /// 255 where a fill or an image was drawn last, 0 where text or a line was.
+    pub fn into_mask(self) -> GrayImage {
+        self.mask
+    }
+
+    pub fn clip(&self) -> Area {
+        self.clip
+    }
+
+    /// What widgets multiply their sizes and offsets by.
+    pub fn scale(&self) -> Scale {
+        self.scale
+    }
+
+    /// Fills `area`, if it is not empty.
+    pub fn fill(&mut self, area: Area, color: Rgb<u8>) {
+        if let Some(rect) = area.rect() {
+            self.dithered = true;
+            drawing::draw_filled_rect_mut(self, rect, color);
+            self.dithered = false;
+        }
+    }
+
+    /// Draws `picture` with its top left corner at `x`, `y`.
+    pub fn image(&mut self, x: u32, y: u32, picture: &RgbImage) {
+        self.dithered = true;
+        picture
+            .enumerate_pixels()
+            .for_each(|(px, py, p)| self.draw_pixel(x + px, y + py, *p));
+        self.dithered = false;
+    }
+}
+
+impl Canvas for Surface<'_> {
+    type Pixel = Rgb<u8>;
+
+    fn dimensions(&self) -> (u32, u32) {
+        self.image.dimensions()
+    }
+
+    fn get_pixel(&self, x: u32, y: u32) -> Self::Pixel {
+        *self.image.get_pixel(x, y)
+    }
+
+    fn draw_pixel(&mut self, x: u32, y: u32, color: Self::Pixel) {
+        if self.clip.contains(x, y) {
+            self.image.put_pixel(x, y, color);
+            self.mask
+                .put_pixel(x, y, Luma([if self.dithered { 255 } else { 0 }]));
+        }
+    }
+}
+
+impl Node {
+    fn new(kind: NodeKind) -> Self {
+        Self {
+            kind,
+            width: Size::Fill,
+            height: Size::Fill,
+            padding: Padding::default(),
+            align: Align::Start,
+            background: None,
+            overflow: Overflow::Visible,
+        }
+    }
+
+    pub fn row(children: Vec<Node>) -> Self {
+        Self::new(NodeKind::Row(children))
+    }
+
+    pub fn column(children: Vec<Node>) -> Self {
+        Self::new(NodeKind::Column(children))
+    }
+
+    pub fn widget(kind: WidgetKind) -> Self {
+        Self::new(NodeKind::Widget(kind))
+    }
+
+    pub fn width(self, width: Size) -> Self {
+        Self { width, ..self }
+    }
+
+    pub fn height(self, height: Size) -> Self {
+        Self { height, ..self }
+    }
+
+    pub fn padding(self, padding: Padding) -> Self {
+        Self { padding, ..self }
+    }
+
+    pub fn align(self, align: Align) -> Self {
+        Self { align, ..self }
+    }
+
+    pub fn background(self, background: Color) -> Self {
+        Self {
+            background: Some(background),
+            ..self
+        }
+    }
+
+    pub fn overflow(self, overflow: Overflow) -> Self {
+        Self { overflow, ..self }
+    }
+
+    /// The same tree with its sizes and padding multiplied by `scale`.
+    pub fn scaled(&self, scale: Scale) -> Self {
+        let children = |children: &[Node]| children.iter().map(|c| c.scaled(scale)).collect();
+
+        Self {
+            kind: match &self.kind {
+                NodeKind::Row(c) => NodeKind::Row(children(c)),
+                NodeKind::Column(c) => NodeKind::Column(children(c)),
+                NodeKind::Widget(kind) => NodeKind::Widget(*kind),
+            },
+            width: self.width.scaled(scale),
+            height: self.height.scaled(scale),
+            padding: self.padding.scaled(scale),
+            ..*self
+        }
+    }
+
+    /// The same tree with every background replaced by `ink(background)`.
+    pub fn recolored(&self, ink: &impl Fn(Color) -> Color) -> Self {
+        let children = |children: &[Node]| children.iter().map(|c| c.recolored(ink)).collect();
+
+        Self {
+            kind: match &self.kind {
+                NodeKind::Row(c) => NodeKind::Row(children(c)),
+                NodeKind::Column(c) => NodeKind::Column(children(c)),
+                NodeKind::Widget(kind) => NodeKind::Widget(*kind),
+            },
+            background: self.background.map(ink),
+            ..*self
+        }
+    }
+
+    /// Size of the box within `width` × `height`, padding included.
+    fn measure(
+        &self,
+        width: u32,
+        height: u32,
+        widgets: &WidgetMap<'_>,
+        scale: Scale,
+    ) -> (u32, u32) {
+        let inner_w = width.saturating_sub(self.padding.horizontal());
+        let inner_h = height.saturating_sub(self.padding.vertical());
+        let (content_w, content_h) = match &self.kind {
+            NodeKind::Widget(kind) => widgets
+                .get(kind)
+                .map(|w| w.measure(inner_w, inner_h, scale))
+                .unwrap_or_default(),
+            NodeKind::Row(children) => children
+                .iter()
+                .map(|c| c.measure(inner_w, inner_h, widgets, scale))
+                .fold((0, 0), |(w, h), (c_w, c_h)| (w + c_w, h.max(c_h))),
+            NodeKind::Column(children) => children
+                .iter()
+                .map(|c| c.measure(inner_w, inner_h, widgets, scale))
+                .fold((0, 0), |(w, h), (c_w, c_h)| (w.max(c_w), h + c_h)),
+        };
+        let size = |size: Size, room: u32, content: u32| match size {
+            Size::Fixed(px) => px.min(room),
+            Size::Fill => room,
+            Size::Auto => content.min(room),
+        };
+
+        (
+            size(self.width, width, content_w + self.padding.horizontal()),
+            size(self.height, height, content_h + self.padding.vertical()),
+        )
+    }
+
+    /// Boxes of `children` along the axis of a row or column filling `area`.
+    fn arrange(
+        &self,
+        children: &[Node],
+        area: Area,
+        horizontal: bool,
+        widgets: &WidgetMap<'_>,
+        scale: Scale,
+    ) -> Vec<Area> {
+        let (length, across) = match horizontal {
+            true => (area.w, area.h),
+            false => (area.h, area.w),
+        };
+        let sizes = children
+            .iter()
+            .map(|c| {
+                let (w, h) = c.measure(area.w, area.h, widgets, scale);
+                match horizontal {
+                    true => (c.width, w, h),
+                    false => (c.height, h, w),
+                }
+            })
+            .collect::<Vec<_>>();
+        let fills = sizes.iter().filter(|(s, ..)| *s == Size::Fill).count() as u32;
+        let taken = sizes
+            .iter()
+            .filter(|(s, ..)| *s != Size::Fill)
+            .map(|(_, main, _)| main)
+            .sum::<u32>();
+        let free = length.saturating_sub(taken);
+        let mut fill_idx = 0;
+        let mut pos = 0;
+
+        sizes
+            .into_iter()
+            .map(|(size, main, cross)| {
+                // The last fill takes what equal shares leave over
+                let main = match size {
+                    Size::Fill => {
+                        fill_idx += 1;
+                        match fill_idx == fills {
+                            true => free - (free / fills) * (fills - 1),
+                            false => free / fills,
+                        }
+                    }
+                    _ => main,
+                }
+                .min(length.saturating_sub(pos));
+                let offset = self.align.offset(across, cross);
+                let child = match horizontal {
+                    true => Area::new(area.x + pos, area.y + offset, main, cross),
+                    false => Area::new(area.x + offset, area.y + pos, cross, main),
+                };
+                pos += main;
+
+                child
+            })
+            .collect()
+    }
+
+    /// Visits the boxes in drawing order, with the clip each one is drawn in.
+    fn walk(
+        &self,
+        area: Area,
+        clip: Area,
+        widgets: &WidgetMap<'_>,
+        scale: Scale,
+        f: &mut impl FnMut(&Node, Area, Area),
+    ) {
+        if area.is_empty() {
+            return;
+        }
+
+        let clip = match self.overflow {
+            Overflow::Visible => clip,
+            Overflow::Clip => clip.intersect(&area),
+        };
+        f(self, area, clip);
+
+        let inner = area.inset(&self.padding);
+        let (children, horizontal) = match &self.kind {
+            NodeKind::Row(children) => (children, true),
+            NodeKind::Column(children) => (children, false),
+            NodeKind::Widget(_) => return,
+        };
+
+        children
+            .iter()
+            .zip(self.arrange(children, inner, horizontal, widgets, scale))
+            .for_each(|(child, child_area)| child.walk(child_area, clip, widgets, scale, f));
+    }
+
+    /// The content area of every widget box within `area`, in drawing order,
+    /// for the tree multiplied by `scale`.
+    pub fn place(
+        &self,
+        area: Area,
+        widgets: &WidgetMap<'_>,
+        scale: Scale,
+    ) -> Vec<(WidgetKind, Area)> {
+        let mut placed = Vec::new();
+
+        self.scaled(scale)
+            .walk(area, area, widgets, scale, &mut |node, area, _clip| {
+                if let NodeKind::Widget(kind) = node.kind {
+                    placed.push((kind, area.inset(&node.padding)));
+                }
+            });
+
+        placed
+    }
+
+    /// Draws the tree multiplied by `scale` over the whole image, and returns
+    /// the pixels to dither, see [`Surface::into_mask`].
+    pub fn draw(&self, image: &mut RgbImage, widgets: &WidgetMap<'_>, scale: Scale) -> GrayImage {
+        let mut surface = Surface::new(image, scale);
+        let page = surface.clip();
+
+        self.scaled(scale)
+            .walk(page, page, widgets, scale, &mut |node, area, clip| {
+                surface.clip = clip;
+
+                if let Some(background) = node.background {
+                    surface.fill(area, background.rgb());
+                }
+                if let NodeKind::Widget(kind) = node.kind {
+                    let content = area.inset(&node.padding);
+
+                    if let Some(widget) = widgets.get(&kind).filter(|_| !content.is_empty()) {
+                        widget.draw(&mut surface, content);
+                    }
+                }
+            });
+
+        surface.into_mask()
+    }
+}
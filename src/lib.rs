@@ -3,17 +3,32 @@ use std::sync::Arc;
 use axum::Router;
 
 pub mod api_error;
+pub mod caldav;
 pub mod cfg;
 pub mod cron;
 pub mod db;
+pub mod dither;
+pub mod epd;
+pub mod ha;
+pub mod http;
+pub mod ics;
+pub mod layout;
 pub mod middleware;
 pub mod model;
+pub mod page;
+pub mod panel;
+pub mod retry;
 pub mod routes;
 pub mod telemetry;
+pub mod widgets;
 
 pub use cfg::*;
 pub use db::*;
-use model::{CalendarMap, WeatherInfoArc};
+use model::{
+    CalendarMap, FeedCacheArc, Generation, RefreshedArc, RenderCacheArc, ScheduleMapArc, SensorMapArc,
+    SharedState, SourceStatusArc, TodoMapArc, WeatherForecastArc, WeatherInfoArc,
+};
+use page::PageConfigArc;
 use time::PrimitiveDateTime;
 use time_tz::{Tz, timezones};
 use tokio::sync::RwLock;
@@ -25,24 +40,73 @@ pub struct AppState {
     pub tz: &'static Tz,
     pub calendar: Arc<RwLock<CalendarMap>>,
     pub weather: WeatherInfoArc,
+    pub forecast: WeatherForecastArc,
+    pub sensors: SensorMapArc,
+    pub todos: TodoMapArc,
+    pub feeds: FeedCacheArc,
+    pub status: SourceStatusArc,
+    pub refreshed: RefreshedArc,
+    pub schedules: ScheduleMapArc,
     pub last_update: Arc<RwLock<PrimitiveDateTime>>,
+    pub generation: Generation,
+    pub page: PageConfigArc,
+    /// Rendered pages, only used by the page route.
+    pub render_cache: RenderCacheArc,
 }
 
-pub fn router(
-    cfg: Config,
-    db: Db,
-    calendar: Arc<RwLock<CalendarMap>>,
-    weather: WeatherInfoArc,
-    last_update: Arc<RwLock<PrimitiveDateTime>>,
-) -> Router {
+impl AppState {
+    /// The state shared with the cron jobs, for fetching from a route.
+    pub fn shared(&self) -> SharedState {
+        SharedState {
+            calendar: self.calendar.clone(),
+            weather: self.weather.clone(),
+            forecast: self.forecast.clone(),
+            sensors: self.sensors.clone(),
+            todos: self.todos.clone(),
+            feeds: self.feeds.clone(),
+            status: self.status.clone(),
+            refreshed: self.refreshed.clone(),
+            schedules: self.schedules.clone(),
+            last_update: self.last_update.clone(),
+            generation: self.generation.clone(),
+            page: self.page.clone(),
+        }
+    }
+}
+
+pub fn router(cfg: Config, db: Db, shared: SharedState) -> Router {
     let tz = timezones::get_by_name(&cfg.tz).unwrap_or(timezones::db::UTC);
+    let SharedState {
+        calendar,
+        weather,
+        forecast,
+        sensors,
+        todos,
+        feeds,
+        status,
+        refreshed,
+        schedules,
+        last_update,
+        generation,
+        page,
+    } = shared;
     let app_state = AppState {
         db,
         cfg,
         tz,
         calendar,
         weather,
+        forecast,
+        sensors,
+        todos,
+        feeds,
+        status,
+        refreshed,
+        schedules,
         last_update,
+        generation,
+        page,
+        render_cache: Default::default(),
     };
 
     // Middleware that adds high level tracing to a Service.
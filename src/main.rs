@@ -1,7 +1,13 @@
-use std::sync::Arc;
+use std::{collections::HashMap, sync::Arc};
 
-use server::{Configuration, Db, cron, model::CalendarMap, telemetry};
+use server::{
+    Configuration, Db, cron, ha,
+    model::{CalendarMap, SharedState},
+    page::{self, PageConfig},
+    telemetry,
+};
 use time::{OffsetDateTime, PrimitiveDateTime};
+use time_tz::timezones;
 use tokio::{net::TcpListener, sync::RwLock};
 
 #[tokio::main]
@@ -18,6 +24,14 @@ async fn main() {
     tracing::debug!("Initializing configuration");
     let cfg = Configuration::new();
 
+    // Validate the layout file before anything else runs.
+    let page_cfg = match &cfg.layout_file {
+        Some(path) => PageConfig::load(path).expect(
+            "Unable to load the layout file. Please make sure LAYOUT_FILE points to a valid TOML, YAML or JSON layout.",
+        ),
+        None => PageConfig::default(),
+    };
+
     // Initialize db pool.
     tracing::debug!("Initializing db pool");
     let db = Db::new(&cfg.db_dsn, cfg.db_pool_max_size)
@@ -27,27 +41,61 @@ async fn main() {
     tracing::debug!("Running migrations");
     db.migrate().await.expect("Failed to run migrations");
 
-    // Initialize calendar state
-    let calendar = Arc::new(RwLock::new(CalendarMap::new()));
-    let weather = Arc::new(RwLock::new(Default::default()));
-    let now_odt = OffsetDateTime::now_utc();
-    let last_update = Arc::new(RwLock::new(PrimitiveDateTime::new(
-        now_odt.date(),
-        now_odt.time(),
+    // Initialize calendar state from the last stored one
+    tracing::debug!("Restoring state");
+    let tz = timezones::get_by_name(&cfg.tz).unwrap_or(timezones::db::UTC);
+    let calendar = Arc::new(RwLock::new(db.load_calendar(tz).await.unwrap_or_else(
+        |e| {
+            tracing::warn!("Unable to restore calendar: {:?}", e);
+            CalendarMap::new()
+        },
     )));
+    let weather = Arc::new(RwLock::new(db.load_weather().await.unwrap_or_else(|e| {
+        tracing::warn!("Unable to restore weather: {:?}", e);
+        None
+    })));
+    let forecast = Arc::new(RwLock::new(
+        db.load_forecast()
+            .await
+            .unwrap_or_else(|e| {
+                tracing::warn!("Unable to restore forecast: {:?}", e);
+                None
+            })
+            .unwrap_or_default(),
+    ));
+    let last_update = Arc::new(RwLock::new(
+        db.load_last_update()
+            .await
+            .unwrap_or_else(|e| {
+                tracing::warn!("Unable to restore last update: {:?}", e);
+                None
+            })
+            .unwrap_or_else(|| {
+                let now_odt = OffsetDateTime::now_utc();
+                PrimitiveDateTime::new(now_odt.date(), now_odt.time())
+            }),
+    ));
 
     // Spin up our server.
     tracing::info!("Starting server on {}", cfg.listen_address);
     let listener = TcpListener::bind(&cfg.listen_address)
         .await
         .expect("Failed to bind address");
-    let router = server::router(
-        cfg.clone(),
-        db,
-        calendar.clone(),
-        weather.clone(),
-        last_update.clone(),
-    );
+    let shared = SharedState {
+        calendar,
+        weather,
+        forecast,
+        sensors: Arc::new(RwLock::new(HashMap::new())),
+        todos: Arc::new(RwLock::new(HashMap::new())),
+        feeds: Arc::new(RwLock::new(HashMap::new())),
+        status: Default::default(),
+        refreshed: Default::default(),
+        schedules: Default::default(),
+        last_update,
+        generation: Default::default(),
+        page: Arc::new(RwLock::new(Arc::new(page_cfg))),
+    };
+    let router = server::router(cfg.clone(), db.clone(), shared.clone());
     let http_task = async {
         axum::serve(listener, router)
             .await
@@ -55,17 +103,26 @@ async fn main() {
     };
 
     // Spin up cron
-    let cron = cron::setup(
-        cfg.clone(),
-        calendar.clone(),
-        weather.clone(),
-        last_update.clone(),
-    )
-    .await
-    .expect("Failed to start Cron");
+    let cron = cron::setup(cfg.clone(), db.clone(), shared.clone())
+        .await
+        .expect("Failed to start Cron");
     let cron_task = async {
         cron.start().await.expect("Failed to run Cron");
     };
 
-    let _res = tokio::join!(http_task, cron_task);
+    // Follow Home Assistant live
+    let ha_task = async {
+        if cfg.ha_websocket {
+            ha::run(cfg.clone(), db.clone(), shared.clone()).await;
+        }
+    };
+
+    // Pick up layout changes
+    let page_task = async {
+        if let Some(path) = cfg.layout_file.clone() {
+            page::watch(path, shared.page.clone()).await;
+        }
+    };
+
+    let _res = tokio::join!(http_task, cron_task, ha_task, page_task);
 }
@@ -72,27 +72,37 @@ pub fn normalize_path_layer() -> NormalizePathLayer {
     NormalizePathLayer::trim_trailing_slash()
 }
 
-/// Middleware for authorization check
-pub async fn auth_check_layer(
-    State(state): State<AppState>,
-    req: Request,
-    next: Next,
-) -> Result<Response, StatusCode> {
-    let auth_header = req
-        .headers()
+/// Paths served without the access token.
+const PUBLIC_PATHS: [&str; 1] = ["/health_check"];
+
+/// Whether the request carries the access token, in the `Authorization` header or the
+/// `token` query parameter.
+pub fn is_authorized(state: &AppState, headers: &http::HeaderMap, uri: &http::Uri) -> bool {
+    let auth_header = headers
         .get(http::header::AUTHORIZATION)
         .and_then(|header| header.to_str().ok());
-    let auth_q = Query::<QueryAuthModel>::try_from_uri(req.uri());
+    let auth_q = Query::<QueryAuthModel>::try_from_uri(uri);
 
     let auth_token = if let Some(auth_header) = auth_header {
         auth_header
     } else if let Ok(aq) = auth_q.as_ref() {
         aq.token.as_ref()
     } else {
-        return Err(StatusCode::UNAUTHORIZED);
+        return false;
     };
 
-    if auth_token != state.cfg.access_token {
+    auth_token == state.cfg.access_token
+}
+
+/// Middleware for authorization check
+pub async fn auth_check_layer(
+    State(state): State<AppState>,
+    req: Request,
+    next: Next,
+) -> Result<Response, StatusCode> {
+    let path = req.uri().path().trim_end_matches('/');
+
+    if !PUBLIC_PATHS.contains(&path) && !is_authorized(&state, req.headers(), req.uri()) {
         return Err(StatusCode::UNAUTHORIZED);
     }
 
@@ -1,25 +1,105 @@
 use std::{
-    collections::{BTreeMap, HashMap},
-    sync::Arc,
+    collections::{BTreeMap, BTreeSet, HashMap},
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Instant,
 };
 
+use axum::body::Bytes;
+use ical::parser::ical::component::IcalEvent;
 use serde::{Deserialize, Serialize};
-use time::{Date, OffsetDateTime, PrimitiveDateTime};
-use tokio::sync::RwLock;
+use time::{
+    Date, Duration, OffsetDateTime, PrimitiveDateTime, format_description::well_known::Rfc3339,
+    macros::format_description,
+};
+use time_tz::{OffsetDateTimeExt, Tz};
+use tokio::sync::{Mutex, RwLock};
 
-#[derive(Serialize, Clone, Debug)]
+use uuid::Uuid;
+
+use crate::{
+    CalendarColor, Schedule,
+    dither::Dither,
+    http::Validators,
+    layout::Color,
+    page::{PageConfig, PageConfigArc},
+};
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
 pub struct DateInfoEventMode {
     pub time: OffsetDateTime,
+    /// Exclusive end of the event.
+    pub end: OffsetDateTime,
+    /// The event has a date rather than a start time.
+    pub all_day: bool,
     pub name: String,
+    /// Name of the calendar source the event came from.
+    pub source: String,
+    pub color: CalendarColor,
+    /// Which day of the event this entry is, starting from 1.
+    pub day: u32,
+    /// Number of dates the event covers.
+    pub days: u32,
 }
 
-#[derive(Serialize, Clone, Debug)]
+impl DateInfoEventMode {
+    /// Dates the event covers. Timed events are split on `tz` midnights;
+    /// all-day events keep their own dates.
+    pub fn span_dates(&self, tz: &Tz) -> Vec<Date> {
+        let (first, last) = match self.all_day {
+            true => {
+                // Round to whole days, a DST change makes a day 23 or 25 hours long
+                let days = ((self.end - self.time).whole_hours() + 12) / 24;
+                let first = self.time.date();
+
+                (first, first + Duration::days(days - 1))
+            }
+            false => (
+                self.time.to_timezone(tz).date(),
+                (self.end - Duration::NANOSECOND)
+                    .max(self.time)
+                    .to_timezone(tz)
+                    .date(),
+            ),
+        };
+
+        let mut dates = vec![first];
+        while let Some(next) = dates
+            .last()
+            .and_then(|d| d.next_day())
+            .filter(|d| *d <= last)
+        {
+            dates.push(next);
+        }
+
+        dates
+    }
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct DateInfoHoliday {
+    pub name: String,
+    /// Name of the calendar source the holiday came from.
+    pub source: String,
+    pub color: CalendarColor,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
 pub struct DateInfo {
     pub date: Date,
-    pub holiday: Option<String>,
+    pub holidays: Vec<DateInfoHoliday>,
     pub events: HashMap<String, DateInfoEventMode>,
 }
 
+impl DateInfo {
+    /// Whether a red holiday source marks this date.
+    pub fn is_holiday(&self) -> bool {
+        self.holidays.iter().any(|h| h.color == CalendarColor::Red)
+    }
+}
+
 pub type CalendarMap = BTreeMap<Date, DateInfo>;
 pub type CalendarMapArc = Arc<RwLock<CalendarMap>>;
 
@@ -41,32 +121,213 @@ pub enum WeatherInfoState {
     Sunny,
     Windy,
     WindyVariant,
+    /// Any other state, e.g. `unavailable` or one added by a newer Home Assistant.
+    #[serde(other)]
+    Unknown,
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+/// Weather entity attributes. Integrations only report some of them, so
+/// every attribute is optional.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default)]
 pub struct WeatherInfoAttribute {
-    pub temperature: f32,
-    pub dew_point: f32,
-    pub temperature_unit: String,
-    pub humidity: f32,
-    pub cloud_coverage: f32,
-    pub uv_index: f32,
-    pub pressure: f32,
-    pub wind_bearing: f32,
-    pub wind_speed: f32,
-}
-
-#[derive(Serialize, Deserialize, Clone, Debug)]
+    pub temperature: Option<f32>,
+    pub dew_point: Option<f32>,
+    pub temperature_unit: Option<String>,
+    pub humidity: Option<f32>,
+    pub cloud_coverage: Option<f32>,
+    pub uv_index: Option<f32>,
+    pub pressure: Option<f32>,
+    pub wind_bearing: Option<f32>,
+    pub wind_speed: Option<f32>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct WeatherInfo {
     pub state: WeatherInfoState,
+    #[serde(default)]
     pub attributes: WeatherInfoAttribute,
 }
 
 pub type WeatherInfoArc = Arc<RwLock<Option<WeatherInfo>>>;
 
+/// One entry of a `weather.get_forecasts` response.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct WeatherForecastEntry {
+    #[serde(with = "time::serde::rfc3339::option")]
+    pub datetime: Option<OffsetDateTime>,
+    pub condition: Option<WeatherInfoState>,
+    pub temperature: Option<f32>,
+    pub templow: Option<f32>,
+    pub precipitation_probability: Option<f32>,
+    pub precipitation: Option<f32>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct WeatherForecast {
+    pub daily: Vec<WeatherForecastEntry>,
+    pub hourly: Vec<WeatherForecastEntry>,
+}
+
+pub type WeatherForecastArc = Arc<RwLock<WeatherForecast>>;
+
+/// Start or end of an event from `/api/calendars/<entity_id>`: `date` for
+/// all-day events, `dateTime` otherwise.
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct HaCalendarTime {
+    #[serde(rename = "dateTime", with = "time::serde::rfc3339::option")]
+    pub date_time: Option<OffsetDateTime>,
+    pub date: Option<String>,
+}
+
+/// An event, already expanded, from `/api/calendars/<entity_id>`.
+#[derive(Deserialize, Debug)]
+pub struct HaCalendarEvent {
+    #[serde(default)]
+    pub summary: String,
+    pub start: HaCalendarTime,
+    pub end: HaCalendarTime,
+    #[serde(default)]
+    pub uid: Option<String>,
+}
+
+/// State object of any Home Assistant entity.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct HaEntityState {
+    pub state: String,
+    #[serde(default)]
+    pub attributes: HashMap<String, serde_json::Value>,
+}
+
+impl HaEntityState {
+    /// Renders `fmt`, replacing `{state}`, `{state:.N}` (rounded to N decimals),
+    /// `{unit}` and `{attr:NAME}`. Unknown placeholders are kept as is.
+    pub fn format(&self, fmt: &str) -> String {
+        let mut res = String::new();
+        let mut rest = fmt;
+
+        while let Some(start) = rest.find('{') {
+            let Some(len) = rest[start..].find('}') else {
+                break;
+            };
+            res.push_str(&rest[..start]);
+            let placeholder = &rest[start + 1..start + len];
+            match self.placeholder(placeholder) {
+                Some(value) => res.push_str(&value),
+                None => res.push_str(&rest[start..=start + len]),
+            }
+            rest = &rest[start + len + 1..];
+        }
+        res.push_str(rest);
+
+        res
+    }
+
+    fn placeholder(&self, name: &str) -> Option<String> {
+        let attr = |key: &str| match self.attributes.get(key) {
+            Some(serde_json::Value::String(v)) => v.clone(),
+            Some(serde_json::Value::Null) | None => String::new(),
+            Some(v) => v.to_string(),
+        };
+
+        match name.split_once(':') {
+            None if name == "state" => Some(self.state.clone()),
+            None if name == "unit" => Some(attr("unit_of_measurement")),
+            Some(("attr", key)) => Some(attr(key)),
+            Some(("state", precision)) => {
+                let precision = precision.strip_prefix('.')?.parse::<usize>().ok()?;
+                Some(match self.state.parse::<f64>() {
+                    Ok(v) => format!("{v:.precision$}"),
+                    Err(_) => self.state.clone(),
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum TodoItemStatus {
+    #[default]
+    NeedsAction,
+    Completed,
+}
+
+/// An item of a `todo.get_items` response.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct TodoItem {
+    pub summary: String,
+    #[serde(default)]
+    pub uid: Option<String>,
+    #[serde(default)]
+    pub status: TodoItemStatus,
+    /// Either a date (`2026-05-01`) or a date-time with offset.
+    #[serde(default)]
+    pub due: Option<String>,
+}
+
+/// When a to-do item is due.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TodoDue {
+    Date(Date),
+    DateTime(OffsetDateTime),
+}
+
+impl TodoItem {
+    pub fn due(&self) -> Option<TodoDue> {
+        let due = self.due.as_deref()?;
+
+        OffsetDateTime::parse(due, &Rfc3339)
+            .map(TodoDue::DateTime)
+            .or_else(|_| {
+                Date::parse(due, format_description!("[year]-[month]-[day]")).map(TodoDue::Date)
+            })
+            .ok()
+    }
+
+    /// Whether an open item is past its due date or time as of `now`.
+    pub fn is_overdue(&self, now: OffsetDateTime, tz: &Tz) -> bool {
+        self.status == TodoItemStatus::NeedsAction
+            && match self.due() {
+                Some(TodoDue::Date(d)) => d < now.to_timezone(tz).date(),
+                Some(TodoDue::DateTime(dt)) => dt < now,
+                None => false,
+            }
+    }
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct HaTodoResponse {
+    pub items: Vec<TodoItem>,
+}
+
+/// Open to-do items, by entity id.
+pub type TodoMapArc = Arc<RwLock<HashMap<String, Vec<TodoItem>>>>;
+
+/// Latest state of each configured sensor, by entity id.
+pub type SensorMapArc = Arc<RwLock<HashMap<String, HaEntityState>>>;
+
+/// Response of a Home Assistant service called with `return_response`,
+/// keyed by entity id.
+#[derive(Deserialize, Debug)]
+pub struct HaServiceResponse<T> {
+    pub service_response: HashMap<String, T>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct HaForecastResponse {
+    pub forecast: Vec<WeatherForecastEntry>,
+}
+
 // * Route mode
 
-#[derive(Deserialize, Default, Debug, PartialEq)]
+#[derive(Deserialize, Default, Debug, PartialEq, Eq, Hash, Clone, Copy)]
 #[serde(rename_all = "kebab-case")]
 pub enum QueryRouteEPaperOutputEnum {
     #[default]
@@ -74,14 +335,35 @@ pub enum QueryRouteEPaperOutputEnum {
     Black,
     BlackInvert,
     Red,
+    Yellow,
+    Green,
+    Blue,
+    Orange,
+}
+
+impl QueryRouteEPaperOutputEnum {
+    /// The ink a layer output shows, `None` for the whole page.
+    pub fn ink(&self) -> Option<Color> {
+        match self {
+            Self::Full => None,
+            Self::Black | Self::BlackInvert => Some(Color::Black),
+            Self::Red => Some(Color::Red),
+            Self::Yellow => Some(Color::Yellow),
+            Self::Green => Some(Color::Green),
+            Self::Blue => Some(Color::Blue),
+            Self::Orange => Some(Color::Orange),
+        }
+    }
 }
 
-#[derive(Deserialize, Default, Debug, PartialEq)]
+#[derive(Deserialize, Default, Debug, PartialEq, Eq, Hash, Clone, Copy)]
 #[serde(rename_all = "kebab-case")]
 pub enum QueryRouteEPaperFormatEnum {
     #[default]
     Png,
     Bmp,
+    /// Packed 1-bit planes for the panel controller, see [`crate::epd`].
+    Epd,
 }
 
 #[derive(Deserialize, Default)]
@@ -90,6 +372,220 @@ pub struct QueryRouteEPaperModel {
     pub output: QueryRouteEPaperOutputEnum,
     #[serde(default)]
     pub format: QueryRouteEPaperFormatEnum,
+    /// IANA time zone name overriding `Configuration::tz` for this request.
+    #[serde(default)]
+    pub tz: Option<String>,
+    /// Panel profile to draw for, see `Configuration::panel`.
+    #[serde(default)]
+    pub panel: Option<String>,
+    /// Device id picking its profile from `Configuration::panel_devices`.
+    #[serde(default)]
+    pub device: Option<String>,
+    /// How fills and images are mixed from the inks of the panel.
+    #[serde(default)]
+    pub dither: Dither,
+    /// Compress the planes of the `epd` format.
+    #[serde(default)]
+    pub rle: bool,
 }
 
 pub type LastUpdateArc = Arc<RwLock<PrimitiveDateTime>>;
+
+/// Counts the changes of anything the page draws, including the ones that do
+/// not bump `last_update`, such as weather and sensor attributes.
+#[derive(Clone, Debug, Default)]
+pub struct Generation(Arc<AtomicU64>);
+
+impl Generation {
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Acquire)
+    }
+
+    /// Marks cached renders as outdated, once the new data is stored.
+    pub fn bump(&self) {
+        self.0.fetch_add(1, Ordering::Release);
+    }
+}
+
+/// Variant of the page a device asks for.
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+pub struct RenderKey {
+    pub output: QueryRouteEPaperOutputEnum,
+    pub format: QueryRouteEPaperFormatEnum,
+    pub tz: &'static str,
+    /// Name of the panel profile.
+    pub panel: String,
+    pub dither: Dither,
+    pub rle: bool,
+}
+
+/// Encoded page, valid as long as the data, the stale sources and the layout
+/// are the same, and until the date drawn on it moves on.
+#[derive(Debug, Clone)]
+pub struct RenderedPage {
+    pub body: Bytes,
+    /// Strong validator of `body`.
+    pub etag: String,
+    pub last_update: PrimitiveDateTime,
+    pub generation: u64,
+    pub stale_sources: BTreeSet<String>,
+    /// The layout it was drawn with, until a reload replaces it.
+    pub page: Arc<PageConfig>,
+    /// When the page would be drawn differently from the same data: the next
+    /// date, hourly forecast slot or due to-do.
+    pub valid_until: OffsetDateTime,
+}
+
+impl RenderedPage {
+    pub fn new(body: Bytes, state: RenderState, valid_until: OffsetDateTime) -> Self {
+        let etag = format!("\"{:x}-{:08x}\"", body.len(), crc32fast::hash(&body));
+
+        Self {
+            body,
+            etag,
+            last_update: state.last_update,
+            generation: state.generation,
+            stale_sources: state.stale_sources,
+            page: state.page,
+            valid_until,
+        }
+    }
+
+    /// Whether the page can still be sent for `state` at `now`.
+    pub fn is_valid(&self, state: &RenderState, now: OffsetDateTime) -> bool {
+        self.last_update == state.last_update
+            && self.generation == state.generation
+            && self.stale_sources == state.stale_sources
+            && Arc::ptr_eq(&self.page, &state.page)
+            && now < self.valid_until
+    }
+}
+
+/// What a page is drawn from, besides the data itself.
+#[derive(Debug, Clone)]
+pub struct RenderState {
+    pub last_update: PrimitiveDateTime,
+    pub generation: u64,
+    pub stale_sources: BTreeSet<String>,
+    pub page: Arc<PageConfig>,
+}
+
+/// Rendered pages, by variant.
+pub type RenderCacheArc = Arc<RwLock<HashMap<RenderKey, RenderedPage>>>;
+
+/// Last downloaded state of an `ics` feed, reused when the server answers
+/// `304 Not Modified`.
+#[derive(Clone, Debug, Default)]
+pub struct FeedCache {
+    pub validators: Validators,
+    pub events: Vec<IcalEvent>,
+}
+
+/// Feed caches, by source name.
+pub type FeedCacheArc = Arc<RwLock<HashMap<String, FeedCache>>>;
+
+/// Fetch health of one source.
+#[derive(Serialize, Clone, Debug, Default, PartialEq)]
+pub struct SourceStatus {
+    #[serde(with = "time::serde::rfc3339::option")]
+    pub last_attempt: Option<OffsetDateTime>,
+    #[serde(with = "time::serde::rfc3339::option")]
+    pub last_success: Option<OffsetDateTime>,
+    pub last_error: Option<String>,
+    pub consecutive_failures: u32,
+    /// While set and in the future, the circuit is open and the source is skipped.
+    #[serde(with = "time::serde::rfc3339::option")]
+    pub open_until: Option<OffsetDateTime>,
+    /// Last run skipped because the circuit was open.
+    #[serde(with = "time::serde::rfc3339::option")]
+    pub last_skipped: Option<OffsetDateTime>,
+    /// Runs skipped since the last attempt.
+    pub skipped_runs: u32,
+}
+
+impl SourceStatus {
+    pub fn record_success(&mut self, now: OffsetDateTime) {
+        self.last_attempt = Some(now);
+        self.last_success = Some(now);
+        self.last_error = None;
+        self.consecutive_failures = 0;
+        self.open_until = None;
+        self.skipped_runs = 0;
+    }
+
+    pub fn record_failure(&mut self, now: OffsetDateTime, error: String) {
+        self.last_attempt = Some(now);
+        self.last_error = Some(error);
+        self.consecutive_failures += 1;
+        self.skipped_runs = 0;
+    }
+
+    pub fn record_skip(&mut self, now: OffsetDateTime) {
+        self.last_skipped = Some(now);
+        self.skipped_runs += 1;
+    }
+
+    /// Whether the source is skipped until its circuit closes.
+    pub fn is_open(&self, now: OffsetDateTime) -> bool {
+        self.open_until.is_some_and(|t| t > now)
+    }
+
+    /// Whether the data of the source is older than `max_age`, or could never be fetched.
+    pub fn is_stale(&self, now: OffsetDateTime, max_age: Duration) -> bool {
+        match self.last_success {
+            Some(t) => now - t > max_age,
+            None => self.last_error.is_some(),
+        }
+    }
+}
+
+/// Fetch health, by source name.
+pub type SourceStatusArc = Arc<RwLock<BTreeMap<String, SourceStatus>>>;
+
+/// When sources were last refreshed on demand, by source name.
+pub type RefreshedArc = Arc<Mutex<HashMap<String, Instant>>>;
+
+/// What an on-demand refresh started.
+#[derive(Serialize, Debug, Default)]
+pub struct RefreshResult {
+    /// Sources being fetched.
+    pub refreshed: Vec<String>,
+    /// Sources skipped because they were refreshed moments ago.
+    pub debounced: Vec<String>,
+    /// Sources skipped because their circuit is open after failing in a row.
+    pub open: Vec<String>,
+}
+
+/// Cron job fetching a source.
+#[derive(Serialize, Clone, Debug)]
+pub struct ScheduledJob {
+    pub schedule: Schedule,
+    /// Cron expression the schedule runs on, `None` for intervals.
+    pub cron: Option<String>,
+    #[serde(skip)]
+    pub job_id: Uuid,
+    #[serde(with = "time::serde::rfc3339::option")]
+    pub last_run: Option<OffsetDateTime>,
+    #[serde(with = "time::serde::rfc3339::option")]
+    pub next_run: Option<OffsetDateTime>,
+}
+
+/// Cron jobs, by source name.
+pub type ScheduleMapArc = Arc<RwLock<BTreeMap<String, ScheduledJob>>>;
+
+/// Data shared between the page, cron jobs and the Home Assistant client.
+#[derive(Clone)]
+pub struct SharedState {
+    pub calendar: CalendarMapArc,
+    pub weather: WeatherInfoArc,
+    pub forecast: WeatherForecastArc,
+    pub sensors: SensorMapArc,
+    pub todos: TodoMapArc,
+    pub feeds: FeedCacheArc,
+    pub status: SourceStatusArc,
+    pub refreshed: RefreshedArc,
+    pub schedules: ScheduleMapArc,
+    pub last_update: LastUpdateArc,
+    pub generation: Generation,
+    pub page: PageConfigArc,
+}
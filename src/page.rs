@@ -0,0 +1,134 @@
+//! The layout file: which widgets go where on the page and the theme they
+//! are drawn with. It is reloaded when it changes or on `SIGHUP`, and an
+//! invalid file leaves the last good layout in place.
+use std::{
+    future::pending,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::{Context, anyhow, bail};
+use serde::Deserialize;
+use tokio::{
+    signal::unix::{Signal, SignalKind, signal},
+    sync::RwLock,
+};
+
+use crate::{
+    layout::Node,
+    widgets::{Theme, default_layout},
+};
+
+/// How often the file is checked for changes.
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PageConfig {
+    pub layout: Node,
+    pub theme: Theme,
+}
+
+impl Default for PageConfig {
+    fn default() -> Self {
+        Self {
+            layout: default_layout(),
+            theme: Theme::default(),
+        }
+    }
+}
+
+/// The current layout, replaced as a whole on reload.
+pub type PageConfigArc = Arc<RwLock<Arc<PageConfig>>>;
+
+impl PageConfig {
+    /// Reads and validates a layout file, in the format its extension names.
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Unable to read {}", path.display()))?;
+
+        Self::parse_file(path, &text)
+    }
+
+    fn parse_file(path: &Path, text: &str) -> anyhow::Result<Self> {
+        let format = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+
+        Self::parse(text, &format)
+            .with_context(|| format!("Invalid layout file {}", path.display()))
+    }
+
+    /// Parses and validates a layout written in `format`: `toml`, `yaml`,
+    /// `yml` or `json`.
+    pub fn parse(text: &str, format: &str) -> anyhow::Result<Self> {
+        let page: Self = match format {
+            "toml" => toml::from_str(text)?,
+            "yaml" | "yml" => serde_yaml::from_str(text)?,
+            "json" => serde_json::from_str(text)?,
+            _ => bail!("Unknown layout format `{format}`, expected toml, yaml or json"),
+        };
+        page.theme.validate().map_err(|e| anyhow!(e))?;
+
+        Ok(page)
+    }
+}
+
+/// Loads the file again. The current layout stays when the file is invalid.
+pub async fn reload(path: &Path, page: &PageConfigArc) -> anyhow::Result<()> {
+    let new = PageConfig::load(path)?;
+    *page.write().await = Arc::new(new);
+
+    Ok(())
+}
+
+/// Resolves on the next `SIGHUP`, or never when it cannot be listened to.
+async fn hangup(signal: &mut Option<Signal>) {
+    match signal {
+        Some(signal) => {
+            signal.recv().await;
+        }
+        None => pending().await,
+    }
+}
+
+/// Reloads the file whenever its content changes or the process gets `SIGHUP`.
+pub async fn watch(path: PathBuf, page: PageConfigArc) {
+    let mut sighup = signal(SignalKind::hangup())
+        .inspect_err(|e| tracing::warn!("Unable to listen to SIGHUP: {e}"))
+        .ok();
+    let mut interval = tokio::time::interval(POLL_INTERVAL);
+    let mut last_text = std::fs::read_to_string(&path).ok();
+
+    loop {
+        let text = tokio::select! {
+            _ = interval.tick() => {
+                let text = std::fs::read_to_string(&path).ok();
+                if text == last_text {
+                    continue;
+                }
+                text
+            }
+            _ = hangup(&mut sighup) => {
+                tracing::info!("Got SIGHUP, reloading {}", path.display());
+                std::fs::read_to_string(&path).ok()
+            }
+        };
+        last_text.clone_from(&text);
+
+        let res = match text {
+            Some(text) => PageConfig::parse_file(&path, &text),
+            None => Err(anyhow!("Unable to read {}", path.display())),
+        };
+        match res {
+            Ok(new) => {
+                *page.write().await = Arc::new(new);
+                tracing::info!("Reloaded layout file {}", path.display());
+            }
+            Err(e) => tracing::error!("Keeping the last good layout: {e:#}"),
+        }
+    }
+}
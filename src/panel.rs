@@ -0,0 +1,255 @@
+//! Panel profiles: resolution, palette and rotation of the e-paper displays
+//! the page is drawn for, and the separation of the page into their inks.
+use std::collections::BTreeMap;
+
+use image::{GrayImage, ImageBuffer, Pixel, RgbImage, imageops};
+use serde::Deserialize;
+
+use crate::{dither::Dither, layout::Color};
+
+/// Size the layout is designed at, scaled to fit each panel.
+pub const DESIGN_WIDTH: u32 = 400;
+pub const DESIGN_HEIGHT: u32 = 300;
+
+/// Profile used when neither the request nor the device picks one.
+pub const DEFAULT_PANEL: &str = "4.20in-bv2-bwr";
+
+/// Clockwise turn from the page as designed to the panel as mounted.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "u32")]
+pub enum Rotation {
+    #[default]
+    None,
+    Quarter,
+    Half,
+    ThreeQuarters,
+}
+
+impl TryFrom<u32> for Rotation {
+    type Error = String;
+
+    fn try_from(degrees: u32) -> Result<Self, Self::Error> {
+        match degrees {
+            0 => Ok(Self::None),
+            90 => Ok(Self::Quarter),
+            180 => Ok(Self::Half),
+            270 => Ok(Self::ThreeQuarters),
+            _ => Err(format!("rotation must be 0, 90, 180 or 270, not {degrees}")),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PanelProfile {
+    /// Resolution the driver takes the image in.
+    pub width: u32,
+    pub height: u32,
+    /// Inks the panel can show. Every pixel of the page ends up one of them.
+    pub palette: Vec<Color>,
+    #[serde(default)]
+    pub rotation: Rotation,
+    /// Scale of the layout, by default the largest that fits the design size.
+    #[serde(default)]
+    pub scale: Option<f32>,
+}
+
+impl PanelProfile {
+    fn new(width: u32, height: u32, palette: &[Color]) -> Self {
+        Self {
+            width,
+            height,
+            palette: palette.to_vec(),
+            rotation: Rotation::None,
+            scale: None,
+        }
+    }
+
+    /// Size of the page before rotation.
+    pub fn page_size(&self) -> (u32, u32) {
+        match self.rotation {
+            Rotation::None | Rotation::Half => (self.width, self.height),
+            Rotation::Quarter | Rotation::ThreeQuarters => (self.height, self.width),
+        }
+    }
+
+    pub fn scale(&self) -> f32 {
+        let (w, h) = self.page_size();
+
+        self.scale.unwrap_or_else(|| {
+            f32::min(
+                w as f32 / DESIGN_WIDTH as f32,
+                h as f32 / DESIGN_HEIGHT as f32,
+            )
+        })
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.width == 0 || self.height == 0 {
+            return Err("width and height must not be 0".to_string());
+        }
+        if self.width > u16::MAX as u32 || self.height > u16::MAX as u32 {
+            return Err(format!("width and height must be at most {}", u16::MAX));
+        }
+        if self.palette.len() < 2 {
+            return Err("palette needs at least two colours".to_string());
+        }
+        if self.scale.is_some_and(|s| !(s.is_finite() && s > 0.0)) {
+            return Err("scale must be above 0".to_string());
+        }
+
+        Ok(())
+    }
+
+    /// Turns the page as drawn to the panel as mounted.
+    pub fn rotate<P: Pixel + 'static>(
+        &self,
+        page: ImageBuffer<P, Vec<P::Subpixel>>,
+    ) -> ImageBuffer<P, Vec<P::Subpixel>> {
+        match self.rotation {
+            Rotation::None => page,
+            Rotation::Quarter => imageops::rotate90(&page),
+            Rotation::Half => imageops::rotate180(&page),
+            Rotation::ThreeQuarters => imageops::rotate270(&page),
+        }
+    }
+
+    /// The ink `color` is drawn with: itself when the panel has it, else the
+    /// nearest one. Gray stays, to be drawn as a dither.
+    pub fn ink(&self, color: Color) -> Color {
+        match color == Color::Gray || self.palette.contains(&color) {
+            true => color,
+            false => {
+                let rgb = color.rgb().0.map(f32::from);
+
+                *self
+                    .palette
+                    .iter()
+                    .min_by(|a, b| {
+                        let a = distance(&rgb, &a.rgb().0.map(f32::from));
+                        let b = distance(&rgb, &b.rgb().0.map(f32::from));
+                        a.total_cmp(&b)
+                    })
+                    .expect("palettes are validated to have inks")
+            }
+        }
+    }
+
+    /// The ink of every pixel of `image`, row by row. Pixels set in `mask`
+    /// are dithered, so tints come out as a mix of inks, the others get the
+    /// nearest ink. Grays are only mixed from the black, white and gray inks,
+    /// so they do not turn into coloured noise.
+    pub fn quantize(&self, image: &RgbImage, mask: &GrayImage, dither: Dither) -> Vec<Color> {
+        let (w, h) = (image.width() as usize, image.height() as usize);
+        let inks = self
+            .palette
+            .iter()
+            .map(|c| (*c, c.rgb().0.map(f32::from)))
+            .collect::<Vec<_>>();
+        let neutral_inks = inks
+            .iter()
+            .filter(|(_, rgb)| is_neutral(rgb))
+            .copied()
+            .collect::<Vec<_>>();
+        let mut pixels = image
+            .pixels()
+            .map(|p| p.0.map(f32::from))
+            .collect::<Vec<_>>();
+        let nearest = |inks: &[(Color, [f32; 3])], px: &[f32; 3]| {
+            *inks
+                .iter()
+                .min_by(|(_, a), (_, b)| distance(px, a).total_cmp(&distance(px, b)))
+                .expect("palettes are validated to have inks")
+        };
+        let mut quantized = Vec::with_capacity(w * h);
+
+        for y in 0..h {
+            for x in 0..w {
+                let idx = y * w + x;
+                let original = image.get_pixel(x as u32, y as u32).0.map(f32::from);
+                let candidates = match neutral_inks.len() >= 2 && is_neutral(&original) {
+                    true => &neutral_inks,
+                    false => &inks,
+                };
+
+                // Text and lines
+                if mask.get_pixel(x as u32, y as u32).0[0] == 0 {
+                    quantized.push(nearest(candidates, &original).0);
+                    continue;
+                }
+
+                // Ordered methods move the pixel by up to half the step between inks
+                let px = match dither.threshold(x as u32, y as u32) {
+                    Some(t) => {
+                        let step = 255.0 / (candidates.len() - 1) as f32;
+                        original.map(|c| c + (t - 0.5) * step)
+                    }
+                    None => pixels[idx],
+                };
+                let (color, ink) = nearest(candidates, &px);
+                quantized.push(color);
+
+                let err = [px[0] - ink[0], px[1] - ink[1], px[2] - ink[2]];
+                for (dx, dy, share) in dither.diffusion() {
+                    let nx = x as isize + dx;
+                    if nx >= 0 && (nx as usize) < w && y + dy < h {
+                        let n = &mut pixels[(y + dy) * w + nx as usize];
+                        (0..3).for_each(|c| n[c] += err[c] * share);
+                    }
+                }
+            }
+        }
+
+        quantized
+    }
+}
+
+fn distance(a: &[f32; 3], b: &[f32; 3]) -> f32 {
+    (0..3).map(|c| (a[c] - b[c]).powi(2)).sum()
+}
+
+/// Whether a colour is a shade of gray, give or take what contrast adds.
+fn is_neutral(rgb: &[f32; 3]) -> bool {
+    let max = rgb.iter().copied().fold(f32::MIN, f32::max);
+    let min = rgb.iter().copied().fold(f32::MAX, f32::min);
+
+    max - min <= 16.0
+}
+
+/// Waveshare panels in use, by their ESPHome model name.
+pub fn builtin_profiles() -> BTreeMap<String, PanelProfile> {
+    use Color::*;
+
+    [
+        ("2.90in-bw", PanelProfile::new(296, 128, &[Black, White])),
+        (
+            "2.90in-b-bwr",
+            PanelProfile::new(296, 128, &[Black, White, Red]),
+        ),
+        (
+            DEFAULT_PANEL,
+            PanelProfile::new(400, 300, &[Black, White, Red]),
+        ),
+        (
+            "5.65in-f-acep",
+            PanelProfile::new(600, 448, &[Black, White, Green, Blue, Red, Yellow, Orange]),
+        ),
+        ("7.50in-v2-bw", PanelProfile::new(800, 480, &[Black, White])),
+        (
+            "7.50in-bv2-bwr",
+            PanelProfile::new(800, 480, &[Black, White, Red]),
+        ),
+        (
+            "7.50in-c-bwy",
+            PanelProfile::new(640, 384, &[Black, White, Yellow]),
+        ),
+        ("13.3in-k-bw", PanelProfile::new(960, 680, &[Black, White])),
+        (
+            "13.3in-e6",
+            PanelProfile::new(1600, 1200, &[Black, White, Yellow, Red, Blue, Green]),
+        ),
+    ]
+    .into_iter()
+    .map(|(name, profile)| (name.to_string(), profile))
+    .collect()
+}
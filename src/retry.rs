@@ -0,0 +1,148 @@
+//! Retries with backoff and a per-source circuit breaker, so a broken source
+//! neither hammers its server nor delays the others.
+use std::{future::Future, time::Duration};
+
+use rand::Rng;
+use serde::{Deserialize, Deserializer};
+use time::OffsetDateTime;
+
+use crate::{api_error::ApiError, model::SourceStatusArc};
+
+/// Set per source with durations in seconds, every field being optional.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetryPolicy {
+    /// Attempts per run, including the first one.
+    pub attempts: u32,
+    /// Delay before the first retry, doubled on each following one.
+    #[serde(deserialize_with = "seconds")]
+    pub base_delay: Duration,
+    /// Failed runs in a row that open the circuit.
+    pub failure_threshold: u32,
+    /// How long an open circuit skips the source. Doubled on each further
+    /// failed run, up to `max_cooldown`.
+    #[serde(deserialize_with = "seconds")]
+    pub cooldown: Duration,
+    #[serde(deserialize_with = "seconds")]
+    pub max_cooldown: Duration,
+}
+
+fn seconds<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+    u64::deserialize(deserializer).map(Duration::from_secs)
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            attempts: 3,
+            base_delay: Duration::from_secs(2),
+            failure_threshold: 3,
+            cooldown: Duration::from_secs(5 * 60),
+            max_cooldown: Duration::from_secs(60 * 60),
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.attempts == 0 || self.failure_threshold == 0 {
+            return Err("attempts and failure_threshold must not be 0".to_string());
+        }
+        if self.cooldown > self.max_cooldown {
+            return Err("cooldown must not be above max_cooldown".to_string());
+        }
+
+        Ok(())
+    }
+
+    /// Exponential delay before retry number `retry` (from 1), plus up to 50% jitter.
+    fn delay(&self, retry: u32) -> Duration {
+        let delay = self.base_delay * 2_u32.saturating_pow(retry - 1);
+        let jitter = rand::rng().random_range(0.0..=0.5);
+
+        delay.mul_f64(1.0 + jitter)
+    }
+
+    fn cooldown(&self, failures: u32) -> Duration {
+        let exp = failures.saturating_sub(self.failure_threshold).min(16);
+
+        Duration::min(self.cooldown * 2_u32.pow(exp), self.max_cooldown)
+    }
+}
+
+/// What [`run`] did with a source.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Outcome {
+    Fetched,
+    /// Not fetched, the circuit of the source being open until then.
+    Skipped {
+        open_until: OffsetDateTime,
+    },
+}
+
+/// Runs `f` for the source `name`, retrying per `policy` and recording the
+/// outcome in `status`. Skipped while the circuit of the source is open.
+pub async fn run<F, Fut>(
+    name: &str,
+    status: &SourceStatusArc,
+    policy: &RetryPolicy,
+    mut f: F,
+) -> Result<Outcome, ApiError>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<(), ApiError>>,
+{
+    let now = OffsetDateTime::now_utc();
+    {
+        let mut status = status.write().await;
+        let open_until = status
+            .get(name)
+            .filter(|s| s.is_open(now))
+            .and_then(|s| s.open_until);
+
+        if let Some(open_until) = open_until {
+            tracing::debug!("Source {}: circuit open until {}", name, open_until);
+            status.entry(name.to_string()).or_default().record_skip(now);
+            return Ok(Outcome::Skipped { open_until });
+        }
+    }
+
+    let mut attempt = 1;
+    let res = loop {
+        match f().await {
+            Ok(()) => break Ok(()),
+            Err(e) if attempt >= policy.attempts => break Err(e),
+            Err(e) => {
+                let delay = policy.delay(attempt);
+                tracing::warn!(
+                    "Source {}: attempt {} failed, retry in {:?}: {:?}",
+                    name,
+                    attempt,
+                    delay,
+                    e
+                );
+                tokio::time::sleep(delay).await;
+                attempt += 1;
+            }
+        }
+    };
+
+    let now = OffsetDateTime::now_utc();
+    let mut status = status.write().await;
+    let entry = status.entry(name.to_string()).or_default();
+
+    match &res {
+        Ok(()) => entry.record_success(now),
+        Err(e) => {
+            entry.record_failure(now, e.detail());
+
+            if entry.consecutive_failures >= policy.failure_threshold {
+                let cooldown = policy.cooldown(entry.consecutive_failures);
+                tracing::error!("Source {}: circuit open for {:?}", name, cooldown);
+                entry.open_until = Some(now + cooldown);
+            }
+        }
+    }
+
+    res.map(|()| Outcome::Fetched)
+}
@@ -0,0 +1,14 @@
+use std::collections::BTreeMap;
+
+use axum::{Json, extract::State};
+
+use crate::{AppState, api_error::ApiError, model::ScheduledJob};
+
+/// Lists the cron job of every source with its next run.
+pub async fn schedules(
+    State(state): State<AppState>,
+) -> Result<Json<BTreeMap<String, ScheduledJob>>, ApiError> {
+    let schedules = state.schedules.read().await;
+
+    Ok(Json(schedules.clone()))
+}
@@ -1,401 +1,320 @@
-use ab_glyph::{FontRef, InvalidFont, PxScale};
 use axum::{
     extract::{Query, State},
-    http::HeaderValue,
+    http::{HeaderMap, HeaderValue, StatusCode, header},
     response::IntoResponse,
 };
-use image::{
-    Luma, Pixel, Rgba,
-    imageops::{
-        BiLevel,
-        colorops::{contrast_in_place, dither},
-    },
+use image::{GrayImage, Luma, Pixel, Rgba, RgbaImage, imageops::colorops::contrast_in_place};
+use imageproc::image::{ImageFormat, RgbImage};
+use itertools::Itertools;
+use std::{
+    collections::BTreeSet,
+    io::{BufWriter, Cursor},
 };
-use imageproc::{
-    drawing,
-    image::{ImageFormat, Rgb, RgbImage},
-    map::map_pixels,
-    rect::Rect,
+use time::{
+    Date, Duration, OffsetDateTime, Weekday, format_description::FormatItem,
+    macros::format_description,
 };
-use itertools::Itertools;
-use std::io::{BufWriter, Cursor};
-use time::{Month, Weekday};
-use time_tz::{OffsetDateTimeExt, timezones};
+use time_tz::{OffsetDateTimeExt, PrimitiveDateTimeExt, TimeZone, Tz, timezones};
 
 use crate::{
     AppState,
     api_error::ApiError,
+    cron, epd,
+    layout::{Color, Scale, WidgetMap},
     model::{
-        CalendarMap, QueryRouteEPaperFormatEnum, QueryRouteEPaperModel,
-        QueryRouteEPaperOutputEnum as OutputEnum, WeatherInfoState,
+        CalendarMap, DateInfo, DateInfoEventMode, QueryRouteEPaperFormatEnum,
+        QueryRouteEPaperModel, QueryRouteEPaperOutputEnum as OutputEnum, RenderKey, RenderState,
+        RenderedPage, TodoDue, TodoItemStatus,
     },
+    panel::PanelProfile,
+    widgets::{Agenda, DateBlock, FONTS, Footer, Sensors, Todo, Weather, WidgetKind},
 };
 
-fn month_abbr(mth: Month) -> String {
-    match mth {
-        Month::January => "Jan",
-        Month::February => "Feb",
-        Month::March => "Mar",
-        Month::April => "Apr",
-        Month::May => "May",
-        Month::June => "Jun",
-        Month::July => "Jul",
-        Month::August => "Aug",
-        Month::September => "Sep",
-        Month::October => "Oct",
-        Month::November => "Nov",
-        Month::December => "Dec",
-    }
-    .to_string()
-    .to_uppercase()
-}
-
-fn substr_th(str: String, len: usize) -> String {
-    let mut cnt = 0_usize;
-
-    if str.chars().count() <= len {
-        return str;
-    }
-
-    let mut res_str = str
-        .chars()
-        .take_while_inclusive(|c| {
-            if matches!(c, 'ั' | 'ุ' ..= '\u{E3E}' | '็' ..= '๎') {
-                return true;
+/// Pages kept in the render cache, at most.
+const RENDER_CACHE_CAP: usize = 64;
+
+/// Format of `Last-Modified`, e.g. `Sat, 17 Oct 2026 08:00:00 GMT`.
+const HTTP_DATE: &[FormatItem<'static>] = format_description!(
+    "[weekday repr:short], [day] [month repr:short] [year] [hour]:[minute]:[second] GMT"
+);
+
+/// Re-key the calendar by the local date of each event in `tz`.
+///
+/// Events are stored under the date of their own time zone, so an event can
+/// move to the previous or next day once shown in another zone. Holidays and
+/// all-day events are plain dates and stay where they are.
+fn localize_calendar(clnd: &CalendarMap, tz: &Tz, from: Date) -> CalendarMap {
+    let mut clnd_n = CalendarMap::new();
+
+    clnd.range(from.previous_day().unwrap_or(from)..)
+        .for_each(|(c_date, c_nf)| {
+            if !c_nf.holidays.is_empty() {
+                clnd_n
+                    .entry(*c_date)
+                    .or_insert_with(|| DateInfo {
+                        date: *c_date,
+                        holidays: Default::default(),
+                        events: Default::default(),
+                    })
+                    .holidays
+                    .clone_from(&c_nf.holidays);
             }
 
-            cnt += 1;
-
-            cnt <= len
-        })
-        .collect::<String>();
-
-    res_str.push('…');
+            c_nf.events.iter().for_each(|(uid, event)| {
+                let event = DateInfoEventMode {
+                    time: event.time.to_timezone(tz),
+                    end: event.end.to_timezone(tz),
+                    ..event.clone()
+                };
+                // All-day events cover dates, not instants
+                let dates = match event.all_day {
+                    true => vec![(*c_date, event.day, event.days)],
+                    false => {
+                        let dates = event.span_dates(tz);
+                        let days = dates.len() as u32;
+
+                        dates
+                            .into_iter()
+                            .enumerate()
+                            .map(|(idx, date)| (date, idx as u32 + 1, days))
+                            .collect_vec()
+                    }
+                };
+
+                dates.into_iter().for_each(|(date, day, days)| {
+                    clnd_n
+                        .entry(date)
+                        .or_insert_with(|| DateInfo {
+                            date,
+                            holidays: Default::default(),
+                            events: Default::default(),
+                        })
+                        .events
+                        .insert(
+                            uid.clone(),
+                            DateInfoEventMode {
+                                day,
+                                days,
+                                ..event.clone()
+                            },
+                        );
+                });
+            });
+        });
 
-    res_str
+    clnd_n.split_off(&from)
 }
 
-pub async fn epaper_page(
-    State(state): State<AppState>,
-    Query(q): Query<QueryRouteEPaperModel>,
-) -> impl IntoResponse {
-    let time_utc = time::OffsetDateTime::now_utc();
-    let time_local =
-        time_utc.to_timezone(timezones::get_by_name("Asia/Bangkok").unwrap_or(timezones::db::UTC));
+/// Draws the page and encodes it in the requested output and format, with the
+/// time until which the same data draws the same page.
+async fn render(
+    state: &AppState,
+    q: &QueryRouteEPaperModel,
+    tz: &'static Tz,
+    time_utc: OffsetDateTime,
+    drawn: &RenderState,
+    panel: &PanelProfile,
+) -> Result<(Vec<u8>, OffsetDateTime), ApiError> {
+    let page = &drawn.page;
+    let time_local = time_utc.to_timezone(tz);
     let time_date = time_local.date();
     let calendar = {
         let clnd = state.calendar.read().await;
-        let mut clnd_n = CalendarMap::new();
 
-        clnd.range(time_date..).take(9).for_each(|(c_date, c_nf)| {
-            clnd_n.insert(*c_date, c_nf.clone());
-        });
-
-        clnd_n
+        localize_calendar(&clnd, tz, time_date)
+            .into_iter()
+            .take(9)
+            .collect::<CalendarMap>()
     };
     let is_holiday = match time_date.weekday() {
         Weekday::Sunday | Weekday::Saturday => true,
         _ => calendar
             .get(&time_date)
-            .map(|c| c.holiday.is_some())
+            .map(|c| c.is_holiday())
             .unwrap_or(false),
     };
     let is_event = calendar
         .get(&time_date)
         .map(|c| !c.events.is_empty())
         .unwrap_or(false);
-
-    // Colours
-    let red = Rgb([255u8, 0u8, 0u8]);
-    let black = Rgb([0u8, 0u8, 0u8]);
-    let gray = Rgb([137u8, 136u8, 136u8]);
-    let white = Rgb([255u8, 255u8, 255u8]);
-    // Fonts
-    let (font_anta, font_chakra_r, font_chakra_b, font_chakra_sb, font_material) = match (|| {
-        Ok::<
-            (
-                FontRef<'_>,
-                FontRef<'_>,
-                FontRef<'_>,
-                FontRef<'_>,
-                FontRef<'_>,
-            ),
-            InvalidFont,
-        >((
-            FontRef::try_from_slice(include_bytes!("../../fonts/Anta/Anta-Regular.ttf"))?,
-            FontRef::try_from_slice(include_bytes!(
-                "../../fonts/Chakra_Petch/ChakraPetch-Regular.ttf"
-            ))?,
-            FontRef::try_from_slice(include_bytes!(
-                "../../fonts/Chakra_Petch/ChakraPetch-Bold.ttf"
-            ))?,
-            FontRef::try_from_slice(include_bytes!(
-                "../../fonts/Chakra_Petch/ChakraPetch-SemiBold.ttf"
-            ))?,
-            FontRef::try_from_slice(include_bytes!(
-                "../../fonts/materialdesignicons-webfont.ttf"
-            ))?,
-        ))
-    })() {
-        Err(e) => {
-            return ApiError::InternalError(e.into()).into_response();
+    let is_stale = |name: &str| drawn.stale_sources.contains(name);
+    let fonts = FONTS
+        .as_ref()
+        .map_err(|e| ApiError::InternalError(anyhow::anyhow!("{e}")))?;
+    // Colours the panel lacks are drawn with its nearest ink
+    let theme = &page.theme.recolored(|c| panel.ink(c));
+
+    // Forecast strip: upcoming days, or upcoming hours when there is no daily forecast
+    let forecast = {
+        let forecast = state.forecast.read().await;
+
+        match forecast.daily.is_empty() {
+            false => forecast
+                .daily
+                .iter()
+                .filter_map(|f| {
+                    let dt = f.datetime?.to_timezone(tz);
+                    (dt.date() > time_date).then(|| {
+                        (
+                            dt.weekday().to_string().chars().take(3).collect::<String>(),
+                            f.clone(),
+                        )
+                    })
+                })
+                .collect_vec(),
+            true => forecast
+                .hourly
+                .iter()
+                .filter_map(|f| {
+                    let dt = f.datetime?.to_timezone(tz);
+                    (dt > time_local).then(|| (format! {"{:02}h", dt.hour()}, f.clone()))
+                })
+                .collect_vec(),
         }
-        Ok(f) => f,
     };
-
-    // Image base
-    let mut image = RgbImage::new(400, 300);
-    let img_w = image.width();
-    let img_h = image.height();
-    let border_px = 10_u32;
-    let last_update_y = img_h - border_px - 10;
-
-    // Background
-    drawing::draw_filled_rect_mut(&mut image, Rect::at(0, 0).of_size(img_w, img_h), white);
-
-    // * today
-    // Date section
-    // Date day
-    let date_day_box_w = 90;
-    let date_day_box_h = 120;
-    let date_day_box_l = border_px + 10;
-    let date_day_box_t = border_px;
-    let left_box_w = date_day_box_w + (date_day_box_l * 2);
-    drawing::draw_filled_rect_mut(
-        &mut image,
-        Rect::at(date_day_box_l as i32, date_day_box_t as i32)
-            .of_size(date_day_box_w, date_day_box_h),
-        match is_holiday {
-            true => red,
-            false => black,
-        },
-    );
-
-    if is_event {
-        // draw dots
-        (0..date_day_box_w).step_by(4).for_each(|x| {
-            (0..date_day_box_h).step_by(4).for_each(|y| {
-                drawing::draw_cross_mut(
-                    &mut image,
-                    white,
-                    (date_day_box_l + x) as i32,
-                    (date_day_box_t + y) as i32,
-                );
-            });
-        });
-    }
-
-    let date_day_str = time_local.day().to_string();
-    let date_day_scale = PxScale {
-        x: 90.0,
-        y: 90.0 * 1.5,
+    // Open to-do items, soonest due first
+    let todos = {
+        let todos = state.todos.read().await;
+
+        state
+            .cfg
+            .ha_todo_entities
+            .iter()
+            .filter_map(|entity_id| todos.get(entity_id))
+            .flatten()
+            .filter(|item| item.status == TodoItemStatus::NeedsAction)
+            .sorted_by_key(|item| match item.due() {
+                Some(TodoDue::Date(d)) => (false, d.midnight().assume_offset(time::UtcOffset::UTC)),
+                Some(TodoDue::DateTime(dt)) => (false, dt),
+                None => (true, time::OffsetDateTime::UNIX_EPOCH),
+            })
+            .take(4)
+            .cloned()
+            .collect_vec()
+    };
+    let sensors = {
+        let sensors = state.sensors.read().await;
+
+        state
+            .cfg
+            .ha_sensors
+            .iter()
+            .map(|sensor| (sensor.clone(), sensors.get(&sensor.entity_id).cloned()))
+            .collect_vec()
     };
-    let (date_day_txt_sz_w, _date_day_txt_sz_h) =
-        drawing::text_size(date_day_scale, &font_anta, &date_day_str);
-    drawing::draw_text_mut(
-        &mut image,
-        white,
-        (border_px
-            + (u32::max(
-                (border_px as f64 * 0.75) as u32,
-                date_day_box_w.abs_diff(date_day_txt_sz_w) / 2,
-            ))) as i32,
-        border_px as i32,
-        date_day_scale,
-        &font_anta,
-        &date_day_str,
+    // The same data draws another page once the date changes, the first
+    // forecast slot has come or a to-do is overdue
+    let next_date = time_date
+        .next_day()
+        .and_then(|d| d.midnight().assume_timezone(tz).take_first())
+        .unwrap_or(time_utc + Duration::DAY);
+    let valid_until = forecast
+        .iter()
+        .filter_map(|(_, f)| f.datetime)
+        .chain(todos.iter().filter_map(|item| match item.due() {
+            Some(TodoDue::DateTime(dt)) => Some(dt),
+            _ => None,
+        }))
+        .filter(|t| *t > time_utc)
+        .fold(next_date, OffsetDateTime::min);
+
+    let mut widgets = WidgetMap::new();
+    widgets.insert(
+        WidgetKind::DateBlock,
+        Box::new(DateBlock {
+            fonts,
+            theme,
+            time: time_local,
+            is_holiday,
+            is_event,
+        }),
     );
-    // Date month
-    let date_mth_str = month_abbr(time_local.month());
-    let date_mth_scale = PxScale { x: 60.0, y: 60.0 };
-    let (date_mth_txt_sz_w, date_mth_txt_sz_h) =
-        drawing::text_size(date_mth_scale, &font_anta, &date_mth_str);
-    drawing::draw_text_mut(
-        &mut image,
-        black,
-        (border_px + (date_mth_txt_sz_w.abs_diff(date_day_box_w) / 2)) as i32,
-        (border_px + date_day_box_h) as i32,
-        date_mth_scale,
-        &font_anta,
-        &date_mth_str,
+    widgets.insert(
+        WidgetKind::Weather,
+        Box::new(Weather {
+            fonts,
+            theme,
+            current: state.weather.read().await.clone(),
+            forecast,
+            stale: is_stale(cron::HA_STATES_SOURCE) || is_stale(cron::FORECAST_SOURCE),
+        }),
     );
-    // Date Year
-    let date_yr_str = (time_local.year() % 100).to_string();
-    let date_yr_scale = PxScale { x: 90.0, y: 90.0 };
-    let (date_yr_txt_sz_w, _date_yr_txt_sz_h) =
-        drawing::text_size(date_yr_scale, &font_anta, &date_yr_str);
-    drawing::draw_text_mut(
-        &mut image,
-        black,
-        (border_px + (date_yr_txt_sz_w.abs_diff(date_day_box_w + date_day_box_l) / 2)) as i32,
-        (border_px + date_day_box_h + date_mth_txt_sz_h + border_px) as i32,
-        date_yr_scale,
-        &font_anta,
-        &date_yr_str,
+    widgets.insert(
+        WidgetKind::Agenda,
+        Box::new(Agenda {
+            fonts,
+            theme,
+            calendar,
+            stale: state.cfg.calendar_sources.iter().any(|s| is_stale(&s.name)),
+        }),
+    );
+    widgets.insert(
+        WidgetKind::Todo,
+        Box::new(Todo {
+            fonts,
+            theme,
+            items: todos,
+            now: time_utc,
+            tz,
+            stale: is_stale(cron::TODO_SOURCE),
+        }),
+    );
+    widgets.insert(
+        WidgetKind::Sensors,
+        Box::new(Sensors {
+            fonts,
+            theme,
+            sensors,
+            stale: is_stale(cron::HA_STATES_SOURCE),
+        }),
+    );
+    widgets.insert(
+        WidgetKind::Footer,
+        Box::new(Footer {
+            fonts,
+            theme,
+            last_update: drawn.last_update.assume_utc().to_timezone(tz),
+        }),
     );
 
-    // * Weather
-    // See: https://community.home-assistant.io/t/display-materialdesign-icons-on-esphome-attached-to-screen/199790/16
-    {
-        let weather = state.weather.read().await;
-        let weather_icon = match weather.as_ref().map(|w| w.state.clone()) {
-            Some(WeatherInfoState::Cloudy) => "\u{0F0590}",
-            Some(WeatherInfoState::Fog) => "\u{0F0591}",
-            Some(WeatherInfoState::Hail) => "\u{0F0592}",
-            Some(WeatherInfoState::Lightning) => "\u{0F0593}",
-            Some(WeatherInfoState::LightningRainy) => "\u{0F067E}",
-            Some(WeatherInfoState::ClearNight) => "\u{0F0594}",
-            Some(WeatherInfoState::Partlycloudy) => "\u{0F0595}",
-            Some(WeatherInfoState::Pouring) => "\u{0F0596}",
-            Some(WeatherInfoState::Rainy) => "\u{0F0597}",
-            Some(WeatherInfoState::Snowy) => "\u{0F0598}",
-            Some(WeatherInfoState::SnowyRainy) => "\u{0F067F}",
-            Some(WeatherInfoState::Sunny) => "\u{0F0599}",
-            Some(WeatherInfoState::Windy) => "\u{0F059D}",
-            Some(WeatherInfoState::WindyVariant) => "\u{0F059E}",
-            Some(WeatherInfoState::Exceptional) => "?",
-            None => "?",
-        };
-        let weather_icon_x = left_box_w + border_px + border_px;
-        let weather_icon_fnt_sz = 45.0_f32;
-        drawing::draw_text_mut(
-            &mut image,
-            black,
-            (left_box_w + border_px + border_px) as i32,
-            border_px as i32,
-            PxScale {
-                x: weather_icon_fnt_sz,
-                y: weather_icon_fnt_sz,
-            },
-            &font_material,
-            weather_icon,
-        );
-        if let Some(w) = weather.as_ref() {
-            drawing::draw_text_mut(
-                &mut image,
-                black,
-                (weather_icon_x + border_px + 45) as i32,
-                (border_px + (f32::abs(weather_icon_fnt_sz - 30.0) / 2.0) as u32) as i32,
-                PxScale { x: 30.0, y: 30.0 },
-                &font_anta,
-                &format! {"{:.1}{}", w.attributes.temperature, w.attributes.temperature_unit},
-            );
-        }
-    }
-
-    // * Event
-    let status_h = 40;
-    let event_fnt_sz = 16;
-    let event_fnt_scale = PxScale {
-        x: event_fnt_sz as f32,
-        y: event_fnt_sz as f32,
-    };
-    let mut event_y_pos = status_h + border_px + border_px;
-
-    for (c_date, c_info) in calendar {
-        let is_holiday = c_info.holiday.is_some();
-        let c_date_cln = c_date.to_calendar_date();
-        let mut date_txt = format! {
-            "{} {} {}",
-            c_date_cln.0, month_abbr(c_date_cln.1), c_date_cln.2
-        };
-
-        if let Some(hld_txt) = c_info.holiday {
-            date_txt.push_str(&format! {"—{hld_txt}"});
-            date_txt = substr_th(date_txt, 32);
-        }
+    let (page_w, page_h) = panel.page_size();
+    let mut image = RgbImage::new(page_w, page_h);
+    let mask =
+        page.layout
+            .recolored(&|c| panel.ink(c))
+            .draw(&mut image, &widgets, Scale(panel.scale()));
 
-        // Date header
-        let date_box_l = left_box_w + border_px;
-        let date_box_h = (event_fnt_sz as f32 * 1.5) as u32;
-        drawing::draw_filled_rect_mut(
-            &mut image,
-            Rect::at(date_box_l as i32, event_y_pos as i32)
-                .of_size(img_w - border_px - date_box_l, date_box_h),
-            match is_holiday {
-                true => red,
-                false => black,
-            },
-        );
-        drawing::draw_text_mut(
-            &mut image,
-            white,
-            (date_box_l + border_px) as i32,
-            (event_y_pos + ((event_fnt_sz as f32 * 0.5) / 2.0) as u32) as i32,
-            event_fnt_scale,
-            &font_chakra_b,
-            &date_txt,
-        );
-        event_y_pos += date_box_h;
+    // Adjust contrast
+    contrast_in_place(&mut image, 200.0);
 
-        if event_y_pos > (last_update_y - (border_px * 2)) {
-            break;
+    // Separate into the inks of the panel
+    let image = panel.rotate(image);
+    let (width, height) = image.dimensions();
+    let inks = panel.quantize(&image, &panel.rotate(mask), q.dither);
+    let ink = |x: u32, y: u32| inks[(y * width + x) as usize];
+
+    // Frame buffers for the controller, one plane per ink but white
+    if q.format == QueryRouteEPaperFormatEnum::Epd {
+        let invert = q.output == OutputEnum::BlackInvert;
+        let planes = match q.output.ink() {
+            Some(layer) => vec![layer],
+            None => panel
+                .palette
+                .iter()
+                .copied()
+                .filter(|c| *c != Color::White)
+                .collect_vec(),
         }
+        .into_iter()
+        .map(|layer| {
+            let plane = epd::pack_plane(width, height, |x, y| (ink(x, y) == layer) != invert);
+            (layer, plane)
+        })
+        .collect_vec();
 
-        for (_uid, event) in c_info.events {
-            let event_name = substr_th(event.name, 32);
-
-            // Draw box for better visibility on ePaper
-            drawing::draw_filled_rect_mut(
-                &mut image,
-                Rect::at(date_box_l as i32, event_y_pos as i32)
-                    .of_size(img_w - border_px - date_box_l, date_box_h),
-                gray,
-            );
-            drawing::draw_text_mut(
-                &mut image,
-                match is_holiday {
-                    true => red,
-                    false => black,
-                },
-                (date_box_l + border_px) as i32,
-                (event_y_pos + (border_px / 2)) as i32,
-                event_fnt_scale,
-                &font_chakra_r,
-                &format! {"{:02}:{:02}", event.time.hour(), event.time.minute()},
-            );
-            drawing::draw_text_mut(
-                &mut image,
-                match is_holiday {
-                    true => red,
-                    false => black,
-                },
-                (date_box_l + border_px + ((event_fnt_scale.x * 2.5) as u32)) as i32,
-                (event_y_pos + (border_px / 2)) as i32,
-                event_fnt_scale,
-                // _r is too slim when render
-                &font_chakra_sb,
-                &event_name,
-            );
-            event_y_pos += event_fnt_sz + (border_px / 2);
-
-            if event_y_pos > (last_update_y - (border_px * 2)) {
-                break;
-            }
-        }
+        return Ok((epd::encode(width, height, &planes, q.rle), valid_until));
     }
 
-    // Last update
-    // Draw box for better visibility on ePaper
-    let last_upd_at_t = last_update_y - (border_px / 2);
-    drawing::draw_filled_rect_mut(
-        &mut image,
-        Rect::at(0_i32, last_upd_at_t as i32).of_size(img_w, img_h - last_upd_at_t),
-        gray,
-    );
-    drawing::draw_text_mut(
-        &mut image,
-        black,
-        border_px as i32,
-        last_update_y as i32,
-        PxScale { x: 12.0, y: 12.0 },
-        &font_chakra_sb,
-        &format! {"Last update: {}", time_local.replace_nanosecond(0).unwrap_or(time_local)},
-    );
-
-    // Adjust contrast
-    contrast_in_place(&mut image, 200.0);
-
     // Save the response
     let img_fmt = match q.format {
         QueryRouteEPaperFormatEnum::Bmp => ImageFormat::Bmp,
@@ -403,57 +322,156 @@ pub async fn epaper_page(
     };
     let mut img_buf = BufWriter::new(Cursor::new(Vec::new()));
 
-    if let Err(e) = match q.output {
-        OutputEnum::Full => image.write_to(&mut img_buf, img_fmt),
+    if let Err(e) = match (q.output, q.output.ink()) {
+        (_, None) => {
+            RgbImage::from_fn(width, height, |x, y| ink(x, y).rgb()).write_to(&mut img_buf, img_fmt)
+        }
         // Paint black only black. Otherwise White
-        OutputEnum::Black | OutputEnum::BlackInvert => {
-            let mut bw_img = map_pixels(&image, |_x, _y, p| {
-                let red = p.channels()[0];
-                let blue = p.channels()[2];
-                let target_luma = red | blue;
-                Luma([if q.output == OutputEnum::BlackInvert {
-                    !target_luma
-                } else {
-                    target_luma
+        (OutputEnum::Black | OutputEnum::BlackInvert, _) => {
+            let invert = q.output == OutputEnum::BlackInvert;
+            GrayImage::from_fn(width, height, |x, y| {
+                Luma([match (ink(x, y) == Color::Black) != invert {
+                    true => 0,
+                    false => 255,
                 }])
-            });
-            dither(&mut bw_img, &BiLevel);
-            bw_img.write_to(&mut img_buf, img_fmt)
-        }
-        // Paint Red only Red. Otherwise transparent
-        OutputEnum::Red => {
-            let mut bw_img = map_pixels(&image, |_x, _y, p| {
-                let red = p.channels()[0];
-                let blue = p.channels()[2];
-                Luma([(red & (!blue))])
-            });
-            dither(&mut bw_img, &BiLevel);
-            map_pixels(&bw_img, |_x, _y, p| {
-                let l = p.channels()[0];
-                Rgba([l, 0_u8, 0_u8, l])
             })
             .write_to(&mut img_buf, img_fmt)
         }
+        // Paint a colour ink only where it goes. Otherwise transparent
+        (_, Some(layer)) => RgbaImage::from_fn(width, height, |x, y| match ink(x, y) == layer {
+            true => layer.rgb().to_rgba(),
+            false => Rgba([0, 0, 0, 0]),
+        })
+        .write_to(&mut img_buf, img_fmt),
     } {
-        return ApiError::InternalError(e.into()).into_response();
+        return Err(ApiError::InternalError(e.into()));
+    }
+
+    img_buf
+        .into_inner()
+        .map(|ib| (ib.into_inner(), valid_until))
+        .map_err(|e| ApiError::InternalError(e.into()))
+}
+
+pub async fn epaper_page(
+    State(state): State<AppState>,
+    Query(q): Query<QueryRouteEPaperModel>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    // Per-request zone override, falling back to the configured one
+    let tz = match q.tz.as_deref() {
+        Some(tz_name) => match timezones::get_by_name(tz_name) {
+            Some(tz) => tz,
+            None => {
+                return ApiError::InvalidRequest(format!("Unknown time zone: {tz_name}"))
+                    .into_response();
+            }
+        },
+        None => state.tz,
+    };
+    let time_utc = OffsetDateTime::now_utc();
+    let last_update = *state.last_update.read().await;
+    // Read before the data, so a change made while drawing is drawn again
+    let generation = state.generation.get();
+    // Sources whose data is too old to be trusted
+    let stale_sources = state
+        .status
+        .read()
+        .await
+        .iter()
+        .filter(|(name, s)| s.is_stale(time_utc, state.cfg.stale_after(name)))
+        .map(|(name, _)| name.clone())
+        .collect::<BTreeSet<_>>();
+    let Some((panel_name, panel)) = state.cfg.panel(q.panel.as_deref(), q.device.as_deref()) else {
+        return ApiError::InvalidRequest(format!(
+            "Unknown panel profile: {}",
+            q.panel.as_deref().unwrap_or_default()
+        ))
+        .into_response();
+    };
+    if let Some(ink) = q.output.ink().filter(|ink| !panel.palette.contains(ink)) {
+        return ApiError::InvalidRequest(format!("The {panel_name} panel has no {ink} ink"))
+            .into_response();
     }
+    let drawn = RenderState {
+        last_update,
+        generation,
+        stale_sources,
+        page: state.page.read().await.clone(),
+    };
 
-    let img_vec = match img_buf.into_inner().map(|ib| ib.into_inner()) {
-        Err(e) => {
-            return ApiError::InternalError(e.into()).into_response();
+    // Rendered again once the data, the date, the stale sources or the layout change
+    let key = RenderKey {
+        output: q.output,
+        format: q.format,
+        tz: tz.name(),
+        panel: panel_name.to_string(),
+        dither: q.dither,
+        rle: q.rle,
+    };
+    let cached = state
+        .render_cache
+        .read()
+        .await
+        .get(&key)
+        .filter(|page| page.is_valid(&drawn, time_utc))
+        .cloned();
+    let page = match cached {
+        Some(page) => page,
+        None => {
+            let (body, valid_until) = match render(&state, &q, tz, time_utc, &drawn, panel).await {
+                Ok(rendered) => rendered,
+                Err(e) => return e.into_response(),
+            };
+            let page = RenderedPage::new(body.into(), drawn.clone(), valid_until);
+            let mut cache = state.render_cache.write().await;
+            // Pages that can no longer be sent go, then the ones expiring first
+            cache.retain(|_, page| page.is_valid(&drawn, time_utc));
+            while cache.len() >= RENDER_CACHE_CAP {
+                let Some(oldest) = cache
+                    .iter()
+                    .min_by_key(|(_, page)| page.valid_until)
+                    .map(|(key, _)| key.clone())
+                else {
+                    break;
+                };
+                cache.remove(&oldest);
+            }
+            cache.insert(key, page.clone());
+            page
         }
-        Ok(img_vec) => img_vec,
     };
 
-    let mut res = img_vec.into_response();
-    res.headers_mut().insert(
-        axum::http::header::CONTENT_TYPE,
-        HeaderValue::from_static(match img_fmt {
-            ImageFormat::Jpeg => "image/jpeg",
-            ImageFormat::Gif => "image/gif",
-            ImageFormat::Bmp => "image/bmp",
+    let last_modified = last_update
+        .assume_utc()
+        .format(HTTP_DATE)
+        .unwrap_or_default();
+    let mut res_headers = HeaderMap::new();
+    res_headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static(match q.format {
+            QueryRouteEPaperFormatEnum::Bmp => "image/bmp",
+            QueryRouteEPaperFormatEnum::Epd => "application/octet-stream",
             _ => "image/png",
         }),
     );
-    res
+    if let Ok(etag) = HeaderValue::from_str(&page.etag) {
+        res_headers.insert(header::ETAG, etag);
+    }
+    if let Ok(last_modified) = HeaderValue::from_str(&last_modified) {
+        res_headers.insert(header::LAST_MODIFIED, last_modified);
+    }
+
+    let not_modified = headers
+        .get_all(header::IF_NONE_MATCH)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(|tag| tag.trim().trim_start_matches("W/"))
+        .any(|tag| tag == "*" || tag == page.etag);
+    if not_modified {
+        return (StatusCode::NOT_MODIFIED, res_headers).into_response();
+    }
+
+    (res_headers, page.body).into_response()
 }
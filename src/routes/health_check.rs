@@ -1,10 +1,89 @@
-use axum::{Json, extract::State};
+use std::collections::BTreeMap;
+
+use axum::{
+    Json,
+    extract::State,
+    http::{HeaderMap, Uri},
+};
+use serde::Serialize;
 use serde_json::{Value, json};
+use time::OffsetDateTime;
+
+use crate::{AppState, api_error::ApiError, cron, middleware, model::SourceStatus};
+
+#[derive(Serialize)]
+pub struct HealthSource {
+    #[serde(flatten)]
+    pub status: SourceStatus,
+    /// Seconds since the last successful fetch.
+    pub data_age: Option<i64>,
+    pub stale: bool,
+    /// The source is skipped until `open_until`, having failed too often.
+    pub circuit_open: bool,
+}
+
+#[derive(Serialize)]
+pub struct Health {
+    /// `ok`, or `degraded` when the data of any source is stale or its circuit
+    /// is open.
+    pub status: &'static str,
+    #[serde(with = "time::serde::rfc3339")]
+    pub last_update: OffsetDateTime,
+    /// Seconds since the page data last changed.
+    pub data_age: i64,
+    pub sources: BTreeMap<String, HealthSource>,
+}
+
+/// Reports the freshness of every source. Errors are only shown with the access token.
+pub async fn health_check(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    uri: Uri,
+) -> Result<Json<Health>, ApiError> {
+    let now = OffsetDateTime::now_utc();
+    let authorized = middleware::is_authorized(&state, &headers, &uri);
+    let last_update = state.last_update.read().await.assume_utc();
+    let status = state.status.read().await;
+
+    let expected = state
+        .cfg
+        .calendar_sources
+        .iter()
+        .map(|s| s.name.as_str())
+        .chain([cron::HA_STATES_SOURCE, cron::FORECAST_SOURCE])
+        .chain((!state.cfg.ha_todo_entities.is_empty()).then_some(cron::TODO_SOURCE));
+    let sources = expected
+        .chain(status.keys().map(String::as_str))
+        .map(|name| {
+            let mut status = status.get(name).cloned().unwrap_or_default();
+            let stale = status.is_stale(now, state.cfg.stale_after(name));
+            let data_age = status.last_success.map(|t| (now - t).whole_seconds());
+            let circuit_open = status.is_open(now);
+            if !authorized {
+                status.last_error = None;
+            }
 
-use crate::{AppState, api_error::ApiError};
+            (
+                name.to_string(),
+                HealthSource {
+                    status,
+                    data_age,
+                    stale,
+                    circuit_open,
+                },
+            )
+        })
+        .collect::<BTreeMap<_, _>>();
 
-pub async fn health_check() -> Result<Json<Value>, ApiError> {
-    Ok(Json(json!({ "status": "ok" })))
+    Ok(Json(Health {
+        status: match sources.values().any(|s| s.stale || s.circuit_open) {
+            true => "degraded",
+            false => "ok",
+        },
+        last_update,
+        data_age: (now - last_update).whole_seconds(),
+        sources,
+    }))
 }
 
 pub async fn last_update(State(state): State<AppState>) -> Result<String, ApiError> {
@@ -1,7 +1,12 @@
-use axum::{Router, routing::get};
+use axum::{
+    Router,
+    routing::{get, post},
+};
 
+pub mod admin;
 pub mod epaper_page;
 pub mod health_check;
+pub mod refresh;
 
 use crate::AppState;
 
@@ -10,5 +15,8 @@ pub fn router() -> Router<AppState> {
         .route("/health_check", get(health_check::health_check))
         .route("/last_update", get(health_check::last_update))
         .route("/epaper_page", get(epaper_page::epaper_page))
+        .route("/refresh", post(refresh::refresh))
+        .route("/webhook/home_assistant", post(refresh::webhook))
         .route("/test", get(health_check::test))
+        .route("/admin/schedules", get(admin::schedules))
 }
@@ -0,0 +1,74 @@
+use axum::{Json, body::Bytes, extract::State, http::StatusCode};
+use serde::{Deserialize, de::DeserializeOwned};
+
+use crate::{AppState, api_error::ApiError, cron, model::RefreshResult};
+
+#[derive(Deserialize, Debug, Default)]
+pub struct RefreshReq {
+    /// Source names. Every source when empty.
+    #[serde(default)]
+    pub sources: Vec<String>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct WebhookReq {
+    /// Entity that changed. Every source when missing.
+    #[serde(default)]
+    pub entity_id: Option<String>,
+}
+
+/// Reads an optional JSON body, an empty body being the default.
+fn optional_json<T: DeserializeOwned + Default>(body: &Bytes) -> Result<T, ApiError> {
+    if body.iter().all(u8::is_ascii_whitespace) {
+        return Ok(T::default());
+    }
+
+    serde_json::from_slice(body).map_err(|e| ApiError::InvalidRequest(e.to_string()))
+}
+
+/// Starts fetching the requested sources right away.
+pub async fn refresh(
+    State(state): State<AppState>,
+    body: Bytes,
+) -> Result<(StatusCode, Json<RefreshResult>), ApiError> {
+    let req = optional_json::<RefreshReq>(&body)?;
+    let res = cron::refresh(
+        state.cfg.clone(),
+        state.db.clone(),
+        state.shared(),
+        &req.sources,
+    )
+    .await?;
+
+    Ok((StatusCode::ACCEPTED, Json(res)))
+}
+
+/// Starts fetching the sources reading the entity a Home Assistant automation
+/// reports as changed.
+pub async fn webhook(
+    State(state): State<AppState>,
+    body: Bytes,
+) -> Result<(StatusCode, Json<RefreshResult>), ApiError> {
+    let req = optional_json::<WebhookReq>(&body)?;
+    let sources = match req.entity_id {
+        Some(entity_id) => {
+            let sources = cron::entity_sources(&state.cfg, &entity_id);
+            if sources.is_empty() {
+                return Err(ApiError::InvalidRequest(format!(
+                    "No source reads {entity_id}"
+                )));
+            }
+            sources
+        }
+        None => Vec::new(),
+    };
+    let res = cron::refresh(
+        state.cfg.clone(),
+        state.db.clone(),
+        state.shared(),
+        &sources,
+    )
+    .await?;
+
+    Ok((StatusCode::ACCEPTED, Json(res)))
+}
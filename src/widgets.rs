@@ -0,0 +1,897 @@
+//! The widgets of the e-paper page and the layout they are drawn in.
+use ab_glyph::{FontRef, InvalidFont, PxScale};
+use imageproc::{drawing, image::Rgb};
+use itertools::Itertools;
+use serde::Deserialize;
+use std::sync::LazyLock;
+use time::{Month, OffsetDateTime};
+use time_tz::{OffsetDateTimeExt, Tz};
+
+use crate::{
+    CalendarColor, HaSensor,
+    layout::{Area, Color, Node, Overflow, Padding, Scale, Size, Surface, Widget},
+    model::{
+        CalendarMap, HaEntityState, TodoDue, TodoItem, WeatherForecastEntry, WeatherInfo,
+        WeatherInfoState,
+    },
+};
+
+/// The widgets a layout can place.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WidgetKind {
+    DateBlock,
+    Weather,
+    Agenda,
+    Todo,
+    Sensors,
+    Footer,
+}
+
+/// The page as it has always looked: the date on the left, weather, agenda,
+/// to-dos and sensors on the right, and the last update at the bottom.
+pub fn default_layout() -> Node {
+    Node::column(vec![
+        Node::row(vec![
+            Node::widget(WidgetKind::DateBlock)
+                .width(Size::Fixed(130))
+                .padding(Padding {
+                    top: 10,
+                    left: 10,
+                    ..Default::default()
+                }),
+            Node::column(vec![
+                Node::widget(WidgetKind::Weather)
+                    .height(Size::Fixed(50))
+                    .padding(Padding {
+                        left: 10,
+                        bottom: 10,
+                        ..Default::default()
+                    }),
+                Node::widget(WidgetKind::Agenda),
+                Node::widget(WidgetKind::Todo)
+                    .height(Size::Auto)
+                    .background(Color::White),
+                Node::widget(WidgetKind::Sensors)
+                    .height(Size::Auto)
+                    .background(Color::White),
+            ])
+            .padding(Padding {
+                top: 10,
+                right: 10,
+                left: 10,
+                ..Default::default()
+            })
+            .overflow(Overflow::Clip),
+        ]),
+        Node::widget(WidgetKind::Footer)
+            .height(Size::Fixed(25))
+            .padding(Padding {
+                top: 5,
+                left: 10,
+                ..Default::default()
+            })
+            .background(Color::Gray),
+    ])
+    .background(Color::White)
+}
+
+/// What the widgets draw with, set by the `theme` of a layout file.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Theme {
+    pub colors: ThemeColors,
+    pub font_sizes: FontSizes,
+    pub max_chars: MaxChars,
+}
+
+/// Colours by what they are used for.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ThemeColors {
+    pub text: Color,
+    pub background: Color,
+    /// The date block and the date headers of working days.
+    pub header: Color,
+    /// Text on the date block and the date headers.
+    pub header_text: Color,
+    /// The date block, date headers and events of days off.
+    pub holiday: Color,
+    /// Behind each event.
+    pub event_row: Color,
+    /// Events of calendars configured `red`.
+    pub red_calendar: Color,
+    pub overdue: Color,
+    /// The mark on widgets whose data is stale.
+    pub stale: Color,
+}
+
+impl Default for ThemeColors {
+    fn default() -> Self {
+        Self {
+            text: Color::Black,
+            background: Color::White,
+            header: Color::Black,
+            header_text: Color::White,
+            holiday: Color::Red,
+            event_row: Color::Gray,
+            red_calendar: Color::Red,
+            overdue: Color::Red,
+            stale: Color::Red,
+        }
+    }
+}
+
+/// Font sizes in pixels.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FontSizes {
+    /// Width of the day digits, drawn half as tall again.
+    pub day: u32,
+    pub month: u32,
+    pub year: u32,
+    pub weather_icon: u32,
+    pub temperature: u32,
+    pub forecast: u32,
+    pub agenda: u32,
+    pub todo: u32,
+    pub sensor: u32,
+    pub footer: u32,
+}
+
+impl Default for FontSizes {
+    fn default() -> Self {
+        Self {
+            day: 90,
+            month: 60,
+            year: 90,
+            weather_icon: 45,
+            temperature: 30,
+            forecast: 9,
+            agenda: 16,
+            todo: 13,
+            sensor: 12,
+            footer: 12,
+        }
+    }
+}
+
+/// Characters shown before a text is cut with an ellipsis.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MaxChars {
+    /// A date header with its holidays.
+    pub holiday: usize,
+    pub event: usize,
+    /// Name of a multi-day event, before its `(day/days)`.
+    pub multi_day_event: usize,
+    pub todo: usize,
+    /// A sensor label with its value.
+    pub sensor: usize,
+}
+
+impl Default for MaxChars {
+    fn default() -> Self {
+        Self {
+            holiday: 32,
+            event: 32,
+            multi_day_event: 26,
+            todo: 28,
+            sensor: 18,
+        }
+    }
+}
+
+impl Theme {
+    /// The same theme with every colour replaced by `ink(colour)`.
+    pub fn recolored(&self, ink: impl Fn(Color) -> Color) -> Self {
+        let c = &self.colors;
+
+        Self {
+            colors: ThemeColors {
+                text: ink(c.text),
+                background: ink(c.background),
+                header: ink(c.header),
+                header_text: ink(c.header_text),
+                holiday: ink(c.holiday),
+                event_row: ink(c.event_row),
+                red_calendar: ink(c.red_calendar),
+                overdue: ink(c.overdue),
+                stale: ink(c.stale),
+            },
+            ..self.clone()
+        }
+    }
+
+    /// Sizes and lengths that cannot be drawn.
+    pub fn validate(&self) -> Result<(), String> {
+        let f = &self.font_sizes;
+        let m = &self.max_chars;
+        let zero = [
+            ("font_sizes.day", f.day as usize),
+            ("font_sizes.month", f.month as usize),
+            ("font_sizes.year", f.year as usize),
+            ("font_sizes.weather_icon", f.weather_icon as usize),
+            ("font_sizes.temperature", f.temperature as usize),
+            ("font_sizes.forecast", f.forecast as usize),
+            ("font_sizes.agenda", f.agenda as usize),
+            ("font_sizes.todo", f.todo as usize),
+            ("font_sizes.sensor", f.sensor as usize),
+            ("font_sizes.footer", f.footer as usize),
+            ("max_chars.holiday", m.holiday),
+            ("max_chars.event", m.event),
+            ("max_chars.multi_day_event", m.multi_day_event),
+            ("max_chars.todo", m.todo),
+            ("max_chars.sensor", m.sensor),
+        ]
+        .into_iter()
+        .filter(|(_, v)| *v == 0)
+        .map(|(name, _)| name)
+        .collect_vec();
+
+        match zero.is_empty() {
+            true => Ok(()),
+            false => Err(format!("{} must not be 0", zero.join(", "))),
+        }
+    }
+}
+
+/// A font size in layout pixels, as drawn at `scale`.
+fn font(scale: Scale, px: u32) -> PxScale {
+    PxScale::from(px as f32 * scale.0)
+}
+
+pub struct Fonts {
+    pub anta: FontRef<'static>,
+    pub chakra_r: FontRef<'static>,
+    pub chakra_b: FontRef<'static>,
+    pub chakra_sb: FontRef<'static>,
+    pub material: FontRef<'static>,
+}
+
+/// Fonts are parsed on the first render only.
+pub static FONTS: LazyLock<Result<Fonts, InvalidFont>> = LazyLock::new(|| {
+    Ok(Fonts {
+        anta: FontRef::try_from_slice(include_bytes!("../fonts/Anta/Anta-Regular.ttf"))?,
+        chakra_r: FontRef::try_from_slice(include_bytes!(
+            "../fonts/Chakra_Petch/ChakraPetch-Regular.ttf"
+        ))?,
+        chakra_b: FontRef::try_from_slice(include_bytes!(
+            "../fonts/Chakra_Petch/ChakraPetch-Bold.ttf"
+        ))?,
+        chakra_sb: FontRef::try_from_slice(include_bytes!(
+            "../fonts/Chakra_Petch/ChakraPetch-SemiBold.ttf"
+        ))?,
+        material: FontRef::try_from_slice(include_bytes!(
+            "../fonts/materialdesignicons-webfont.ttf"
+        ))?,
+    })
+});
+
+pub fn month_abbr(mth: Month) -> String {
+    match mth {
+        Month::January => "Jan",
+        Month::February => "Feb",
+        Month::March => "Mar",
+        Month::April => "Apr",
+        Month::May => "May",
+        Month::June => "Jun",
+        Month::July => "Jul",
+        Month::August => "Aug",
+        Month::September => "Sep",
+        Month::October => "Oct",
+        Month::November => "Nov",
+        Month::December => "Dec",
+    }
+    .to_string()
+    .to_uppercase()
+}
+
+/// Material Design Icons glyph of a Home Assistant weather condition
+fn weather_icon(state: Option<&WeatherInfoState>) -> &'static str {
+    match state {
+        Some(WeatherInfoState::Cloudy) => "\u{0F0590}",
+        Some(WeatherInfoState::Fog) => "\u{0F0591}",
+        Some(WeatherInfoState::Hail) => "\u{0F0592}",
+        Some(WeatherInfoState::Lightning) => "\u{0F0593}",
+        Some(WeatherInfoState::LightningRainy) => "\u{0F067E}",
+        Some(WeatherInfoState::ClearNight) => "\u{0F0594}",
+        Some(WeatherInfoState::Partlycloudy) => "\u{0F0595}",
+        Some(WeatherInfoState::Pouring) => "\u{0F0596}",
+        Some(WeatherInfoState::Rainy) => "\u{0F0597}",
+        Some(WeatherInfoState::Snowy) => "\u{0F0598}",
+        Some(WeatherInfoState::SnowyRainy) => "\u{0F067F}",
+        Some(WeatherInfoState::Sunny) => "\u{0F0599}",
+        Some(WeatherInfoState::Windy) => "\u{0F059D}",
+        Some(WeatherInfoState::WindyVariant) => "\u{0F059E}",
+        Some(WeatherInfoState::Exceptional) => "?",
+        Some(WeatherInfoState::Unknown) => "?",
+        None => "?",
+    }
+}
+
+/// Left of something `w` wide at the right of `area`, or the left of `area`
+/// when it is narrower than that.
+fn from_right(area: Area, w: u32) -> u32 {
+    area.right().saturating_sub(w).max(area.x)
+}
+
+/// Draws a warning glyph at `x`, `y` on a widget whose data is stale.
+fn draw_stale_mark(
+    surface: &mut Surface<'_>,
+    font: &FontRef<'_>,
+    x: u32,
+    y: u32,
+    color: Rgb<u8>,
+    background: Rgb<u8>,
+) {
+    let s = surface.scale();
+    let size = s.px(14);
+
+    surface.fill(Area::new(x, y, size, size), background);
+    drawing::draw_text_mut(
+        surface,
+        color,
+        x as i32,
+        y as i32,
+        self::font(s, 14),
+        font,
+        "\u{F0026}",
+    );
+}
+
+pub fn substr_th(str: String, len: usize) -> String {
+    let mut cnt = 0_usize;
+
+    if str.chars().count() <= len {
+        return str;
+    }
+
+    let mut res_str = str
+        .chars()
+        .take_while_inclusive(|c| {
+            if matches!(c, 'ั' | 'ุ' ..= '\u{E3E}' | '็' ..= '๎') {
+                return true;
+            }
+
+            cnt += 1;
+
+            cnt <= len
+        })
+        .collect::<String>();
+
+    res_str.push('…');
+
+    res_str
+}
+
+/// Today's day on a black or red block, dotted when there are events, with
+/// the month and the year below.
+pub struct DateBlock<'a> {
+    pub fonts: &'static Fonts,
+    pub theme: &'a Theme,
+    pub time: OffsetDateTime,
+    pub is_holiday: bool,
+    pub is_event: bool,
+}
+
+impl Widget for DateBlock<'_> {
+    fn draw(&self, surface: &mut Surface<'_>, area: Area) {
+        let colors = &self.theme.colors;
+        let sizes = &self.theme.font_sizes;
+        let s = surface.scale();
+        let inset = s.px(10);
+        let day_box = Area::new(area.x + inset, area.y, s.px(90), s.px(120));
+        surface.fill(
+            day_box,
+            match self.is_holiday {
+                true => colors.holiday,
+                false => colors.header,
+            }
+            .rgb(),
+        );
+
+        if self.is_event {
+            // draw dots
+            let step = s.px(4).max(1) as usize;
+            (0..day_box.w).step_by(step).for_each(|x| {
+                (0..day_box.h).step_by(step).for_each(|y| {
+                    drawing::draw_cross_mut(
+                        surface,
+                        colors.header_text.rgb(),
+                        (day_box.x + x) as i32,
+                        (day_box.y + y) as i32,
+                    );
+                });
+            });
+        }
+
+        let day_str = self.time.day().to_string();
+        let day_scale = PxScale {
+            x: sizes.day as f32 * s.0,
+            y: sizes.day as f32 * 1.5 * s.0,
+        };
+        let (day_txt_w, _) = drawing::text_size(day_scale, &self.fonts.anta, &day_str);
+        drawing::draw_text_mut(
+            surface,
+            colors.header_text.rgb(),
+            (area.x + u32::max(s.px(7), day_box.w.abs_diff(day_txt_w) / 2)) as i32,
+            area.y as i32,
+            day_scale,
+            &self.fonts.anta,
+            &day_str,
+        );
+        // Month
+        let mth_str = month_abbr(self.time.month());
+        let mth_scale = font(s, sizes.month);
+        let (mth_txt_w, mth_txt_h) = drawing::text_size(mth_scale, &self.fonts.anta, &mth_str);
+        drawing::draw_text_mut(
+            surface,
+            colors.text.rgb(),
+            (area.x + (mth_txt_w.abs_diff(day_box.w) / 2)) as i32,
+            day_box.bottom() as i32,
+            mth_scale,
+            &self.fonts.anta,
+            &mth_str,
+        );
+        // Year
+        let yr_str = (self.time.year() % 100).to_string();
+        let yr_scale = font(s, sizes.year);
+        let (yr_txt_w, _) = drawing::text_size(yr_scale, &self.fonts.anta, &yr_str);
+        drawing::draw_text_mut(
+            surface,
+            colors.text.rgb(),
+            (area.x + (yr_txt_w.abs_diff(day_box.w + inset * 2) / 2)) as i32,
+            (day_box.bottom() + mth_txt_h + inset) as i32,
+            yr_scale,
+            &self.fonts.anta,
+            &yr_str,
+        );
+    }
+}
+
+/// Current condition and temperature, then a forecast strip of as many
+/// columns as fit.
+pub struct Weather<'a> {
+    pub fonts: &'static Fonts,
+    pub theme: &'a Theme,
+    pub current: Option<WeatherInfo>,
+    /// Upcoming days or hours, by their label.
+    pub forecast: Vec<(String, WeatherForecastEntry)>,
+    pub stale: bool,
+}
+
+impl Widget for Weather<'_> {
+    // See: https://community.home-assistant.io/t/display-materialdesign-icons-on-esphome-attached-to-screen/199790/16
+    fn draw(&self, surface: &mut Surface<'_>, area: Area) {
+        let colors = &self.theme.colors;
+        let sizes = &self.theme.font_sizes;
+        let text = colors.text.rgb();
+        let s = surface.scale();
+        drawing::draw_text_mut(
+            surface,
+            text,
+            area.x as i32,
+            area.y as i32,
+            font(s, sizes.weather_icon),
+            &self.fonts.material,
+            weather_icon(self.current.as_ref().map(|w| &w.state)),
+        );
+        let mut forecast_x = area.x + s.px(sizes.weather_icon + 10);
+        if let Some(temperature) = self.current.as_ref().and_then(|w| w.attributes.temperature) {
+            let temperature_unit = self
+                .current
+                .as_ref()
+                .and_then(|w| w.attributes.temperature_unit.as_deref())
+                .unwrap_or_default();
+            let temperature_scale = font(s, sizes.temperature);
+            let temperature_txt = format! {"{:.1}{}", temperature, temperature_unit};
+            let (temperature_w, _) =
+                drawing::text_size(temperature_scale, &self.fonts.anta, &temperature_txt);
+            drawing::draw_text_mut(
+                surface,
+                text,
+                forecast_x as i32,
+                (area.y + s.px(sizes.weather_icon.abs_diff(sizes.temperature) / 2)) as i32,
+                temperature_scale,
+                &self.fonts.anta,
+                &temperature_txt,
+            );
+            forecast_x += temperature_w + s.px(20);
+        }
+
+        let col_w = s.px(32);
+        let fnt_scale = font(s, sizes.forecast);
+
+        for (label, entry) in &self.forecast {
+            if forecast_x + col_w > area.right() {
+                break;
+            }
+
+            let temps = match (entry.temperature, entry.templow) {
+                (Some(high), Some(low)) => format! {"{:.0}/{:.0}", high, low},
+                (Some(temp), None) => format! {"{:.0}", temp},
+                _ => "-".to_string(),
+            };
+            let lines = [
+                (area.y, label.to_uppercase()),
+                (area.y + s.px(23), temps),
+                (
+                    area.y + s.px(32),
+                    entry
+                        .precipitation_probability
+                        .map(|p| format! {"{:.0}%", p})
+                        .unwrap_or_default(),
+                ),
+            ];
+
+            for (y, txt) in lines {
+                drawing::draw_text_mut(
+                    surface,
+                    text,
+                    forecast_x as i32,
+                    y as i32,
+                    fnt_scale,
+                    &self.fonts.chakra_sb,
+                    &txt,
+                );
+            }
+            drawing::draw_text_mut(
+                surface,
+                text,
+                forecast_x as i32,
+                (area.y + s.px(9)) as i32,
+                font(s, 14),
+                &self.fonts.material,
+                weather_icon(entry.condition.as_ref()),
+            );
+            forecast_x += col_w;
+        }
+
+        if self.stale {
+            draw_stale_mark(
+                surface,
+                &self.fonts.material,
+                area.x + s.px(33),
+                area.y + s.px(33),
+                colors.stale.rgb(),
+                colors.background.rgb(),
+            );
+        }
+    }
+}
+
+/// Upcoming dates with their holidays and events, as many as fit.
+pub struct Agenda<'a> {
+    pub fonts: &'static Fonts,
+    pub theme: &'a Theme,
+    pub calendar: CalendarMap,
+    pub stale: bool,
+}
+
+impl Widget for Agenda<'_> {
+    fn draw(&self, surface: &mut Surface<'_>, area: Area) {
+        let colors = &self.theme.colors;
+        let max_chars = &self.theme.max_chars;
+        let s = surface.scale();
+        let fnt_sz = s.px(self.theme.font_sizes.agenda);
+        let fnt_scale = font(s, self.theme.font_sizes.agenda);
+        let row_h = (fnt_sz as f32 * 1.5) as u32;
+        // Rows start at least this far above the bottom
+        let room = |y: u32| y + s.px(15) <= area.bottom();
+        let mut y = area.y;
+        let mut stale_mark = self.stale;
+
+        for (c_date, c_info) in &self.calendar {
+            let is_holiday = c_info.is_holiday();
+            let c_date_cln = c_date.to_calendar_date();
+            let mut date_txt = format! {
+                "{} {} {}",
+                c_date_cln.0, month_abbr(c_date_cln.1), c_date_cln.2
+            };
+
+            if !c_info.holidays.is_empty() {
+                let hld_txt = c_info.holidays.iter().map(|h| h.name.as_str()).join(", ");
+                date_txt.push_str(&format! {"—{hld_txt}"});
+                date_txt = substr_th(date_txt, max_chars.holiday);
+            }
+
+            // Date header
+            let header_color = match is_holiday {
+                true => colors.holiday.rgb(),
+                false => colors.header.rgb(),
+            };
+            surface.fill(Area::new(area.x, y, area.w, row_h), header_color);
+            drawing::draw_text_mut(
+                surface,
+                colors.header_text.rgb(),
+                (area.x + s.px(10)) as i32,
+                (y + ((fnt_sz as f32 * 0.5) / 2.0) as u32) as i32,
+                fnt_scale,
+                &self.fonts.chakra_b,
+                &date_txt,
+            );
+            // On the first date header only
+            if stale_mark {
+                draw_stale_mark(
+                    surface,
+                    &self.fonts.material,
+                    from_right(area, s.px(18)),
+                    y + s.px(5),
+                    colors.header_text.rgb(),
+                    header_color,
+                );
+                stale_mark = false;
+            }
+            y += row_h;
+
+            if !room(y) {
+                break;
+            }
+
+            // All-day events first, then by start time
+            let events = c_info
+                .events
+                .iter()
+                .sorted_by_key(|(uid, e)| (!e.all_day, e.time, &e.name, *uid))
+                .map(|(_, e)| e);
+
+            for event in events {
+                let event_name = match event.days {
+                    0 | 1 => substr_th(event.name.clone(), max_chars.event),
+                    days => format! {
+                        "{} ({}/{})",
+                        substr_th(event.name.clone(), max_chars.multi_day_event), event.day, days
+                    },
+                };
+                // Multi-day events show their start on the first day and their end on the last
+                let event_time = match (event.all_day, event.day, event.day == event.days) {
+                    (false, 1, _) => {
+                        format! {"{:02}:{:02}", event.time.hour(), event.time.minute()}
+                    }
+                    (false, _, true) => {
+                        format! {"-{:02}:{:02}", event.end.hour(), event.end.minute()}
+                    }
+                    _ => "All day".to_string(),
+                };
+                let (event_time_w, _) =
+                    drawing::text_size(fnt_scale, &self.fonts.chakra_r, &event_time);
+                let event_name_x = u32::max((fnt_scale.x * 2.5) as u32, event_time_w + s.px(5));
+
+                // Draw box for better visibility on ePaper
+                surface.fill(Area::new(area.x, y, area.w, row_h), colors.event_row.rgb());
+                // Red sources keep their colour on any date
+                let event_color = match (is_holiday, event.color == CalendarColor::Red) {
+                    (true, _) => colors.holiday,
+                    (false, true) => colors.red_calendar,
+                    (false, false) => colors.text,
+                }
+                .rgb();
+                drawing::draw_text_mut(
+                    surface,
+                    event_color,
+                    (area.x + s.px(10)) as i32,
+                    (y + s.px(5)) as i32,
+                    fnt_scale,
+                    &self.fonts.chakra_r,
+                    &event_time,
+                );
+                drawing::draw_text_mut(
+                    surface,
+                    event_color,
+                    (area.x + s.px(10) + event_name_x) as i32,
+                    (y + s.px(5)) as i32,
+                    fnt_scale,
+                    // _r is too slim when render
+                    &self.fonts.chakra_sb,
+                    &event_name,
+                );
+                y += fnt_sz + s.px(5);
+
+                if !room(y) {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// Open to-do items with their due date, overdue ones in red.
+pub struct Todo<'a> {
+    pub fonts: &'static Fonts,
+    pub theme: &'a Theme,
+    pub items: Vec<TodoItem>,
+    pub now: OffsetDateTime,
+    pub tz: &'static Tz,
+    pub stale: bool,
+}
+
+impl Todo<'_> {
+    const ROW_H: u32 = 16;
+}
+
+impl Widget for Todo<'_> {
+    fn measure(&self, width: u32, _height: u32, scale: Scale) -> (u32, u32) {
+        (width, self.items.len() as u32 * scale.px(Self::ROW_H))
+    }
+
+    fn draw(&self, surface: &mut Surface<'_>, area: Area) {
+        let colors = &self.theme.colors;
+        let s = surface.scale();
+        let fnt_scale = font(s, self.theme.font_sizes.todo);
+        let today = self.now.to_timezone(self.tz).date();
+
+        for (idx, item) in self.items.iter().enumerate() {
+            let row_y = area.y + (idx as u32 * s.px(Self::ROW_H));
+            // Overdue items go to the red layer
+            let item_color = match item.is_overdue(self.now, self.tz) {
+                true => colors.overdue,
+                false => colors.text,
+            }
+            .rgb();
+            let due_txt = match item.due() {
+                Some(TodoDue::DateTime(dt)) => {
+                    let dt = dt.to_timezone(self.tz);
+                    match dt.date() == today {
+                        true => format! {"{:02}:{:02}", dt.hour(), dt.minute()},
+                        false => format! {"{} {}", dt.day(), month_abbr(dt.month())},
+                    }
+                }
+                Some(TodoDue::Date(d)) => format! {"{} {}", d.day(), month_abbr(d.month())},
+                None => String::new(),
+            };
+            let (due_w, _) = drawing::text_size(fnt_scale, &self.fonts.chakra_sb, &due_txt);
+            // The first row makes room for the stale mark
+            let due_r = match self.stale && idx == 0 {
+                true => {
+                    draw_stale_mark(
+                        surface,
+                        &self.fonts.material,
+                        from_right(area, s.px(14)),
+                        row_y + s.px(1),
+                        colors.stale.rgb(),
+                        colors.background.rgb(),
+                    );
+                    from_right(area, s.px(16))
+                }
+                false => area.right(),
+            };
+
+            // Checkbox
+            drawing::draw_text_mut(
+                surface,
+                item_color,
+                area.x as i32,
+                (row_y + s.px(1)) as i32,
+                font(s, 14),
+                &self.fonts.material,
+                "\u{F0131}",
+            );
+            drawing::draw_text_mut(
+                surface,
+                item_color,
+                (area.x + s.px(16)) as i32,
+                (row_y + s.px(2)) as i32,
+                fnt_scale,
+                &self.fonts.chakra_sb,
+                &substr_th(item.summary.clone(), self.theme.max_chars.todo),
+            );
+            drawing::draw_text_mut(
+                surface,
+                item_color,
+                due_r.saturating_sub(due_w).max(area.x) as i32,
+                (row_y + s.px(2)) as i32,
+                fnt_scale,
+                &self.fonts.chakra_sb,
+                &due_txt,
+            );
+        }
+    }
+}
+
+/// Home Assistant sensors, two per row.
+pub struct Sensors<'a> {
+    pub fonts: &'static Fonts,
+    pub theme: &'a Theme,
+    /// Each sensor with its current state, if any.
+    pub sensors: Vec<(HaSensor, Option<HaEntityState>)>,
+    pub stale: bool,
+}
+
+impl Sensors<'_> {
+    const ROW_H: u32 = 18;
+    const MAX_ROWS: usize = 3;
+
+    fn rows(&self) -> u32 {
+        self.sensors.len().div_ceil(2).min(Self::MAX_ROWS) as u32
+    }
+}
+
+impl Widget for Sensors<'_> {
+    fn measure(&self, width: u32, _height: u32, scale: Scale) -> (u32, u32) {
+        (width, self.rows() * scale.px(Self::ROW_H))
+    }
+
+    fn draw(&self, surface: &mut Surface<'_>, area: Area) {
+        let colors = &self.theme.colors;
+        let s = surface.scale();
+        let col_w = area.w / 2;
+        let icon_sz = 14;
+
+        for (idx, (sensor, state)) in self
+            .sensors
+            .iter()
+            .take((self.rows() * 2) as usize)
+            .enumerate()
+        {
+            let cell_x = area.x + (idx as u32 % 2) * col_w;
+            let cell_y = area.y + (idx as u32 / 2) * s.px(Self::ROW_H);
+            let value = state
+                .as_ref()
+                .map(|s| s.format(sensor.format()))
+                .unwrap_or_else(|| "-".to_string());
+            let mut text_x = cell_x;
+
+            if let Some(icon) = sensor.icon_char() {
+                drawing::draw_text_mut(
+                    surface,
+                    colors.text.rgb(),
+                    cell_x as i32,
+                    (cell_y + s.px(1)) as i32,
+                    font(s, icon_sz),
+                    &self.fonts.material,
+                    &icon.to_string(),
+                );
+                text_x += s.px(icon_sz + 2);
+            }
+            drawing::draw_text_mut(
+                surface,
+                colors.text.rgb(),
+                text_x as i32,
+                (cell_y + s.px(3)) as i32,
+                font(s, self.theme.font_sizes.sensor),
+                &self.fonts.chakra_sb,
+                &substr_th(
+                    format! {"{} {}", sensor.label, value},
+                    self.theme.max_chars.sensor,
+                ),
+            );
+        }
+
+        if self.stale {
+            draw_stale_mark(
+                surface,
+                &self.fonts.material,
+                from_right(area, s.px(14)),
+                area.y + s.px(2),
+                colors.stale.rgb(),
+                colors.background.rgb(),
+            );
+        }
+    }
+}
+
+/// When the data was last updated.
+pub struct Footer<'a> {
+    pub fonts: &'static Fonts,
+    pub theme: &'a Theme,
+    pub last_update: OffsetDateTime,
+}
+
+impl Widget for Footer<'_> {
+    fn draw(&self, surface: &mut Surface<'_>, area: Area) {
+        let fnt_scale = font(surface.scale(), self.theme.font_sizes.footer);
+        drawing::draw_text_mut(
+            surface,
+            self.theme.colors.text.rgb(),
+            area.x as i32,
+            area.y as i32,
+            fnt_scale,
+            &self.fonts.chakra_sb,
+            &format! {
+                "Last update: {}",
+                self.last_update.replace_nanosecond(0).unwrap_or(self.last_update)
+            },
+        );
+    }
+}
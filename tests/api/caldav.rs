@@ -0,0 +1,144 @@
+use axum::{
+    Router,
+    body::Bytes,
+    http::{HeaderMap, Method, StatusCode},
+    routing::any,
+};
+use server::{
+    CalendarProvider, CalendarSource, CalendarSourceKind,
+    caldav::{calendar_data, calendar_query, fetch_events},
+};
+use time::macros::datetime;
+use tokio::net::TcpListener;
+
+const MULTISTATUS: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<multistatus xmlns="DAV:" xmlns:C="urn:ietf:params:xml:ns:caldav">
+  <response>
+    <href>/user/calendar/standup.ics</href>
+    <propstat>
+      <prop>
+        <getetag>"1"</getetag>
+        <C:calendar-data>BEGIN:VCALENDAR
+VERSION:2.0
+BEGIN:VEVENT
+UID:standup@example.com
+DTSTART:20260501T020000Z
+DTEND:20260501T023000Z
+SUMMARY:Standup &amp; review
+END:VEVENT
+END:VCALENDAR
+</C:calendar-data>
+      </prop>
+      <status>HTTP/1.1 200 OK</status>
+    </propstat>
+  </response>
+  <response>
+    <href>/user/calendar/trip.ics</href>
+    <propstat>
+      <prop>
+        <getetag>"2"</getetag>
+        <C:calendar-data><![CDATA[BEGIN:VCALENDAR
+VERSION:2.0
+BEGIN:VEVENT
+UID:trip@example.com
+DTSTART;VALUE=DATE:20260504
+DTEND;VALUE=DATE:20260506
+SUMMARY:Trip
+END:VEVENT
+END:VCALENDAR
+]]></C:calendar-data>
+      </prop>
+      <status>HTTP/1.1 200 OK</status>
+    </propstat>
+  </response>
+</multistatus>"#;
+
+/// Radicale-like stand-in: answers `REPORT` with two events, for user `alice` only.
+async fn fake_caldav() -> String {
+    async fn report(method: Method, headers: HeaderMap, body: Bytes) -> (StatusCode, String) {
+        // alice:secret
+        let authorized = headers
+            .get("authorization")
+            .is_some_and(|v| v == "Basic YWxpY2U6c2VjcmV0" || v == "Bearer token");
+
+        match (method.as_str(), authorized) {
+            (_, false) => (StatusCode::UNAUTHORIZED, String::new()),
+            ("REPORT", true) => {
+                let body = String::from_utf8_lossy(&body);
+                assert_eq!(headers.get("depth").unwrap(), "1");
+                assert!(body.contains("calendar-query"));
+                assert!(body.contains(r#"start="20260501T000000Z""#));
+                (StatusCode::MULTI_STATUS, MULTISTATUS.to_string())
+            }
+            _ => (StatusCode::METHOD_NOT_ALLOWED, String::new()),
+        }
+    }
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        axum::serve(
+            listener,
+            Router::new().route("/user/calendar/", any(report)),
+        )
+        .await
+        .unwrap();
+    });
+
+    format!("http://{addr}/user/calendar/")
+}
+
+fn source(url: String) -> CalendarSource {
+    CalendarSource {
+        name: "dav".to_string(),
+        provider: CalendarProvider::Caldav,
+        url,
+        entity_id: None,
+        kind: CalendarSourceKind::Event,
+        color: None,
+        auth_header: None,
+        username: None,
+        password: None,
+        schedule: None,
+        retry: None,
+    }
+}
+
+#[test]
+fn test_calendar_query_time_range() {
+    let query = calendar_query(
+        datetime!(2026-05-01 07:00 +7),
+        datetime!(2026-06-01 07:00 +7),
+    );
+
+    assert!(query.contains(r#"<c:comp-filter name="VEVENT">"#));
+    assert!(query.contains(r#"<c:time-range start="20260501T000000Z" end="20260601T000000Z"/>"#));
+}
+
+#[test]
+fn test_calendar_data() {
+    let data = calendar_data(MULTISTATUS).unwrap();
+
+    assert_eq!(data.len(), 2);
+    assert!(data[0].contains("SUMMARY:Standup & review"));
+    assert!(data[1].contains("DTSTART;VALUE=DATE:20260504"));
+}
+
+#[tokio::test]
+async fn test_fetch_events_auth() {
+    let url = fake_caldav().await;
+    let start = datetime!(2026-05-01 00:00 UTC);
+    let end = datetime!(2026-06-01 00:00 UTC);
+
+    let mut basic = source(url.clone());
+    basic.username = Some("alice".to_string());
+    basic.password = Some("secret".to_string());
+    let events = fetch_events(&basic, start, end).await.unwrap();
+    assert_eq!(events.len(), 2);
+
+    let mut bearer = source(url.clone());
+    bearer.auth_header = Some("Bearer token".to_string());
+    assert_eq!(fetch_events(&bearer, start, end).await.unwrap().len(), 2);
+
+    assert!(fetch_events(&source(url), start, end).await.is_err());
+}
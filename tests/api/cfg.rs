@@ -0,0 +1,43 @@
+use serde_json::json;
+use server::{CalendarSource, cron};
+
+use crate::helpers::*;
+
+fn source(name: &str) -> CalendarSource {
+    serde_json::from_value(json!({
+        "name": name,
+        "url": "https://example.com/home.ics",
+        "kind": "event"
+    }))
+    .unwrap()
+}
+
+#[test]
+fn test_calendar_source_validate() {
+    assert!(source("home").validate().is_ok());
+
+    // Names of the Home Assistant fetches are taken
+    for name in [
+        cron::HA_STATES_SOURCE,
+        cron::FORECAST_SOURCE,
+        cron::TODO_SOURCE,
+    ] {
+        assert!(source(name).validate().is_err());
+    }
+
+    let mut no_url = source("home");
+    no_url.url = String::new();
+    assert!(no_url.validate().is_err());
+}
+
+#[tokio::test]
+async fn test_calendar_window_validate() {
+    let mut cfg = (*TestApp::new().await.cfg).clone();
+    assert!(cfg.validate().is_ok());
+
+    cfg.calendar_window_days = 0;
+    assert!(cfg.validate().is_ok());
+    // A window ending before it starts would leave the calendar empty
+    cfg.calendar_window_days = -1;
+    assert!(cfg.validate().is_err());
+}
@@ -0,0 +1,403 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Arc,
+};
+
+use axum::{
+    Router,
+    body::Body,
+    http::{Request, StatusCode, header},
+    routing::get,
+};
+use serde_json::json;
+use server::{
+    CalendarColor, CalendarSource, Schedule,
+    cron::{occurrences_from_ha, reconcile_events, reconcile_holidays, register, setup},
+    model::{CalendarMap, DateInfoEventMode, DateInfoHoliday, HaCalendarEvent},
+};
+use time::{
+    Duration, OffsetDateTime,
+    macros::{date, datetime, format_description},
+};
+use time_tz::timezones;
+use tokio::net::TcpListener;
+use tokio_cron_scheduler::JobScheduler;
+
+use crate::helpers::*;
+
+fn event(source: &str, name: &str) -> DateInfoEventMode {
+    DateInfoEventMode {
+        time: datetime!(2026-05-01 09:00 +7),
+        end: datetime!(2026-05-01 10:00 +7),
+        all_day: false,
+        name: name.to_string(),
+        source: source.to_string(),
+        color: CalendarColor::Black,
+        day: 1,
+        days: 1,
+    }
+}
+
+fn holiday(source: &str, name: &str) -> DateInfoHoliday {
+    DateInfoHoliday {
+        name: name.to_string(),
+        source: source.to_string(),
+        color: CalendarColor::Red,
+    }
+}
+
+#[test]
+fn test_reconcile_events_prunes_and_moves() {
+    let mut calendar = CalendarMap::new();
+
+    assert!(reconcile_events(
+        &mut calendar,
+        "work",
+        BTreeMap::from([
+            (
+                date!(2026 - 05 - 01),
+                HashMap::from([
+                    ("a".to_string(), event("work", "Kept")),
+                    ("b".to_string(), event("work", "Cancelled")),
+                    ("c".to_string(), event("work", "Moved")),
+                ]),
+            ),
+            (
+                date!(2026 - 05 - 02),
+                HashMap::from([("d".to_string(), event("work", "Gone"))]),
+            ),
+        ]),
+    ));
+    assert!(reconcile_holidays(
+        &mut calendar,
+        "holiday",
+        BTreeMap::from([(date!(2026 - 05 - 01), holiday("holiday", "Labour Day"))]),
+    ));
+
+    let latest = BTreeMap::from([
+        (
+            date!(2026 - 05 - 01),
+            HashMap::from([("a".to_string(), event("work", "Kept"))]),
+        ),
+        (
+            date!(2026 - 05 - 03),
+            HashMap::from([("c".to_string(), event("work", "Moved"))]),
+        ),
+    ]);
+    assert!(reconcile_events(&mut calendar, "work", latest.clone()));
+    assert!(!reconcile_events(&mut calendar, "work", latest));
+
+    assert_eq!(
+        calendar.keys().copied().collect::<Vec<_>>(),
+        vec![date!(2026 - 05 - 01), date!(2026 - 05 - 03)]
+    );
+    assert!(calendar[&date!(2026 - 05 - 01)].is_holiday());
+    assert_eq!(calendar[&date!(2026 - 05 - 01)].events.len(), 1);
+    assert!(calendar[&date!(2026 - 05 - 03)].events.contains_key("c"));
+
+    assert!(reconcile_holidays(
+        &mut calendar,
+        "holiday",
+        BTreeMap::new()
+    ));
+    assert!(!calendar[&date!(2026 - 05 - 01)].is_holiday());
+}
+
+#[test]
+fn test_reconcile_keeps_other_sources() {
+    let mut calendar = CalendarMap::new();
+
+    reconcile_events(
+        &mut calendar,
+        "work",
+        BTreeMap::from([(
+            date!(2026 - 05 - 01),
+            HashMap::from([("work/a".to_string(), event("work", "Review"))]),
+        )]),
+    );
+    reconcile_events(
+        &mut calendar,
+        "family",
+        BTreeMap::from([(
+            date!(2026 - 05 - 01),
+            HashMap::from([("family/a".to_string(), event("family", "Dinner"))]),
+        )]),
+    );
+    reconcile_holidays(
+        &mut calendar,
+        "th",
+        BTreeMap::from([(date!(2026 - 05 - 01), holiday("th", "Labour Day"))]),
+    );
+    reconcile_holidays(
+        &mut calendar,
+        "intl",
+        BTreeMap::from([(date!(2026 - 05 - 01), holiday("intl", "May Day"))]),
+    );
+
+    assert!(reconcile_events(&mut calendar, "family", BTreeMap::new()));
+    assert!(reconcile_holidays(&mut calendar, "intl", BTreeMap::new()));
+
+    let c_nty = &calendar[&date!(2026 - 05 - 01)];
+    assert_eq!(
+        c_nty.events.keys().collect::<Vec<_>>(),
+        vec![&"work/a".to_string()]
+    );
+    assert_eq!(c_nty.holidays, vec![holiday("th", "Labour Day")]);
+}
+
+#[test]
+fn test_occurrences_from_ha() {
+    let events = serde_json::from_str::<Vec<HaCalendarEvent>>(
+        r#"[
+            {
+                "start": {"dateTime": "2026-05-01T09:00:00+07:00"},
+                "end": {"dateTime": "2026-05-01T10:30:00+07:00"},
+                "summary": "Standup",
+                "description": null,
+                "location": null,
+                "uid": "abc@example.com",
+                "recurrence_id": "20260501T020000Z",
+                "rrule": "FREQ=DAILY"
+            },
+            {
+                "start": {"date": "2026-05-04"},
+                "end": {"date": "2026-05-06"},
+                "summary": "Trip"
+            },
+            {
+                "start": {},
+                "end": {},
+                "summary": "Broken"
+            }
+        ]"#,
+    )
+    .unwrap();
+    let tz = timezones::db::asia::BANGKOK;
+    let occs = occurrences_from_ha(events, tz);
+
+    assert_eq!(occs.len(), 2);
+    assert_eq!(occs[0].id, "abc@example.com/20260501T020000Z");
+    assert_eq!(occs[0].start, datetime!(2026-05-01 09:00 +7));
+    assert_eq!(occs[0].end, datetime!(2026-05-01 10:30 +7));
+    assert!(!occs[0].all_day);
+    assert_eq!(occs[1].uid, "Trip");
+    assert!(occs[1].all_day);
+    assert_eq!(occs[1].start, datetime!(2026-05-04 00:00 +7));
+    assert_eq!(occs[1].end, datetime!(2026-05-06 00:00 +7));
+}
+
+#[test]
+fn test_schedule_parse() {
+    assert_eq!("300".parse::<Schedule>().unwrap(), Schedule::Interval(300));
+    assert_eq!(
+        Schedule::Cron("0 */5 * * * *".to_string()).cron().unwrap(),
+        Some("0 */5 * * * *".to_string())
+    );
+    assert_eq!(Schedule::Interval(60).cron().unwrap(), None);
+    assert!("every day at 4:00".parse::<Schedule>().is_ok());
+    assert!("0".parse::<Schedule>().is_err());
+    assert!("now and then".parse::<Schedule>().is_err());
+    assert!("61 * * * * *".parse::<Schedule>().is_err());
+}
+
+#[test]
+fn test_schedule_next_run() {
+    let tz = timezones::db::asia::BANGKOK;
+    let now = datetime!(2026-10-17 10:00:30 +7);
+    let daily = Schedule::Cron("0 0 4 * * *".to_string());
+
+    assert_eq!(
+        daily.next_run(now, tz),
+        Some(datetime!(2026-10-18 04:00 +7))
+    );
+    assert_eq!(daily.period(now, tz), Duration::days(1));
+    assert_eq!(
+        Schedule::Cron("0 */5 * * * *".to_string()).next_run(now, tz),
+        Some(datetime!(2026-10-17 10:05 +7))
+    );
+    assert_eq!(
+        Schedule::Interval(90).next_run(now, tz),
+        Some(datetime!(2026-10-17 10:02 +7))
+    );
+    assert_eq!(
+        Schedule::Interval(90).period(now, tz),
+        Duration::seconds(90)
+    );
+}
+
+#[test]
+fn test_schedule_next_run_dst() {
+    let tz = timezones::db::america::NEW_YORK;
+    let daily = Schedule::Cron("0 0 5 * * *".to_string());
+
+    // Clocks go forward on 8 March 2026, and back on 1 November
+    let now = datetime!(2026-03-07 12:00 -5);
+    assert_eq!(
+        daily.next_run(now, tz),
+        Some(datetime!(2026-03-08 05:00 -4))
+    );
+    assert_eq!(
+        daily.period(datetime!(2026-03-07 04:00 -5), tz),
+        Duration::hours(23)
+    );
+    let now = datetime!(2026-10-31 12:00 -4);
+    assert_eq!(
+        daily.next_run(now, tz),
+        Some(datetime!(2026-11-01 05:00 -5))
+    );
+    assert_eq!(
+        daily.period(datetime!(2026-10-31 04:00 -4), tz),
+        Duration::hours(25)
+    );
+
+    // Skipped wall times run as much later, repeated ones once
+    let night = Schedule::Cron("0 30 2 * * *".to_string());
+    assert_eq!(
+        night.next_run(datetime!(2026-03-07 12:00 -5), tz),
+        Some(datetime!(2026-03-08 03:30 -4))
+    );
+    let night = Schedule::Cron("0 30 1 * * *".to_string());
+    let first = night.next_run(datetime!(2026-10-31 12:00 -4), tz).unwrap();
+    assert_eq!(first, datetime!(2026-11-01 01:30 -4));
+    assert_eq!(
+        night.next_run(first, tz),
+        Some(datetime!(2026-11-02 01:30 -5))
+    );
+    let often = Schedule::Cron("0 */5 * * * *".to_string());
+    assert_eq!(
+        often.next_run(datetime!(2026-11-01 01:10 -5), tz),
+        Some(datetime!(2026-11-01 01:15 -5))
+    );
+}
+
+#[tokio::test]
+async fn test_register_cron_runs_again() {
+    let app = TestApp::new().await;
+    let mut cfg = (*app.cfg).clone();
+    cfg.ha_retry.attempts = 1;
+    let sched = JobScheduler::new().await.unwrap();
+    sched.start().await.unwrap();
+    register(
+        &sched,
+        Arc::new(cfg),
+        app.db.clone(),
+        app.shared.clone(),
+        "forecast".to_string(),
+        Schedule::Cron("* * * * * *".to_string()),
+    )
+    .await
+    .unwrap();
+    let first = app.shared.schedules.read().await["forecast"].job_id;
+
+    // Each run adds the job of the next one
+    tokio::time::timeout(std::time::Duration::from_secs(10), async {
+        loop {
+            let job = app.shared.schedules.read().await["forecast"].clone();
+            if job.last_run.is_some() && job.job_id != first {
+                let next = sched.clone().next_tick_for_job(job.job_id).await.unwrap();
+                if next.is_some() {
+                    break;
+                }
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        }
+    })
+    .await
+    .expect("No run scheduled after the first");
+    sched.clone().shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_calendar_saved_after_failed_save() {
+    let app = TestApp::new().await;
+    let tz = timezones::get_by_name(&app.cfg.tz).unwrap();
+    let day = OffsetDateTime::now_utc().date() + Duration::days(2);
+    let feed = format!(
+        "BEGIN:VCALENDAR\nBEGIN:VEVENT\nUID:dentist@example.com\nDTSTART;VALUE=DATE:{}\n\
+         SUMMARY:Dentist\nEND:VEVENT\nEND:VCALENDAR\n",
+        day.format(format_description!("[year][month][day]"))
+            .unwrap()
+    );
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let feeds = Router::new().route("/work.ics", get(move || async move { feed }));
+    tokio::spawn(async move { axum::serve(listener, feeds).await.unwrap() });
+    let source: CalendarSource = serde_json::from_value(json!({
+        "name": "work",
+        "url": format!("http://{addr}/work.ics"),
+        "kind": "event",
+        "retry": {"attempts": 1}
+    }))
+    .unwrap();
+    let mut cfg = (*app.cfg).clone();
+    cfg.calendar_sources = vec![source];
+    cfg.ha_retry.attempts = 1;
+    let cfg = Arc::new(cfg);
+    let has_event = |calendar: &CalendarMap| {
+        calendar
+            .get(&day)
+            .is_some_and(|d| d.events.values().any(|e| e.name == "Dentist"))
+    };
+
+    // Not shown while it cannot be saved
+    let rename = |from: &str, to: &str| format!("ALTER TABLE {from} RENAME TO {to}");
+    let off = rename("calendar_events", "calendar_events_off");
+    sqlx::query(&off).execute(&app.db.pool).await.unwrap();
+    setup(cfg.clone(), app.db.clone(), app.shared.clone())
+        .await
+        .unwrap();
+    assert!(!has_event(&*app.shared.calendar.read().await));
+
+    // The next fetch of the same feed saves it
+    let on = rename("calendar_events_off", "calendar_events");
+    sqlx::query(&on).execute(&app.db.pool).await.unwrap();
+    setup(cfg, app.db.clone(), app.shared.clone())
+        .await
+        .unwrap();
+    assert!(has_event(&*app.shared.calendar.read().await));
+    assert!(has_event(&app.db.load_calendar(tz).await.unwrap()));
+}
+
+#[tokio::test]
+async fn test_register_replaces_job() {
+    let app = TestApp::new().await;
+    let sched = JobScheduler::new().await.unwrap();
+    let register = |schedule| {
+        register(
+            &sched,
+            app.cfg.clone(),
+            app.db.clone(),
+            app.shared.clone(),
+            "forecast".to_string(),
+            schedule,
+        )
+    };
+
+    register(Schedule::Interval(600)).await.unwrap();
+    let first = app.shared.schedules.read().await["forecast"].job_id;
+    register(Schedule::Cron("every 10 minutes".to_string()))
+        .await
+        .unwrap();
+
+    let schedules = app.shared.schedules.read().await.clone();
+    assert_eq!(schedules.len(), 1);
+    assert_ne!(schedules["forecast"].job_id, first);
+    assert!(schedules["forecast"].cron.is_some());
+    assert!(schedules["forecast"].next_run.is_some());
+
+    let req = Request::get("/admin/schedules")
+        .header(header::AUTHORIZATION, app.cfg.access_token.as_str())
+        .body(Body::empty())
+        .unwrap();
+    let resp = app.request(req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let body = axum::body::to_bytes(resp.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let body = serde_json::from_slice::<serde_json::Value>(&body).unwrap();
+    assert_eq!(body["forecast"]["schedule"], "every 10 minutes");
+    assert_eq!(
+        body["forecast"]["cron"],
+        schedules["forecast"].cron.as_deref().unwrap()
+    );
+}
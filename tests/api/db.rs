@@ -0,0 +1,133 @@
+use std::collections::{BTreeMap, HashMap};
+
+use server::{
+    CalendarColor,
+    model::{
+        DateInfoEventMode, DateInfoHoliday, WeatherForecast, WeatherForecastEntry, WeatherInfo,
+        WeatherInfoAttribute, WeatherInfoState,
+    },
+};
+use time::macros::{date, datetime};
+use time_tz::timezones;
+
+use crate::helpers::*;
+
+#[tokio::test]
+async fn test_calendar_round_trip() {
+    let app = TestApp::new().await;
+    let event = DateInfoEventMode {
+        time: datetime!(2026-05-01 00:00 +7),
+        end: datetime!(2026-05-03 00:00 +7),
+        all_day: true,
+        name: "Offsite".to_string(),
+        source: "work".to_string(),
+        color: CalendarColor::Black,
+        day: 1,
+        days: 2,
+    };
+    let events = BTreeMap::from([
+        (
+            date!(2026 - 05 - 01),
+            HashMap::from([("work/offsite".to_string(), event.clone())]),
+        ),
+        (
+            date!(2026 - 05 - 02),
+            HashMap::from([(
+                "work/offsite".to_string(),
+                DateInfoEventMode {
+                    day: 2,
+                    ..event.clone()
+                },
+            )]),
+        ),
+    ]);
+    let holidays = BTreeMap::from([(
+        date!(2026 - 05 - 01),
+        DateInfoHoliday {
+            name: "Labour Day".to_string(),
+            source: "th".to_string(),
+            color: CalendarColor::Red,
+        },
+    )]);
+
+    app.db.save_events("work", &events).await.unwrap();
+    app.db.save_holidays("th", &holidays).await.unwrap();
+    // Saving again replaces the source
+    app.db.save_events("work", &events).await.unwrap();
+    app.db.prune_calendar(date!(2026 - 05 - 02)).await.unwrap();
+
+    let calendar = app
+        .db
+        .load_calendar(timezones::db::asia::BANGKOK)
+        .await
+        .unwrap();
+
+    assert_eq!(calendar.len(), 1);
+    let c_nty = &calendar[&date!(2026 - 05 - 02)];
+    assert!(c_nty.holidays.is_empty());
+    assert_eq!(
+        c_nty.events["work/offsite"],
+        DateInfoEventMode { day: 2, ..event }
+    );
+}
+
+#[tokio::test]
+async fn test_weather_and_last_update_round_trip() {
+    let app = TestApp::new().await;
+
+    assert!(app.db.load_weather().await.unwrap().is_none());
+    assert!(app.db.load_last_update().await.unwrap().is_none());
+
+    let weather = WeatherInfo {
+        state: WeatherInfoState::Rainy,
+        attributes: WeatherInfoAttribute {
+            temperature: Some(28.5),
+            temperature_unit: Some("°C".to_string()),
+            humidity: Some(80.0),
+            ..Default::default()
+        },
+    };
+    app.db.save_weather(&weather).await.unwrap();
+    app.db.save_weather(&weather).await.unwrap();
+    app.db
+        .save_last_update(datetime!(2026-05-01 03:00))
+        .await
+        .unwrap();
+    app.db
+        .save_last_update(datetime!(2026-05-01 04:00))
+        .await
+        .unwrap();
+
+    let loaded = app.db.load_weather().await.unwrap().unwrap();
+    assert_eq!(loaded.state, WeatherInfoState::Rainy);
+    assert_eq!(loaded.attributes.temperature, Some(28.5));
+    assert_eq!(loaded.attributes.uv_index, None);
+    assert_eq!(
+        app.db.load_last_update().await.unwrap(),
+        Some(datetime!(2026-05-01 04:00))
+    );
+}
+
+#[tokio::test]
+async fn test_forecast_round_trip() {
+    let app = TestApp::new().await;
+
+    assert!(app.db.load_forecast().await.unwrap().is_none());
+
+    let mut forecast = WeatherForecast {
+        daily: vec![WeatherForecastEntry {
+            datetime: Some(datetime!(2026-05-02 05:00 UTC)),
+            condition: Some(WeatherInfoState::Sunny),
+            temperature: Some(34.0),
+            templow: Some(26.0),
+            precipitation_probability: Some(10.0),
+            ..Default::default()
+        }],
+        hourly: Vec::new(),
+    };
+    app.db.save_forecast(&forecast).await.unwrap();
+    forecast.daily[0].templow = Some(25.0);
+    app.db.save_forecast(&forecast).await.unwrap();
+
+    assert_eq!(app.db.load_forecast().await.unwrap(), Some(forecast));
+}
@@ -0,0 +1,93 @@
+use std::collections::BTreeSet;
+
+use image::{GrayImage, Luma, Rgb, RgbImage};
+use server::{dither::Dither, layout::Color, panel::PanelProfile};
+
+fn bw(width: u32, height: u32) -> PanelProfile {
+    PanelProfile {
+        width,
+        height,
+        palette: vec![Color::Black, Color::White],
+        rotation: Default::default(),
+        scale: None,
+    }
+}
+
+fn black_share(inks: &[Color]) -> f32 {
+    inks.iter().filter(|c| **c == Color::Black).count() as f32 / inks.len() as f32
+}
+
+#[test]
+fn test_dither_thresholds() {
+    // Ordered maps rank every cell of their tile once
+    for (dither, size) in [(Dither::Bayer, 8), (Dither::BlueNoise, 64)] {
+        let thresholds = (0..size)
+            .flat_map(|y| (0..size).map(move |x| (x, y)))
+            .map(|(x, y)| dither.threshold(x, y).unwrap())
+            .collect::<Vec<_>>();
+        let distinct = thresholds
+            .iter()
+            .map(|t| (t * (size * size) as f32) as u32)
+            .collect::<BTreeSet<_>>();
+
+        assert_eq!(distinct.len(), (size * size) as usize, "{dither:?}");
+        assert!(thresholds.iter().all(|t| (0.0..1.0).contains(t)));
+        // The tile repeats
+        assert_eq!(
+            dither.threshold(3, 5),
+            dither.threshold(size + 3, size * 2 + 5)
+        );
+    }
+    assert_eq!(Dither::FloydSteinberg.threshold(0, 0), None);
+    assert!(Dither::Bayer.diffusion().is_empty());
+}
+
+#[test]
+fn test_dither_gray_fill() {
+    let panel = bw(64, 64);
+    let gray = RgbImage::from_pixel(64, 64, Rgb([128, 128, 128]));
+    let fill = GrayImage::from_pixel(64, 64, Luma([255]));
+
+    // Half gray comes out about half black, but for a hard threshold
+    for dither in [
+        Dither::Bayer,
+        Dither::FloydSteinberg,
+        Dither::Atkinson,
+        Dither::BlueNoise,
+    ] {
+        let share = black_share(&panel.quantize(&gray, &fill, dither));
+        assert!((0.4..=0.6).contains(&share), "{dither:?}: {share}");
+    }
+    assert_eq!(
+        black_share(&panel.quantize(&gray, &fill, Dither::None)),
+        0.0
+    );
+
+    // A light gray gets few black dots
+    let light = RgbImage::from_pixel(64, 64, Rgb([213, 213, 213]));
+    let share = black_share(&panel.quantize(&light, &fill, Dither::BlueNoise));
+    assert!((0.1..=0.25).contains(&share), "{share}");
+}
+
+#[test]
+fn test_dither_keeps_text_crisp() {
+    let panel = bw(8, 8);
+    // Anti-aliased text edges over a gray fill
+    let image = RgbImage::from_fn(8, 8, |x, _| match x < 4 {
+        true => Rgb([100, 100, 100]),
+        false => Rgb([160, 160, 160]),
+    });
+    let mask = GrayImage::from_fn(8, 8, |x, _| Luma([if x < 4 { 0 } else { 255 }]));
+
+    for dither in [Dither::Bayer, Dither::FloydSteinberg, Dither::Atkinson] {
+        let inks = panel.quantize(&image, &mask, dither);
+
+        // Text pixels get their nearest ink, the fill is dithered
+        assert!((0..8).all(|y| (0..4).all(|x| inks[y * 8 + x] == Color::Black)));
+        let fill = (0..8)
+            .flat_map(|y| (4..8).map(move |x| y * 8 + x))
+            .map(|idx| inks[idx])
+            .collect::<Vec<_>>();
+        assert!(fill.contains(&Color::Black) && fill.contains(&Color::White));
+    }
+}
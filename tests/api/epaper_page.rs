@@ -0,0 +1,204 @@
+use axum::{
+    body::Body,
+    http::{Request, StatusCode, header},
+};
+use server::{
+    layout::Color,
+    model::{TodoItem, TodoItemStatus},
+    page::PageConfig,
+};
+use std::sync::Arc;
+use time::{Duration, OffsetDateTime, format_description::well_known::Rfc3339};
+use tower::ServiceExt;
+
+use crate::helpers::*;
+
+#[tokio::test]
+async fn test_epaper_page_tz_override() {
+    let app = TestApp::new().await;
+
+    let req = Request::get(format!(
+        "/epaper_page?token={}&tz=America/New_York",
+        app.cfg.access_token
+    ))
+    .body(Body::empty())
+    .unwrap();
+    let resp = app.request(req).await;
+
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(resp.headers().get("content-type").unwrap(), "image/png");
+}
+
+#[tokio::test]
+async fn test_epaper_page_tz_invalid() {
+    let app = TestApp::new().await;
+
+    let req = Request::get(format!(
+        "/epaper_page?token={}&tz=Nowhere/Atlantis",
+        app.cfg.access_token
+    ))
+    .body(Body::empty())
+    .unwrap();
+    let resp = app.request(req).await;
+
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_epaper_page_etag() {
+    let app = TestApp::new().await;
+    let page = |query: &str, etag: Option<&str>| {
+        let mut req = Request::get(format!(
+            "/epaper_page?token={}&{query}",
+            app.cfg.access_token
+        ));
+        if let Some(etag) = etag {
+            req = req.header(header::IF_NONE_MATCH, etag);
+        }
+        app.request(req.body(Body::empty()).unwrap())
+    };
+
+    let resp = page("", None).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let etag = resp.headers()[header::ETAG].to_str().unwrap().to_string();
+    let last_modified = resp.headers()[header::LAST_MODIFIED].clone();
+    assert!(etag.starts_with('"'));
+
+    let resp = page("", Some(&etag)).await;
+    assert_eq!(resp.status(), StatusCode::NOT_MODIFIED);
+    assert_eq!(resp.headers()[header::ETAG], etag.as_str());
+    let body = axum::body::to_bytes(resp.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    assert!(body.is_empty());
+
+    // Each variant has its own validator
+    let resp = page("output=red", Some(&etag)).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_ne!(resp.headers()[header::ETAG], etag.as_str());
+
+    // New data renders the page again
+    *app.shared.last_update.write().await += Duration::minutes(1);
+    let resp = page("", None).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_ne!(resp.headers()[header::LAST_MODIFIED], last_modified);
+}
+
+#[tokio::test]
+async fn test_epaper_page_todo_overdue() {
+    let app = TestApp::new().await;
+    let mut cfg = (*app.cfg).clone();
+    cfg.ha_todo_entities = vec!["todo.home".to_string()];
+    let token = cfg.access_token.clone();
+    let router = server::router(Arc::new(cfg), app.db.clone(), app.shared.clone());
+    let etag = || async {
+        let req = Request::get(format!("/epaper_page?token={token}"))
+            .body(Body::empty())
+            .unwrap();
+        let resp = router.clone().oneshot(req).await.unwrap();
+        resp.headers()[header::ETAG].to_str().unwrap().to_string()
+    };
+
+    // Due in a moment
+    let due = OffsetDateTime::now_utc().replace_nanosecond(0).unwrap() + Duration::seconds(2);
+    app.shared.todos.write().await.insert(
+        "todo.home".to_string(),
+        vec![TodoItem {
+            summary: "Call the plumber".to_string(),
+            uid: None,
+            status: TodoItemStatus::NeedsAction,
+            due: Some(due.format(&Rfc3339).unwrap()),
+        }],
+    );
+    app.shared.generation.bump();
+    let before = etag().await;
+    assert_eq!(etag().await, before);
+
+    // Drawn as overdue once due, though the data is the same
+    let wait = due - OffsetDateTime::now_utc() + Duration::milliseconds(100);
+    tokio::time::sleep(wait.try_into().unwrap_or_default()).await;
+    assert_ne!(etag().await, before);
+}
+
+#[tokio::test]
+async fn test_epaper_page_same_data() {
+    let app = TestApp::new().await;
+    let req = || Request::get(format!("/epaper_page?token={}", app.cfg.access_token));
+
+    let resp = app.request(req().body(Body::empty()).unwrap()).await;
+    let etag = resp.headers()[header::ETAG].to_str().unwrap().to_string();
+
+    // Drawn again in another minute, by a router with an empty cache, the
+    // same data makes the same page
+    next_minute().await;
+    let router = server::router(app.cfg.clone(), app.db.clone(), app.shared.clone());
+    let req = req()
+        .header(header::IF_NONE_MATCH, &etag)
+        .body(Body::empty())
+        .unwrap();
+    let resp = router.oneshot(req).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::NOT_MODIFIED);
+}
+
+#[tokio::test]
+async fn test_epaper_page_layout_reload() {
+    let app = TestApp::new().await;
+    let page = |etag: Option<&str>| {
+        let mut req = Request::get(format!("/epaper_page?token={}", app.cfg.access_token));
+        if let Some(etag) = etag {
+            req = req.header(header::IF_NONE_MATCH, etag);
+        }
+        app.request(req.body(Body::empty()).unwrap())
+    };
+
+    let resp = page(None).await;
+    let etag = resp.headers()[header::ETAG].to_str().unwrap().to_string();
+
+    // A new layout is drawn at once, even though the data is the same
+    let footer_only = PageConfig::parse("layout:\n  widget: footer\n", "yaml").unwrap();
+    *app.shared.page.write().await = Arc::new(footer_only);
+    let resp = page(Some(&etag)).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_ne!(resp.headers()[header::ETAG], etag.as_str());
+}
+
+#[tokio::test]
+async fn test_epaper_page_panel() {
+    let app = TestApp::new().await;
+    let page = |query: &str| {
+        Request::get(format!(
+            "/epaper_page?token={}&{query}",
+            app.cfg.access_token
+        ))
+        .body(Body::empty())
+        .unwrap()
+    };
+
+    // The page is drawn at the resolution of the panel, in its inks
+    let resp = app.request(page("panel=7.50in-v2-bw")).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let body = axum::body::to_bytes(resp.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let image = image::load_from_memory(&body).unwrap().to_rgb8();
+    assert_eq!(image.dimensions(), (800, 480));
+    assert!(
+        image
+            .pixels()
+            .all(|p| [Color::Black.rgb(), Color::White.rgb()].contains(p))
+    );
+
+    // Unknown devices get the default panel
+    let resp = app.request(page("device=nowhere")).await;
+    let body = axum::body::to_bytes(resp.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let image = image::load_from_memory(&body).unwrap();
+    assert_eq!((image.width(), image.height()), (400, 300));
+
+    // Unknown panels and inks they lack are refused
+    let resp = app.request(page("panel=1.54in-nope")).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    let resp = app.request(page("panel=2.90in-bw&output=red")).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+}
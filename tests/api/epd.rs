@@ -0,0 +1,149 @@
+use axum::{
+    body::Body,
+    http::{Request, StatusCode, header},
+};
+use server::{epd, layout::Color};
+
+use crate::helpers::*;
+
+/// Reference PackBits decoder, as a device would run it.
+fn unpack_bits(mut packed: &[u8]) -> Vec<u8> {
+    let mut data = Vec::new();
+
+    while let Some((&n, rest)) = packed.split_first() {
+        packed = match n {
+            0..128 => {
+                let (literal, rest) = rest.split_at(n as usize + 1);
+                data.extend(literal);
+                rest
+            }
+            128 => rest,
+            _ => {
+                data.extend(std::iter::repeat_n(rest[0], 257 - n as usize));
+                &rest[1..]
+            }
+        };
+    }
+
+    data
+}
+
+struct Frame {
+    width: u16,
+    height: u16,
+    stride: u16,
+    flags: u8,
+    inks: Vec<u8>,
+    data: Vec<u8>,
+}
+
+fn parse(body: &[u8]) -> Frame {
+    let (header, payload) = body.split_at(epd::HEADER_LEN);
+    assert_eq!(&header[0..4], epd::MAGIC);
+    let u16_at = |idx: usize| u16::from_le_bytes([header[idx], header[idx + 1]]);
+    let crc = u32::from_le_bytes(header[12..16].try_into().unwrap());
+    assert_eq!(crc, crc32fast::hash(payload));
+
+    let planes = header[10] as usize;
+    let (inks, data) = payload.split_at(planes);
+    let flags = header[11];
+
+    Frame {
+        width: u16_at(4),
+        height: u16_at(6),
+        stride: u16_at(8),
+        flags,
+        inks: inks.to_vec(),
+        data: match flags & epd::FLAG_RLE {
+            0 => data.to_vec(),
+            _ => unpack_bits(data),
+        },
+    }
+}
+
+#[test]
+fn test_epd_pack_plane() {
+    // Rows are padded to whole bytes, a 0 bit is ink
+    let plane = epd::pack_plane(10, 2, |x, y| x == y || x == 9);
+
+    assert_eq!(epd::stride(10), 2);
+    assert_eq!(plane, [0b0111_1111, 0b1011_1111, 0b1011_1111, 0b1011_1111]);
+}
+
+#[test]
+fn test_epd_pack_bits() {
+    let samples = [
+        vec![],
+        vec![7],
+        vec![0xff; 300],
+        (0..=255).chain(0..=255).collect(),
+        [vec![1, 2, 3], vec![0; 129], vec![4, 4, 5]].concat(),
+    ];
+
+    for data in samples {
+        let packed = epd::pack_bits(&data);
+        assert_eq!(unpack_bits(&packed), data);
+    }
+    // Long runs take 2 bytes per 128
+    assert_eq!(epd::pack_bits(&[0xff; 256]), [129, 0xff, 129, 0xff]);
+}
+
+#[tokio::test]
+async fn test_epaper_page_epd() {
+    let app = TestApp::new().await;
+    let page = |query: &str| {
+        Request::get(format!(
+            "/epaper_page?token={}&format=epd&{query}",
+            app.cfg.access_token
+        ))
+        .body(Body::empty())
+        .unwrap()
+    };
+    let body = |query: &'static str| {
+        let req = page(query);
+        async {
+            let resp = app.request(req).await;
+            assert_eq!(resp.status(), StatusCode::OK);
+            assert_eq!(
+                resp.headers().get(header::CONTENT_TYPE).unwrap(),
+                "application/octet-stream"
+            );
+            axum::body::to_bytes(resp.into_body(), usize::MAX)
+                .await
+                .unwrap()
+        }
+    };
+
+    // Black and red planes of the default 4.2" panel
+    let raw = parse(&body("").await);
+    assert_eq!((raw.width, raw.height, raw.stride), (400, 300, 50));
+    assert_eq!(raw.flags, 0);
+    assert_eq!(
+        raw.inks,
+        [epd::ink_code(Color::Black), epd::ink_code(Color::Red)]
+    );
+    assert_eq!(raw.data.len(), 2 * 50 * 300);
+
+    // Each request draws the page again, only the footer time can change
+    let above_footer = 50 * 275;
+
+    // Compressed, the planes are the same
+    let body_rle = body("rle=true").await;
+    let rle = parse(&body_rle);
+    assert_eq!(rle.flags, epd::FLAG_RLE);
+    assert_eq!(rle.data.len(), raw.data.len());
+    assert_eq!(rle.data[..above_footer], raw.data[..above_footer]);
+    assert!(body_rle.len() < raw.data.len());
+
+    // A layer alone, or inverted
+    let black = parse(&body("output=black").await);
+    assert_eq!(black.inks, [epd::ink_code(Color::Black)]);
+    assert_eq!(black.data[..above_footer], raw.data[..above_footer]);
+    let inverted = parse(&body("output=black-invert").await);
+    assert!(
+        inverted.data[..above_footer]
+            .iter()
+            .zip(&black.data)
+            .all(|(a, b)| *a == !b)
+    );
+}
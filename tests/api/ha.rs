@@ -0,0 +1,207 @@
+use std::{sync::Arc, time::Duration};
+
+use futures_util::{SinkExt, StreamExt};
+use serde_json::{Value, json};
+use server::{
+    HaSensor,
+    ha::{self, websocket_url},
+    model::{SharedState, WeatherInfoState},
+};
+use time::{OffsetDateTime, PrimitiveDateTime};
+use tokio::{net::TcpListener, sync::RwLock};
+use tokio_tungstenite::{accept_async, tungstenite::Message};
+
+use crate::helpers::*;
+
+/// Minimal stand-in for the Home Assistant WebSocket API.
+///
+/// The first connection is dropped right after `auth_required` to exercise reconnecting.
+/// Later ones authenticate, confirm the subscription and push the `events` of
+/// the subscribed entities.
+async fn fake_ha(token: String, events: Vec<Value>) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    tokio::spawn(async move {
+        let mut connections = 0;
+
+        while let Ok((stream, _)) = listener.accept().await {
+            connections += 1;
+            let token = token.clone();
+            let events = events.clone();
+
+            tokio::spawn(async move {
+                // REST fallback requests are not WebSocket upgrades
+                let Ok(mut ws) = accept_async(stream).await else {
+                    return;
+                };
+
+                ws.send(Message::text(json!({"type": "auth_required"}).to_string()))
+                    .await
+                    .unwrap();
+                if connections == 1 {
+                    return;
+                }
+
+                let auth = ws.next().await.unwrap().unwrap();
+                let auth = serde_json::from_str::<Value>(auth.to_text().unwrap()).unwrap();
+                assert_eq!(auth["type"], "auth");
+                assert_eq!(auth["access_token"], token.as_str());
+                ws.send(Message::text(json!({"type": "auth_ok"}).to_string()))
+                    .await
+                    .unwrap();
+
+                let sub = ws.next().await.unwrap().unwrap();
+                let sub = serde_json::from_str::<Value>(sub.to_text().unwrap()).unwrap();
+                assert_eq!(sub["type"], "subscribe_trigger");
+                assert_eq!(sub["trigger"]["platform"], "state");
+                let entity_ids = sub["trigger"]["entity_id"].as_array().unwrap().clone();
+                let id = sub["id"].clone();
+                ws.send(Message::text(
+                    json!({"id": id, "type": "result", "success": true, "result": null})
+                        .to_string(),
+                ))
+                .await
+                .unwrap();
+
+                for event in events
+                    .iter()
+                    .filter(|e| entity_ids.contains(&e["variables"]["trigger"]["entity_id"]))
+                {
+                    ws.send(Message::text(
+                        json!({"id": id, "type": "event", "event": event}).to_string(),
+                    ))
+                    .await
+                    .unwrap();
+                }
+
+                // Keep the connection open until the client goes away
+                while let Some(Ok(_)) = ws.next().await {}
+            });
+        }
+    });
+
+    format!("http://{addr}")
+}
+
+/// Event of a state trigger on `entity_id`.
+fn triggered(entity_id: &str, to_state: Value) -> Value {
+    json!({
+        "variables": {
+            "trigger": {
+                "platform": "state",
+                "entity_id": entity_id,
+                "from_state": null,
+                "to_state": to_state
+            }
+        },
+        "context": null
+    })
+}
+
+fn weather_changed(entity_id: &str, state: &str, temperature: f32) -> Value {
+    triggered(
+        entity_id,
+        json!({
+            "entity_id": entity_id,
+            "state": state,
+            "attributes": {"temperature": temperature, "temperature_unit": "°C"}
+        }),
+    )
+}
+
+#[test]
+fn test_websocket_url() {
+    assert_eq!(
+        websocket_url("http://192.168.1.2:8123"),
+        "ws://192.168.1.2:8123/api/websocket"
+    );
+    assert_eq!(
+        websocket_url("https://ha.example.com/"),
+        "wss://ha.example.com/api/websocket"
+    );
+}
+
+#[tokio::test]
+async fn test_websocket_updates() {
+    let app = TestApp::new().await;
+    let mut cfg = (*app.cfg).clone();
+    cfg.ha_sensors = vec![HaSensor {
+        entity_id: "sensor.co2".to_string(),
+        label: "CO2".to_string(),
+        icon: None,
+        format: None,
+    }];
+    cfg.ha_url = fake_ha(
+        cfg.ha_token.clone(),
+        vec![
+            weather_changed(&cfg.ha_weather_entity, "rainy", 27.5),
+            // Not followed, so never sent
+            weather_changed("weather.elsewhere", "sunny", 35.0),
+            triggered(
+                "sensor.co2",
+                json!({"state": "612", "attributes": {"unit_of_measurement": "ppm"}}),
+            ),
+            // Same state, new attribute: drawn but not an update
+            triggered(
+                "sensor.co2",
+                json!({
+                    "state": "612",
+                    "attributes": {"unit_of_measurement": "ppm", "room": "Office"}
+                }),
+            ),
+        ],
+    )
+    .await;
+    let cfg = Arc::new(cfg);
+
+    let start = PrimitiveDateTime::new(
+        OffsetDateTime::now_utc().date(),
+        OffsetDateTime::now_utc().time(),
+    ) - time::Duration::hours(1);
+    let shared = SharedState {
+        calendar: Default::default(),
+        weather: Default::default(),
+        forecast: Default::default(),
+        sensors: Default::default(),
+        todos: Default::default(),
+        feeds: Default::default(),
+        status: Default::default(),
+        refreshed: Default::default(),
+        schedules: Default::default(),
+        last_update: Arc::new(RwLock::new(start)),
+        generation: Default::default(),
+        page: Default::default(),
+    };
+    let client = tokio::spawn(ha::run(cfg, app.db.clone(), shared.clone()));
+
+    tokio::time::timeout(Duration::from_secs(10), async {
+        while shared
+            .sensors
+            .read()
+            .await
+            .get("sensor.co2")
+            .is_none_or(|s| !s.attributes.contains_key("room"))
+        {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await
+    .expect("No sensor update over the WebSocket");
+    client.abort();
+
+    let current = shared.weather.read().await.clone().unwrap();
+    assert_eq!(current.state, WeatherInfoState::Rainy);
+    assert_eq!(current.attributes.temperature, Some(27.5));
+    assert!(*shared.last_update.read().await > start);
+    assert_eq!(
+        app.db.load_weather().await.unwrap().map(|w| w.state),
+        Some(WeatherInfoState::Rainy)
+    );
+    assert_eq!(
+        shared.sensors.read().await["sensor.co2"].format("{state} {unit}"),
+        "612 ppm"
+    );
+    // The weather, the sensor and its attribute
+    assert_eq!(shared.generation.get(), 3);
+}
@@ -1,7 +1,9 @@
 use axum::{
     body::Body,
-    http::{Request, StatusCode},
+    http::{Request, Response, StatusCode},
 };
+use serde_json::Value;
+use time::{Duration, OffsetDateTime};
 
 use crate::helpers::*;
 
@@ -24,3 +26,66 @@ async fn test_db_connection_ok() {
     let app = TestApp::new().await;
     assert_eq!(app.db.pool.size(), 1);
 }
+
+#[tokio::test]
+async fn test_health_check_degraded() {
+    let app = TestApp::new().await;
+    let now = OffsetDateTime::now_utc();
+    {
+        let mut status = app.shared.status.write().await;
+        status
+            .entry("forecast".to_string())
+            .or_default()
+            .record_failure(now, "connection refused".to_string());
+        status
+            .entry("todo".to_string())
+            .or_default()
+            .record_success(now - Duration::minutes(5));
+    }
+
+    let req = Request::get("/health_check").body(Body::empty()).unwrap();
+    let health = body_json(app.request(req).await).await;
+
+    assert_eq!(health["status"], "degraded");
+    assert_eq!(health["sources"]["forecast"]["stale"], true);
+    assert_eq!(health["sources"]["forecast"]["last_error"], Value::Null);
+    assert_eq!(health["sources"]["todo"]["stale"], false);
+    assert_eq!(health["sources"]["todo"]["data_age"], 300);
+
+    // Errors are only shown with the access token
+    let req = Request::get(format!("/health_check?token={}", app.cfg.access_token))
+        .body(Body::empty())
+        .unwrap();
+    let health = body_json(app.request(req).await).await;
+
+    assert_eq!(
+        health["sources"]["forecast"]["last_error"],
+        "connection refused"
+    );
+}
+
+#[tokio::test]
+async fn test_health_check_ok_when_fresh() {
+    let app = TestApp::new().await;
+    app.shared
+        .status
+        .write()
+        .await
+        .entry("forecast".to_string())
+        .or_default()
+        .record_success(OffsetDateTime::now_utc());
+
+    let req = Request::get("/health_check").body(Body::empty()).unwrap();
+    let health = body_json(app.request(req).await).await;
+
+    assert_eq!(health["status"], "ok");
+    assert!(health["sources"]["forecast"]["last_success"].is_string());
+}
+
+async fn body_json(resp: Response<Body>) -> Value {
+    let body = axum::body::to_bytes(resp.into_body(), usize::MAX)
+        .await
+        .unwrap();
+
+    serde_json::from_slice(&body).unwrap()
+}
@@ -6,13 +6,19 @@ use tokio::sync::RwLock;
 use tower::ServiceExt;
 use uuid::Uuid;
 
-use server::{Configuration, Db, model::CalendarMap, telemetry};
+use server::{
+    Config, Configuration, Db,
+    model::{CalendarMap, SharedState},
+    telemetry,
+};
 
 static TRACING: Once = Once::new();
 
 pub struct TestApp {
     pub router: Router,
     pub db: Db,
+    pub cfg: Config,
+    pub shared: SharedState,
 }
 
 impl TestApp {
@@ -44,20 +50,35 @@ impl TestApp {
         // Initialize calendar state
         let calendar = Arc::new(RwLock::new(CalendarMap::new()));
         let weather = Arc::new(RwLock::new(Default::default()));
+        let forecast = Arc::new(RwLock::new(Default::default()));
         let now_odt = OffsetDateTime::now_utc();
         let last_update = Arc::new(RwLock::new(PrimitiveDateTime::new(
             now_odt.date(),
             now_odt.time(),
         )));
 
-        let router = server::router(
-            cfg.clone(),
-            db.clone(),
-            calendar.clone(),
-            weather.clone(),
-            last_update.clone(),
-        );
-        Self { db, router }
+        let shared = SharedState {
+            calendar,
+            weather,
+            forecast,
+            sensors: Default::default(),
+            todos: Default::default(),
+            feeds: Default::default(),
+            status: Default::default(),
+            refreshed: Default::default(),
+            schedules: Default::default(),
+            last_update,
+            generation: Default::default(),
+            page: Default::default(),
+        };
+
+        let router = server::router(cfg.clone(), db.clone(), shared.clone());
+        Self {
+            db,
+            router,
+            cfg,
+            shared,
+        }
     }
 
     pub async fn request(&self, req: Request<Body>) -> Response<Body> {
@@ -82,3 +103,10 @@ pub async fn create_test_db(db_dsn: &str) -> String {
         .expect("Failed to create test database");
     db_url
 }
+
+/// Waits for the next minute to start.
+pub async fn next_minute() {
+    let second = OffsetDateTime::now_utc().second();
+
+    tokio::time::sleep(std::time::Duration::from_secs((60 - second) as u64)).await;
+}
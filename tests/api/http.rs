@@ -0,0 +1,78 @@
+use axum::{
+    Router,
+    http::{HeaderMap, StatusCode, header},
+    response::IntoResponse,
+    routing::get,
+};
+use server::http::{Conditional, Validators, client, send_conditional};
+use tokio::net::TcpListener;
+
+const ETAG: &str = "\"v1\"";
+const LAST_MODIFIED: &str = "Fri, 01 May 2026 00:00:00 GMT";
+
+/// Feed that only answers `304` to the matching validator.
+async fn fake_feed() -> String {
+    async fn feed(headers: HeaderMap) -> impl IntoResponse {
+        let etag_match = headers
+            .get(header::IF_NONE_MATCH)
+            .is_some_and(|v| v == ETAG);
+        let date_match = headers
+            .get(header::IF_MODIFIED_SINCE)
+            .is_some_and(|v| v == LAST_MODIFIED);
+
+        match etag_match || date_match {
+            true => (StatusCode::NOT_MODIFIED, HeaderMap::new(), ""),
+            false => {
+                let mut res_headers = HeaderMap::new();
+                res_headers.insert(header::ETAG, ETAG.parse().unwrap());
+                res_headers.insert(header::LAST_MODIFIED, LAST_MODIFIED.parse().unwrap());
+                (
+                    StatusCode::OK,
+                    res_headers,
+                    "BEGIN:VCALENDAR\nEND:VCALENDAR\n",
+                )
+            }
+        }
+    }
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        axum::serve(listener, Router::new().route("/feed.ics", get(feed)))
+            .await
+            .unwrap();
+    });
+
+    format!("http://{addr}/feed.ics")
+}
+
+#[tokio::test]
+async fn test_send_conditional() {
+    let url = fake_feed().await;
+
+    let Conditional::Modified { body, validators } =
+        send_conditional(client().get(&url), &Validators::default())
+            .await
+            .unwrap()
+    else {
+        panic!("First request should download the feed");
+    };
+    assert!(body.starts_with(b"BEGIN:VCALENDAR"));
+    assert_eq!(validators.etag.as_deref(), Some(ETAG));
+    assert_eq!(validators.last_modified.as_deref(), Some(LAST_MODIFIED));
+
+    let res = send_conditional(client().get(&url), &validators)
+        .await
+        .unwrap();
+    assert!(matches!(res, Conditional::NotModified));
+
+    // Servers without ETag support still get If-Modified-Since
+    let date_only = Validators {
+        etag: None,
+        ..validators
+    };
+    let res = send_conditional(client().get(&url), &date_only)
+        .await
+        .unwrap();
+    assert!(matches!(res, Conditional::NotModified));
+}
@@ -0,0 +1,262 @@
+use std::io::Cursor;
+
+use itertools::Itertools;
+use server::{
+    CalendarColor,
+    ics::{self, EventOccurrence, Frequency, IcalDateTime, RecurrenceRule},
+    model::DateInfoEventMode,
+};
+use time::{
+    Duration, OffsetDateTime, Weekday,
+    macros::{date, datetime},
+};
+use time_tz::timezones;
+
+fn occurrences(body: &str, start: OffsetDateTime, end: OffsetDateTime) -> Vec<EventOccurrence> {
+    let ics = format!("BEGIN:VCALENDAR\nVERSION:2.0\n{body}END:VCALENDAR\n");
+    let events = ical::IcalParser::new(Cursor::new(ics))
+        .flat_map(|c| c.unwrap().events)
+        .collect_vec();
+
+    ics::expand_events(events, timezones::db::asia::BANGKOK, start, end)
+        .into_iter()
+        .sorted_by_key(|o| o.start)
+        .collect_vec()
+}
+
+#[test]
+fn test_rrule_parse() {
+    let rule = "FREQ=MONTHLY;INTERVAL=2;BYDAY=-1FR,+2MO;COUNT=5"
+        .parse::<RecurrenceRule>()
+        .unwrap();
+
+    assert_eq!(rule.freq, Frequency::Monthly);
+    assert_eq!(rule.interval, 2);
+    assert_eq!(rule.count, Some(5));
+    assert_eq!(
+        rule.by_day,
+        vec![(Some(-1), Weekday::Friday), (Some(2), Weekday::Monday)]
+    );
+    assert!("FREQ=HOURLY".parse::<RecurrenceRule>().is_err());
+    assert!("INTERVAL=2".parse::<RecurrenceRule>().is_err());
+}
+
+#[test]
+fn test_weekly_with_exdate_and_override() {
+    let start = datetime!(2026-01-05 00:00 +7);
+    let occ = occurrences(
+        "BEGIN:VEVENT\n\
+         UID:standup\n\
+         SUMMARY:Stand-up\n\
+         DTSTART;TZID=Asia/Bangkok:20260105T090000\n\
+         RRULE:FREQ=WEEKLY;BYDAY=MO,WE\n\
+         EXDATE;TZID=Asia/Bangkok:20260107T090000\n\
+         END:VEVENT\n\
+         BEGIN:VEVENT\n\
+         UID:standup\n\
+         SUMMARY:Stand-up (moved)\n\
+         RECURRENCE-ID;TZID=Asia/Bangkok:20260112T090000\n\
+         DTSTART;TZID=Asia/Bangkok:20260112T140000\n\
+         END:VEVENT\n",
+        start,
+        start + Duration::days(10),
+    );
+
+    assert_eq!(
+        occ.iter()
+            .map(|o| (o.start, o.summary.as_str()))
+            .collect_vec(),
+        vec![
+            (datetime!(2026-01-05 09:00 +7), "Stand-up"),
+            (datetime!(2026-01-12 14:00 +7), "Stand-up (moved)"),
+            (datetime!(2026-01-14 09:00 +7), "Stand-up"),
+        ]
+    );
+    assert_eq!(occ[1].id, "standup/20260112T020000Z");
+    assert!(occ.iter().map(|o| &o.id).all_unique());
+}
+
+#[test]
+fn test_monthly_last_friday_with_rdate_and_count() {
+    let start = datetime!(2026-01-01 00:00 +7);
+    let occ = occurrences(
+        "BEGIN:VEVENT\n\
+         UID:review\n\
+         SUMMARY:Monthly review\n\
+         DTSTART;TZID=Asia/Bangkok:20260130T160000\n\
+         RRULE:FREQ=MONTHLY;BYDAY=-1FR;COUNT=3\n\
+         RDATE;TZID=Asia/Bangkok:20260210T160000\n\
+         END:VEVENT\n",
+        start,
+        start + Duration::days(365),
+    );
+
+    assert_eq!(
+        occ.iter().map(|o| o.start).collect_vec(),
+        vec![
+            datetime!(2026-01-30 16:00 +7),
+            datetime!(2026-02-10 16:00 +7),
+            datetime!(2026-02-27 16:00 +7),
+            datetime!(2026-03-27 16:00 +7),
+        ]
+    );
+}
+
+#[test]
+fn test_daily_until_outside_window() {
+    let start = datetime!(2026-03-10 00:00 +7);
+    let occ = occurrences(
+        "BEGIN:VEVENT\n\
+         UID:sprint\n\
+         SUMMARY:Sprint\n\
+         DTSTART;TZID=Asia/Bangkok:20260301T100000\n\
+         RRULE:FREQ=DAILY;INTERVAL=3;UNTIL=20260316T100000\n\
+         END:VEVENT\n",
+        start,
+        start + Duration::days(30),
+    );
+
+    assert_eq!(
+        occ.iter().map(|o| o.start).collect_vec(),
+        vec![
+            datetime!(2026-03-10 10:00 +7),
+            datetime!(2026-03-13 10:00 +7),
+            datetime!(2026-03-16 10:00 +7),
+        ]
+    );
+}
+
+#[test]
+fn test_date_time_value_kinds() {
+    assert_eq!(
+        IcalDateTime::parse_value("20260301", false, None),
+        Some(IcalDateTime::Date(date!(2026 - 03 - 01)))
+    );
+    assert_eq!(
+        IcalDateTime::parse_value("20260301T090000", false, None),
+        Some(IcalDateTime::Floating(datetime!(2026-03-01 09:00)))
+    );
+    assert_eq!(
+        IcalDateTime::parse_value("20260301T090000Z", false, Some("Asia/Bangkok")),
+        Some(IcalDateTime::Utc(datetime!(2026-03-01 09:00)))
+    );
+    assert_eq!(
+        IcalDateTime::parse_value("20260301T090000", false, Some("Europe/London")),
+        Some(IcalDateTime::Zoned(
+            datetime!(2026-03-01 09:00),
+            timezones::db::europe::LONDON
+        ))
+    );
+    assert!(IcalDateTime::parse_value("2026030", false, None).is_none());
+}
+
+#[test]
+fn test_all_day_and_utc_events() {
+    let start = datetime!(2026-03-01 00:00 +7);
+    let occ = occurrences(
+        "BEGIN:VEVENT\n\
+         UID:offsite\n\
+         SUMMARY:Offsite\n\
+         DTSTART;VALUE=DATE:20260302\n\
+         END:VEVENT\n\
+         BEGIN:VEVENT\n\
+         UID:call\n\
+         SUMMARY:Late call\n\
+         DTSTART:20260302T200000Z\n\
+         END:VEVENT\n",
+        start,
+        start + Duration::days(7),
+    );
+
+    assert_eq!(
+        occ.iter()
+            .map(|o| (o.start, o.all_day, o.summary.as_str()))
+            .collect_vec(),
+        vec![
+            (datetime!(2026-03-02 00:00 +7), true, "Offsite"),
+            (datetime!(2026-03-02 20:00 UTC), false, "Late call"),
+        ]
+    );
+}
+
+#[test]
+fn test_duration_parse() {
+    assert_eq!(ics::parse_duration("PT1H30M"), Some(Duration::minutes(90)));
+    assert_eq!(ics::parse_duration("P2W"), Some(Duration::weeks(2)));
+    assert_eq!(
+        ics::parse_duration("-P1DT2S"),
+        Some(-Duration::seconds(86_402))
+    );
+    assert!(ics::parse_duration("P1H").is_none());
+    assert!(ics::parse_duration("1D").is_none());
+}
+
+#[test]
+fn test_multi_day_events() {
+    let start = datetime!(2026-04-06 00:00 +7);
+    let occ = occurrences(
+        "BEGIN:VEVENT\n\
+         UID:conf\n\
+         SUMMARY:Conference\n\
+         DTSTART;VALUE=DATE:20260406\n\
+         DTEND;VALUE=DATE:20260409\n\
+         END:VEVENT\n\
+         BEGIN:VEVENT\n\
+         UID:trip\n\
+         SUMMARY:Trip\n\
+         DTSTART;TZID=Asia/Bangkok:20260403T180000\n\
+         DURATION:P3DT2H\n\
+         END:VEVENT\n",
+        start,
+        start + Duration::days(7),
+    );
+
+    assert_eq!(
+        occ.iter()
+            .map(|o| (o.summary.as_str(), o.start, o.end))
+            .collect_vec(),
+        vec![
+            (
+                "Trip",
+                datetime!(2026-04-03 18:00 +7),
+                datetime!(2026-04-06 20:00 +7)
+            ),
+            (
+                "Conference",
+                datetime!(2026-04-06 00:00 +7),
+                datetime!(2026-04-09 00:00 +7)
+            ),
+        ]
+    );
+
+    let event = DateInfoEventMode {
+        time: occ[1].start,
+        end: occ[1].end,
+        all_day: true,
+        name: occ[1].summary.clone(),
+        source: "event".to_string(),
+        color: CalendarColor::Black,
+        day: 1,
+        days: 1,
+    };
+    assert_eq!(
+        event.span_dates(timezones::db::UTC),
+        vec![
+            date!(2026 - 04 - 06),
+            date!(2026 - 04 - 07),
+            date!(2026 - 04 - 08)
+        ]
+    );
+
+    let event = DateInfoEventMode {
+        time: occ[0].start,
+        end: occ[0].end,
+        all_day: false,
+        ..event
+    };
+    assert_eq!(event.span_dates(timezones::db::asia::BANGKOK).len(), 4);
+    assert_eq!(
+        event.span_dates(timezones::db::UTC).first(),
+        Some(&date!(2026 - 04 - 03))
+    );
+}
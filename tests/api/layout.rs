@@ -0,0 +1,463 @@
+use std::collections::HashMap;
+
+use image::{Rgb, RgbImage};
+use imageproc::drawing;
+use server::{
+    CalendarColor, HaSensor,
+    layout::{
+        Align, Area, Color, Node, Overflow, Padding, Scale, Size, Surface, Widget, WidgetMap,
+    },
+    model::{
+        CalendarMap, DateInfo, DateInfoEventMode, DateInfoHoliday, HaEntityState, TodoItem,
+        TodoItemStatus, WeatherForecastEntry, WeatherInfo, WeatherInfoAttribute, WeatherInfoState,
+    },
+    widgets::{
+        Agenda, DateBlock, FONTS, Footer, Sensors, Theme, Todo, Weather, WidgetKind, default_layout,
+    },
+};
+use time::macros::{date, datetime};
+use time_tz::timezones;
+
+/// Fills its whole area, and asks for `rows` rows of 10 px.
+struct Block {
+    color: Rgb<u8>,
+    rows: u32,
+}
+
+impl Widget for Block {
+    fn measure(&self, width: u32, _height: u32, scale: Scale) -> (u32, u32) {
+        (width, self.rows * scale.px(10))
+    }
+
+    fn draw(&self, surface: &mut Surface<'_>, area: Area) {
+        // Twice as tall as its area, to spill over
+        surface.fill(
+            Area {
+                h: area.h * 2,
+                ..area
+            },
+            self.color,
+        );
+    }
+}
+
+fn widgets(todo_rows: u32, sensor_rows: u32) -> WidgetMap<'static> {
+    let mut widgets = WidgetMap::new();
+    [
+        (WidgetKind::DateBlock, 0),
+        (WidgetKind::Weather, 0),
+        (WidgetKind::Agenda, 0),
+        (WidgetKind::Todo, todo_rows),
+        (WidgetKind::Sensors, sensor_rows),
+        (WidgetKind::Footer, 0),
+    ]
+    .into_iter()
+    .for_each(|(kind, rows)| {
+        widgets.insert(
+            kind,
+            Box::new(Block {
+                color: Rgb([0, 0, 0]),
+                rows,
+            }),
+        );
+    });
+
+    widgets
+}
+
+#[test]
+fn test_default_layout_positions() {
+    let page = Area::new(0, 0, 400, 300);
+    let placed = default_layout().place(page, &widgets(2, 1), Scale::default());
+
+    assert_eq!(
+        placed,
+        vec![
+            (WidgetKind::DateBlock, Area::new(10, 10, 120, 265)),
+            (WidgetKind::Weather, Area::new(150, 10, 240, 40)),
+            (WidgetKind::Agenda, Area::new(140, 60, 250, 185)),
+            (WidgetKind::Todo, Area::new(140, 245, 250, 20)),
+            (WidgetKind::Sensors, Area::new(140, 265, 250, 10)),
+            (WidgetKind::Footer, Area::new(10, 280, 390, 20)),
+        ]
+    );
+
+    // Empty auto boxes are left out and the agenda takes their room
+    let placed = default_layout().place(page, &widgets(0, 0), Scale::default());
+    assert!(placed.contains(&(WidgetKind::Agenda, Area::new(140, 60, 250, 215))));
+    assert!(!placed.iter().any(|(kind, _)| *kind == WidgetKind::Todo));
+}
+
+#[test]
+fn test_default_layout_scaled() {
+    // Twice the design size
+    let page = Area::new(0, 0, 800, 600);
+    let placed = default_layout().place(page, &widgets(2, 1), Scale(2.0));
+
+    assert_eq!(
+        placed,
+        vec![
+            (WidgetKind::DateBlock, Area::new(20, 20, 240, 530)),
+            (WidgetKind::Weather, Area::new(300, 20, 480, 80)),
+            (WidgetKind::Agenda, Area::new(280, 120, 500, 370)),
+            (WidgetKind::Todo, Area::new(280, 490, 500, 40)),
+            (WidgetKind::Sensors, Area::new(280, 530, 500, 20)),
+            (WidgetKind::Footer, Area::new(20, 560, 780, 40)),
+        ]
+    );
+}
+
+#[test]
+fn test_layout_fill_and_align() {
+    let mut widgets = WidgetMap::new();
+    widgets.insert(
+        WidgetKind::Weather,
+        Box::new(Block {
+            color: Rgb([0, 0, 0]),
+            rows: 1,
+        }),
+    );
+    widgets.insert(
+        WidgetKind::Agenda,
+        Box::new(Block {
+            color: Rgb([0, 0, 0]),
+            rows: 1,
+        }),
+    );
+
+    // Fills share the room left, the last one takes the remainder
+    let row = Node::row(vec![
+        Node::widget(WidgetKind::Weather),
+        Node::widget(WidgetKind::Agenda).width(Size::Fixed(10)),
+        Node::widget(WidgetKind::Weather),
+    ])
+    .padding(Padding::all(1));
+    let placed = row.place(Area::new(0, 0, 33, 10), &widgets, Scale::default());
+    assert_eq!(placed[0].1, Area::new(1, 1, 10, 8));
+    assert_eq!(placed[1].1, Area::new(11, 1, 10, 8));
+    assert_eq!(placed[2].1, Area::new(21, 1, 11, 8));
+
+    // Narrower children are aligned across a column
+    let column = Node::column(vec![
+        Node::widget(WidgetKind::Weather).width(Size::Fixed(10)),
+        Node::widget(WidgetKind::Agenda)
+            .width(Size::Fixed(20))
+            .height(Size::Auto),
+    ])
+    .align(Align::Center);
+    let placed = column.place(Area::new(0, 0, 40, 40), &widgets, Scale::default());
+    assert_eq!(placed[0].1, Area::new(15, 0, 10, 30));
+    assert_eq!(placed[1].1, Area::new(10, 30, 20, 10));
+}
+
+#[test]
+fn test_layout_overflow_clip() {
+    let red = Rgb([255, 0, 0]);
+    let white = Color::White.rgb();
+    let mut widgets = WidgetMap::new();
+    widgets.insert(
+        WidgetKind::Agenda,
+        Box::new(Block {
+            color: red,
+            rows: 0,
+        }),
+    );
+    let page = |overflow: Overflow| {
+        let mut image = RgbImage::new(10, 20);
+        Node::column(vec![
+            Node::column(vec![Node::widget(WidgetKind::Agenda)])
+                .height(Size::Fixed(10))
+                .padding(Padding {
+                    left: 2,
+                    ..Default::default()
+                })
+                .overflow(overflow),
+        ])
+        .background(Color::White)
+        .draw(&mut image, &widgets, Scale::default());
+        image
+    };
+
+    // The widget spills below its box unless the box clips it
+    let image = page(Overflow::Visible);
+    assert_eq!(*image.get_pixel(5, 15), red);
+    let image = page(Overflow::Clip);
+    assert_eq!(*image.get_pixel(5, 5), red);
+    assert_eq!(*image.get_pixel(5, 15), white);
+    // Padding is inside the clip, but not drawn on
+    assert_eq!(*image.get_pixel(1, 5), white);
+}
+
+/// A filled square with a cross drawn over its middle.
+struct Marked;
+
+impl Widget for Marked {
+    fn draw(&self, surface: &mut Surface<'_>, area: Area) {
+        surface.fill(area, Color::Gray.rgb());
+        drawing::draw_cross_mut(surface, Color::Black.rgb(), 5, 5);
+    }
+}
+
+#[test]
+fn test_layout_dither_mask() {
+    let mut widgets = WidgetMap::new();
+    widgets.insert(WidgetKind::Agenda, Box::new(Marked));
+    let mut image = RgbImage::new(10, 10);
+    let mask = Node::widget(WidgetKind::Agenda).draw(&mut image, &widgets, Scale::default());
+
+    // Fills are dithered, lines and text drawn over them are not
+    assert_eq!(mask.get_pixel(0, 0).0, [255]);
+    assert_eq!(mask.get_pixel(5, 5).0, [0]);
+    assert_eq!(mask.get_pixel(4, 5).0, [0]);
+    assert_eq!(mask.get_pixel(8, 8).0, [255]);
+}
+
+#[test]
+fn test_layout_narrow_widgets() {
+    let fonts = FONTS.as_ref().unwrap();
+    let theme = Theme::default();
+    let sensor = HaSensor {
+        entity_id: "sensor.power".to_string(),
+        label: "Power".to_string(),
+        icon: None,
+        format: None,
+    };
+    let mut widgets = WidgetMap::new();
+    widgets.insert(
+        WidgetKind::Agenda,
+        Box::new(Agenda {
+            fonts,
+            theme: &theme,
+            calendar: CalendarMap::from([(
+                date!(2026 - 05 - 01),
+                DateInfo {
+                    date: date!(2026 - 05 - 01),
+                    holidays: vec![],
+                    events: HashMap::new(),
+                },
+            )]),
+            stale: true,
+        }),
+    );
+    widgets.insert(
+        WidgetKind::Todo,
+        Box::new(Todo {
+            fonts,
+            theme: &theme,
+            items: vec![TodoItem {
+                summary: "Water the plants".to_string(),
+                uid: None,
+                status: TodoItemStatus::NeedsAction,
+                due: Some("2026-05-02".to_string()),
+            }],
+            now: datetime!(2026-05-01 09:00 +7),
+            tz: timezones::db::asia::BANGKOK,
+            stale: true,
+        }),
+    );
+    widgets.insert(
+        WidgetKind::Sensors,
+        Box::new(Sensors {
+            fonts,
+            theme: &theme,
+            sensors: vec![(sensor, None)],
+            stale: true,
+        }),
+    );
+
+    // Marks and due dates at the right of a column narrower than they are
+    // are drawn from its left instead
+    let mut image = RgbImage::new(4, 120);
+    Node::column(vec![
+        Node::widget(WidgetKind::Agenda),
+        Node::widget(WidgetKind::Todo).height(Size::Auto),
+        Node::widget(WidgetKind::Sensors).height(Size::Auto),
+    ])
+    .background(Color::White)
+    .draw(&mut image, &widgets, Scale::default());
+}
+
+#[test]
+fn test_default_layout_golden() {
+    let fonts = FONTS.as_ref().unwrap();
+    let theme = Theme::default();
+    let tz = timezones::db::asia::BANGKOK;
+    let now = datetime!(2026-05-01 09:00 +7);
+    let today = date!(2026 - 05 - 01);
+    let event = |time, end, name: &str| DateInfoEventMode {
+        time,
+        end,
+        all_day: false,
+        name: name.to_string(),
+        source: "home".to_string(),
+        color: CalendarColor::Black,
+        day: 1,
+        days: 1,
+    };
+    let calendar = CalendarMap::from([
+        (
+            today,
+            DateInfo {
+                date: today,
+                holidays: vec![DateInfoHoliday {
+                    name: "Labour Day".to_string(),
+                    source: "holidays".to_string(),
+                    color: CalendarColor::Red,
+                }],
+                events: HashMap::from([
+                    (
+                        "dentist".to_string(),
+                        event(
+                            datetime!(2026-05-01 10:30 +7),
+                            datetime!(2026-05-01 11:30 +7),
+                            "Dentist",
+                        ),
+                    ),
+                    (
+                        "standup".to_string(),
+                        event(
+                            datetime!(2026-05-01 09:00 +7),
+                            datetime!(2026-05-01 09:15 +7),
+                            "Standup",
+                        ),
+                    ),
+                    (
+                        "bank".to_string(),
+                        event(
+                            datetime!(2026-05-01 10:30 +7),
+                            datetime!(2026-05-01 11:00 +7),
+                            "Call the bank",
+                        ),
+                    ),
+                    (
+                        "closed".to_string(),
+                        DateInfoEventMode {
+                            all_day: true,
+                            ..event(
+                                datetime!(2026-05-01 00:00 +7),
+                                datetime!(2026-05-02 00:00 +7),
+                                "Office closed",
+                            )
+                        },
+                    ),
+                ]),
+            },
+        ),
+        (
+            date!(2026 - 05 - 02),
+            DateInfo {
+                date: date!(2026 - 05 - 02),
+                holidays: vec![],
+                events: HashMap::from([(
+                    "market".to_string(),
+                    event(
+                        datetime!(2026-05-02 08:00 +7),
+                        datetime!(2026-05-02 09:00 +7),
+                        "Market",
+                    ),
+                )]),
+            },
+        ),
+    ]);
+    let forecast = (1..=3)
+        .map(|i| {
+            let entry = WeatherForecastEntry {
+                datetime: Some(now + time::Duration::hours(3 * i)),
+                condition: Some(WeatherInfoState::Rainy),
+                temperature: Some(30.0 + i as f32),
+                templow: None,
+                precipitation_probability: Some(60.0),
+                precipitation: None,
+            };
+            (format!("{:02}h", 9 + 3 * i), entry)
+        })
+        .collect();
+    let sensor = HaSensor {
+        entity_id: "sensor.power".to_string(),
+        label: "Power".to_string(),
+        icon: None,
+        format: None,
+    };
+    let power = HaEntityState {
+        state: "412".to_string(),
+        attributes: HashMap::from([("unit_of_measurement".to_string(), "W".into())]),
+    };
+
+    let mut widgets = WidgetMap::new();
+    widgets.insert(
+        WidgetKind::DateBlock,
+        Box::new(DateBlock {
+            fonts,
+            theme: &theme,
+            time: now,
+            is_holiday: true,
+            is_event: true,
+        }),
+    );
+    widgets.insert(
+        WidgetKind::Weather,
+        Box::new(Weather {
+            fonts,
+            theme: &theme,
+            current: Some(WeatherInfo {
+                state: WeatherInfoState::Cloudy,
+                attributes: WeatherInfoAttribute {
+                    temperature: Some(29.5),
+                    temperature_unit: Some("°C".to_string()),
+                    humidity: Some(74.0),
+                    ..Default::default()
+                },
+            }),
+            forecast,
+            stale: false,
+        }),
+    );
+    widgets.insert(
+        WidgetKind::Agenda,
+        Box::new(Agenda {
+            fonts,
+            theme: &theme,
+            calendar,
+            stale: false,
+        }),
+    );
+    widgets.insert(
+        WidgetKind::Todo,
+        Box::new(Todo {
+            fonts,
+            theme: &theme,
+            items: vec![TodoItem {
+                summary: "Water the plants".to_string(),
+                uid: None,
+                status: TodoItemStatus::NeedsAction,
+                due: Some("2026-05-02".to_string()),
+            }],
+            now,
+            tz,
+            stale: false,
+        }),
+    );
+    widgets.insert(
+        WidgetKind::Sensors,
+        Box::new(Sensors {
+            fonts,
+            theme: &theme,
+            sensors: vec![(sensor, Some(power))],
+            stale: false,
+        }),
+    );
+    widgets.insert(
+        WidgetKind::Footer,
+        Box::new(Footer {
+            fonts,
+            theme: &theme,
+            last_update: now,
+        }),
+    );
+
+    // The default page at a fixed time and data draws the same pixels as the
+    // renderer before the layout did, the events of a day in order
+    let mut image = RgbImage::new(400, 300);
+    default_layout().draw(&mut image, &widgets, Scale::default());
+    assert_eq!(crc32fast::hash(image.as_raw()), 2884146034);
+}
@@ -1,2 +1,18 @@
+mod caldav;
+mod cfg;
+mod cron;
+mod db;
+mod dither;
+mod epaper_page;
+mod epd;
+mod ha;
 mod health_check;
 mod helpers;
+mod http;
+mod ics;
+mod layout;
+mod model;
+mod page;
+mod panel;
+mod refresh;
+mod retry;
@@ -0,0 +1,141 @@
+use server::model::{
+    HaEntityState, HaForecastResponse, HaServiceResponse, HaTodoResponse, TodoDue, TodoItemStatus,
+    WeatherInfo, WeatherInfoState,
+};
+use time::macros::{date, datetime};
+use time_tz::timezones;
+
+#[test]
+fn test_weather_info_partial_attributes() {
+    let weather = serde_json::from_str::<WeatherInfo>(
+        r#"{
+            "entity_id": "weather.home",
+            "state": "partlycloudy",
+            "attributes": {
+                "temperature": 31.2,
+                "temperature_unit": "°C",
+                "humidity": 64,
+                "friendly_name": "Home"
+            }
+        }"#,
+    )
+    .unwrap();
+
+    assert_eq!(weather.state, WeatherInfoState::Partlycloudy);
+    assert_eq!(weather.attributes.temperature, Some(31.2));
+    assert_eq!(weather.attributes.dew_point, None);
+    assert_eq!(weather.attributes.cloud_coverage, None);
+}
+
+#[test]
+fn test_weather_info_unknown_state() {
+    let weather =
+        serde_json::from_str::<WeatherInfo>(r#"{"state": "unavailable", "attributes": {}}"#)
+            .unwrap();
+
+    assert_eq!(weather.state, WeatherInfoState::Unknown);
+    assert!(weather.attributes.temperature.is_none());
+}
+
+#[test]
+fn test_weather_forecast_service_response() {
+    let mut res = serde_json::from_str::<HaServiceResponse<HaForecastResponse>>(
+        r#"{
+            "changed_states": [],
+            "service_response": {
+                "weather.home": {
+                    "forecast": [
+                        {
+                            "condition": "rainy",
+                            "datetime": "2026-10-18T05:00:00+00:00",
+                            "temperature": 32.0,
+                            "templow": 25.4,
+                            "precipitation_probability": 60
+                        },
+                        {
+                            "condition": "cloudy",
+                            "datetime": "2026-10-19T05:00:00+00:00",
+                            "temperature": 31.0
+                        }
+                    ]
+                }
+            }
+        }"#,
+    )
+    .unwrap();
+    let forecast = res
+        .service_response
+        .remove("weather.home")
+        .unwrap()
+        .forecast;
+
+    assert_eq!(forecast.len(), 2);
+    assert_eq!(forecast[0].condition, Some(WeatherInfoState::Rainy));
+    assert_eq!(forecast[0].datetime, Some(datetime!(2026-10-18 05:00 UTC)));
+    assert_eq!(forecast[0].templow, Some(25.4));
+    assert_eq!(forecast[0].precipitation_probability, Some(60.0));
+    assert_eq!(forecast[1].templow, None);
+    assert_eq!(forecast[1].precipitation_probability, None);
+}
+
+#[test]
+fn test_entity_state_format() {
+    let sensor = serde_json::from_str::<HaEntityState>(
+        r#"{
+            "entity_id": "sensor.indoor",
+            "state": "24.4567",
+            "attributes": {"unit_of_measurement": "°C", "battery": 87, "friendly_name": "Indoor"}
+        }"#,
+    )
+    .unwrap();
+
+    assert_eq!(sensor.format("{state}{unit}"), "24.4567°C");
+    assert_eq!(sensor.format("{state:.1} {unit}"), "24.5 °C");
+    assert_eq!(
+        sensor.format("{attr:friendly_name} {attr:battery}%"),
+        "Indoor 87%"
+    );
+    assert_eq!(sensor.format("{attr:missing}|{nope}|{"), "|{nope}|{");
+
+    let lock = HaEntityState {
+        state: "locked".to_string(),
+        attributes: Default::default(),
+    };
+    assert_eq!(lock.format("{state:.1}{unit}"), "locked");
+}
+
+#[test]
+fn test_todo_items_due_and_overdue() {
+    let mut res = serde_json::from_str::<HaServiceResponse<HaTodoResponse>>(
+        r#"{
+            "service_response": {
+                "todo.home": {
+                    "items": [
+                        {"summary": "Pay bill", "uid": "1", "status": "needs_action", "due": "2026-05-01"},
+                        {"summary": "Call", "uid": "2", "status": "needs_action", "due": "2026-05-02T09:00:00+07:00"},
+                        {"summary": "Milk", "uid": "3", "status": "needs_action"},
+                        {"summary": "Done", "uid": "4", "status": "completed", "due": "2026-04-01"}
+                    ]
+                }
+            }
+        }"#,
+    )
+    .unwrap();
+    let items = res.service_response.remove("todo.home").unwrap().items;
+    let tz = timezones::db::asia::BANGKOK;
+    let now = datetime!(2026-05-02 08:00 +7);
+
+    assert_eq!(items[0].due(), Some(TodoDue::Date(date!(2026 - 05 - 01))));
+    assert_eq!(
+        items[1].due(),
+        Some(TodoDue::DateTime(datetime!(2026-05-02 09:00 +7)))
+    );
+    assert_eq!(items[2].due(), None);
+    assert_eq!(items[3].status, TodoItemStatus::Completed);
+
+    assert!(items[0].is_overdue(now, tz));
+    assert!(!items[1].is_overdue(now, tz));
+    assert!(items[1].is_overdue(datetime!(2026-05-02 09:30 +7), tz));
+    assert!(!items[2].is_overdue(now, tz));
+    assert!(!items[3].is_overdue(now, tz));
+}
@@ -0,0 +1,146 @@
+use std::{path::PathBuf, sync::Arc, time::Duration};
+
+use server::{
+    layout::{Color, Size},
+    page::{self, PageConfig},
+    widgets::default_layout,
+};
+use tokio::sync::RwLock;
+use uuid::Uuid;
+
+const YAML: &str = r#"
+layout:
+  column:
+    - widget: agenda
+    - widget: footer
+      height: 25
+      background: gray
+theme:
+  colors:
+    holiday: black
+  font_sizes:
+    agenda: 14
+"#;
+
+fn temp_file(ext: &str, text: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("layout-{}.{ext}", Uuid::now_v7()));
+    std::fs::write(&path, text).unwrap();
+
+    path
+}
+
+#[test]
+fn test_page_config_example() {
+    let page = PageConfig::load("layout.example.toml".as_ref()).unwrap();
+    let default = PageConfig::default();
+
+    assert_eq!(page.layout, default_layout());
+    assert_eq!(page.theme, default.theme);
+}
+
+#[test]
+fn test_page_config_formats() {
+    let yaml = PageConfig::parse(YAML, "yaml").unwrap();
+    let json = PageConfig::parse(
+        r#"{
+            "layout": {"column": [
+                {"widget": "agenda"},
+                {"widget": "footer", "height": 25, "background": "gray"}
+            ]},
+            "theme": {"colors": {"holiday": "black"}, "font_sizes": {"agenda": 14}}
+        }"#,
+        "json",
+    )
+    .unwrap();
+    let toml = PageConfig::parse(
+        r#"
+        [layout]
+        column = [
+            { widget = "agenda" },
+            { widget = "footer", height = 25, background = "gray" },
+        ]
+
+        [theme]
+        colors = { holiday = "black" }
+        font_sizes = { agenda = 14 }
+        "#,
+        "toml",
+    )
+    .unwrap();
+
+    assert_eq!(yaml.layout, json.layout);
+    assert_eq!(yaml.layout, toml.layout);
+    assert_eq!(yaml.theme, json.theme);
+    assert_eq!(yaml.theme, toml.theme);
+    assert_eq!(yaml.theme.colors.holiday, Color::Black);
+    // Unset values keep their default
+    assert_eq!(yaml.theme.colors.red_calendar, Color::Red);
+    assert_eq!(yaml.theme.font_sizes.agenda, 14);
+    assert_eq!(yaml.theme.font_sizes.todo, 13);
+}
+
+#[test]
+fn test_page_config_invalid() {
+    let invalid = [
+        ("layout:\n  widget: clock\n", "yaml", "clock"),
+        (
+            "layout:\n  widget: agenda\n  row: []\n",
+            "yaml",
+            "exactly one of",
+        ),
+        ("layout:\n  widget: agenda\n  width: wide\n", "yaml", "wide"),
+        ("layout:\n  widget: agenda\n  margin: 4\n", "yaml", "margin"),
+        (
+            "theme:\n  font_sizes:\n    agenda: 0\n",
+            "yaml",
+            "font_sizes.agenda",
+        ),
+        ("theme:\n  colors:\n    text: purple\n", "yaml", "purple"),
+        ("{}", "ini", "Unknown layout format"),
+    ];
+
+    for (text, format, needle) in invalid {
+        let err = PageConfig::parse(text, format).unwrap_err();
+        let msg = format!("{err:#}");
+        assert!(msg.contains(needle), "{msg:?} should mention {needle:?}");
+    }
+
+    // Sizes are pixels or keywords
+    let page = PageConfig::parse("layout:\n  widget: agenda\n  width: auto\n", "yaml").unwrap();
+    assert_eq!(page.layout.width, Size::Auto);
+}
+
+#[tokio::test]
+async fn test_page_config_reload_keeps_last_good() {
+    let path = temp_file("yaml", YAML);
+    let page = Arc::new(RwLock::new(Arc::new(PageConfig::default())));
+
+    page::reload(&path, &page).await.unwrap();
+    let good = page.read().await.clone();
+    assert_eq!(good.theme.font_sizes.agenda, 14);
+
+    std::fs::write(&path, "layout:\n  widget: clock\n").unwrap();
+    assert!(page::reload(&path, &page).await.is_err());
+    assert!(Arc::ptr_eq(&good, &*page.read().await));
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn test_page_config_watch() {
+    let path = temp_file("yaml", YAML);
+    let page = Arc::new(RwLock::new(Arc::new(PageConfig::load(&path).unwrap())));
+    tokio::spawn(page::watch(path.clone(), page.clone()));
+
+    // Changes are picked up, broken files are ignored
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    std::fs::write(&path, "layout:\n  widget: clock\n").unwrap();
+    tokio::time::sleep(Duration::from_secs(3)).await;
+    assert_eq!(page.read().await.theme.font_sizes.agenda, 14);
+
+    std::fs::write(&path, YAML.replace("agenda: 14", "agenda: 18")).unwrap();
+    tokio::time::sleep(Duration::from_secs(3)).await;
+    assert_eq!(page.read().await.theme.font_sizes.agenda, 18);
+
+    std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,115 @@
+use image::{GrayImage, Luma, Rgb, RgbImage};
+use server::{
+    dither::Dither,
+    layout::Color,
+    panel::{self, PanelProfile, Rotation},
+};
+
+fn profile(width: u32, height: u32, palette: &[Color]) -> PanelProfile {
+    PanelProfile {
+        width,
+        height,
+        palette: palette.to_vec(),
+        rotation: Rotation::None,
+        scale: None,
+    }
+}
+
+#[test]
+fn test_panel_builtin_profiles() {
+    let profiles = panel::builtin_profiles();
+
+    assert!(profiles.contains_key(panel::DEFAULT_PANEL));
+    assert!(profiles.values().all(|p| p.validate().is_ok()));
+    // The default panel is drawn at the design size
+    assert_eq!(profiles[panel::DEFAULT_PANEL].scale(), 1.0);
+    assert_eq!(
+        profiles["7.50in-v2-bw"].palette,
+        [Color::Black, Color::White]
+    );
+    assert_eq!(profiles["5.65in-f-acep"].palette.len(), 7);
+}
+
+#[test]
+fn test_panel_profile_file() {
+    let p: PanelProfile = serde_json::from_str(
+        r#"{"width": 480, "height": 800, "palette": ["black", "white"], "rotation": 270}"#,
+    )
+    .unwrap();
+    assert_eq!(p.rotation, Rotation::ThreeQuarters);
+    // Drawn landscape, then turned to the portrait panel
+    assert_eq!(p.page_size(), (800, 480));
+    assert_eq!(p.scale(), 1.6);
+    assert_eq!(p.rotate(RgbImage::new(800, 480)).dimensions(), (480, 800));
+
+    let invalid = [
+        r#"{"width": 400, "height": 300, "palette": ["black"]}"#,
+        r#"{"width": 0, "height": 300, "palette": ["black", "white"]}"#,
+        r#"{"width": 400, "height": 300, "palette": ["black", "white"], "scale": 0}"#,
+    ];
+    for json in invalid {
+        let p: PanelProfile = serde_json::from_str(json).unwrap();
+        assert!(p.validate().is_err(), "{json} should be invalid");
+    }
+    assert!(
+        serde_json::from_str::<PanelProfile>(
+            r#"{"width": 400, "height": 300, "palette": ["black", "white"], "rotation": 45}"#
+        )
+        .is_err()
+    );
+}
+
+#[test]
+fn test_panel_inks() {
+    let bw = profile(2, 2, &[Color::Black, Color::White]);
+    let bwr = profile(2, 2, &[Color::Black, Color::White, Color::Red]);
+
+    // Missing inks fall back to the nearest one, gray stays a tint
+    assert_eq!(bw.ink(Color::Red), Color::Black);
+    assert_eq!(bw.ink(Color::Yellow), Color::White);
+    assert_eq!(bw.ink(Color::Gray), Color::Gray);
+    assert_eq!(bwr.ink(Color::Red), Color::Red);
+    assert_eq!(bwr.ink(Color::Orange), Color::Red);
+}
+
+#[test]
+fn test_panel_quantize() {
+    let bwr = profile(3, 1, &[Color::Black, Color::White, Color::Red]);
+    let image = RgbImage::from_fn(3, 1, |x, _| {
+        [Color::Black, Color::White, Color::Red][x as usize].rgb()
+    });
+
+    // Inks are kept as they are
+    let fill = GrayImage::from_pixel(3, 1, Luma([255]));
+    assert_eq!(
+        bwr.quantize(&image, &fill, Dither::FloydSteinberg),
+        [Color::Black, Color::White, Color::Red]
+    );
+
+    // Gray is mixed from black and white, never red
+    let acep = profile(
+        16,
+        16,
+        &[
+            Color::Black,
+            Color::White,
+            Color::Green,
+            Color::Blue,
+            Color::Red,
+            Color::Yellow,
+            Color::Orange,
+        ],
+    );
+    let gray = RgbImage::from_pixel(16, 16, Rgb([128, 128, 128]));
+    let fill = GrayImage::from_pixel(16, 16, Luma([255]));
+    let inks = acep.quantize(&gray, &fill, Dither::FloydSteinberg);
+    let black = inks.iter().filter(|c| **c == Color::Black).count();
+    assert!(
+        inks.iter()
+            .all(|c| matches!(c, Color::Black | Color::White))
+    );
+    assert!(
+        (96..=160).contains(&black),
+        "{black} of 256 pixels are black"
+    );
+}
@@ -0,0 +1,213 @@
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::Duration,
+};
+
+use axum::{
+    Router,
+    body::Body,
+    http::{Request, StatusCode, header},
+    routing::get,
+};
+use serde_json::{Value, json};
+use server::{
+    CalendarProvider, CalendarSource, CalendarSourceKind, HaSensor, cron::entity_sources,
+};
+use tokio::net::TcpListener;
+use tower::ServiceExt;
+
+use crate::helpers::*;
+
+/// Empty feed counting its downloads.
+async fn fake_feed(hits: Arc<AtomicUsize>) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let app = Router::new().route(
+        "/feed.ics",
+        get(move || async move {
+            hits.fetch_add(1, Ordering::SeqCst);
+            "BEGIN:VCALENDAR\nEND:VCALENDAR\n"
+        }),
+    );
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+    format!("http://{addr}/feed.ics")
+}
+
+fn source(name: &str, url: String, entity_id: Option<&str>) -> CalendarSource {
+    CalendarSource {
+        name: name.to_string(),
+        provider: match entity_id {
+            Some(_) => CalendarProvider::HomeAssistant,
+            None => CalendarProvider::Ics,
+        },
+        url,
+        entity_id: entity_id.map(str::to_string),
+        kind: CalendarSourceKind::Event,
+        color: None,
+        auth_header: None,
+        username: None,
+        password: None,
+        schedule: None,
+        retry: None,
+    }
+}
+
+fn post(uri: &str, token: &str, body: Value) -> Request<Body> {
+    Request::post(uri)
+        .header(header::AUTHORIZATION, token)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap()
+}
+
+async fn body_json(router: &Router, req: Request<Body>) -> (StatusCode, Value) {
+    let resp = router.clone().oneshot(req).await.unwrap();
+    let status = resp.status();
+    let body = axum::body::to_bytes(resp.into_body(), usize::MAX)
+        .await
+        .unwrap();
+
+    (status, serde_json::from_slice(&body).unwrap())
+}
+
+#[tokio::test]
+async fn test_refresh_debounced() {
+    let app = TestApp::new().await;
+    let hits = Arc::new(AtomicUsize::new(0));
+    let mut cfg = (*app.cfg).clone();
+    cfg.calendar_sources = vec![source("work", fake_feed(hits.clone()).await, None)];
+    let token = cfg.access_token.clone();
+    let router = server::router(Arc::new(cfg), app.db.clone(), app.shared.clone());
+
+    let (status, res) = body_json(
+        &router,
+        post("/refresh", &token, json!({"sources": ["work"]})),
+    )
+    .await;
+    assert_eq!(status, StatusCode::ACCEPTED);
+    assert_eq!(res["refreshed"], json!(["work"]));
+
+    // Answered before the fetch is done
+    tokio::time::timeout(Duration::from_secs(10), async {
+        while app
+            .shared
+            .status
+            .read()
+            .await
+            .get("work")
+            .is_none_or(|s| s.last_success.is_none())
+        {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await
+    .expect("The refresh did not fetch the source");
+    assert_eq!(hits.load(Ordering::SeqCst), 1);
+
+    // Concurrent and repeated calls are skipped
+    let (a, b) = tokio::join!(
+        body_json(
+            &router,
+            post("/refresh", &token, json!({"sources": ["work"]}))
+        ),
+        body_json(
+            &router,
+            post("/refresh", &token, json!({"sources": ["work"]}))
+        ),
+    );
+    assert_eq!(a.1["debounced"], json!(["work"]));
+    assert_eq!(b.1["debounced"], json!(["work"]));
+    assert_eq!(hits.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn test_refresh_ha_unauthorized() {
+    let app = TestApp::new().await;
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let ha = Router::new().fallback(|| async { StatusCode::UNAUTHORIZED });
+    tokio::spawn(async move { axum::serve(listener, ha).await.unwrap() });
+    let mut cfg = (*app.cfg).clone();
+    cfg.ha_url = format!("http://{addr}");
+    cfg.ha_retry.attempts = 1;
+    let token = cfg.access_token.clone();
+    let router = server::router(Arc::new(cfg), app.db.clone(), app.shared.clone());
+
+    let (status, _) = body_json(
+        &router,
+        post("/refresh", &token, json!({"sources": ["home-assistant"]})),
+    )
+    .await;
+    assert_eq!(status, StatusCode::ACCEPTED);
+
+    // Reported as the HTTP error it is, not as unreadable data
+    let error = tokio::time::timeout(Duration::from_secs(10), async {
+        loop {
+            let status = app.shared.status.read().await;
+            if let Some(e) = status
+                .get("home-assistant")
+                .and_then(|s| s.last_error.clone())
+            {
+                break e;
+            }
+            drop(status);
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await
+    .expect("The refresh did not fail");
+    assert!(error.contains("401"), "{error}");
+}
+
+#[tokio::test]
+async fn test_refresh_rejected() {
+    let app = TestApp::new().await;
+
+    let req = Request::post("/refresh").body(Body::empty()).unwrap();
+    assert_eq!(app.request(req).await.status(), StatusCode::UNAUTHORIZED);
+
+    let req = post(
+        "/refresh",
+        &app.cfg.access_token,
+        json!({"sources": ["nope"]}),
+    );
+    assert_eq!(app.request(req).await.status(), StatusCode::BAD_REQUEST);
+
+    let req = post(
+        "/webhook/home_assistant",
+        &app.cfg.access_token,
+        json!({"entity_id": "light.kitchen"}),
+    );
+    assert_eq!(app.request(req).await.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_webhook_entity_sources() {
+    let app = TestApp::new().await;
+    let mut cfg = (*app.cfg).clone();
+    cfg.calendar_sources = vec![
+        source("family", String::new(), Some("calendar.family")),
+        source("work", "http://127.0.0.1:9/work.ics".to_string(), None),
+    ];
+    cfg.ha_sensors = vec![HaSensor {
+        entity_id: "sensor.co2".to_string(),
+        label: "CO2".to_string(),
+        icon: None,
+        format: None,
+    }];
+    cfg.ha_todo_entities = vec!["todo.home".to_string()];
+    let cfg = Arc::new(cfg);
+
+    assert_eq!(entity_sources(&cfg, "calendar.family"), ["family"]);
+    assert_eq!(
+        entity_sources(&cfg, &cfg.ha_weather_entity),
+        ["home-assistant", "forecast"]
+    );
+    assert_eq!(entity_sources(&cfg, "sensor.co2"), ["home-assistant"]);
+    assert_eq!(entity_sources(&cfg, "todo.home"), ["todo"]);
+    assert!(entity_sources(&cfg, "light.kitchen").is_empty());
+}
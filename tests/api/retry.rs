@@ -0,0 +1,123 @@
+use std::{
+    sync::atomic::{AtomicU32, Ordering},
+    time::Duration,
+};
+
+use serde_json::json;
+use server::{
+    CalendarSource,
+    api_error::ApiError,
+    cron,
+    model::SourceStatusArc,
+    retry::{self, Outcome, RetryPolicy},
+};
+
+use crate::helpers::*;
+
+fn policy() -> RetryPolicy {
+    RetryPolicy {
+        attempts: 3,
+        base_delay: Duration::ZERO,
+        failure_threshold: 2,
+        cooldown: Duration::from_secs(60),
+        max_cooldown: Duration::from_secs(600),
+    }
+}
+
+fn failure() -> Result<(), ApiError> {
+    Err(ApiError::InvalidRequest("feed is down".to_string()))
+}
+
+#[tokio::test]
+async fn test_retry_until_success() {
+    let status = SourceStatusArc::default();
+    let calls = AtomicU32::new(0);
+
+    let res = retry::run("feed", &status, &policy(), || async {
+        match calls.fetch_add(1, Ordering::SeqCst) {
+            0 | 1 => failure(),
+            _ => Ok(()),
+        }
+    })
+    .await;
+
+    assert_eq!(res.unwrap(), Outcome::Fetched);
+    assert_eq!(calls.load(Ordering::SeqCst), 3);
+    let status = status.read().await["feed"].clone();
+    assert!(status.last_success.is_some());
+    assert_eq!(status.last_error, None);
+    assert_eq!(status.consecutive_failures, 0);
+}
+
+#[tokio::test]
+async fn test_circuit_breaker_opens() {
+    let status = SourceStatusArc::default();
+    let calls = AtomicU32::new(0);
+    let policy = policy();
+    let run = || {
+        retry::run("feed", &status, &policy, || async {
+            calls.fetch_add(1, Ordering::SeqCst);
+            failure()
+        })
+    };
+
+    assert!(run().await.is_err());
+    assert_eq!(calls.load(Ordering::SeqCst), 3);
+    assert!(status.read().await["feed"].open_until.is_none());
+
+    assert!(run().await.is_err());
+    assert_eq!(calls.load(Ordering::SeqCst), 6);
+    let feed = status.read().await["feed"].clone();
+    assert_eq!(feed.consecutive_failures, 2);
+    assert_eq!(
+        feed.last_error.as_deref(),
+        Some("Invalid request: feed is down")
+    );
+    assert!(feed.last_success.is_none());
+    assert!(feed.open_until > feed.last_attempt);
+
+    // Skipped while open
+    assert_eq!(
+        run().await.unwrap(),
+        Outcome::Skipped {
+            open_until: feed.open_until.unwrap()
+        }
+    );
+    assert_eq!(calls.load(Ordering::SeqCst), 6);
+    let feed = status.read().await["feed"].clone();
+    assert_eq!(feed.skipped_runs, 1);
+    assert!(feed.last_skipped.is_some());
+}
+
+#[tokio::test]
+async fn test_retry_settings() {
+    let source: CalendarSource = serde_json::from_value(json!({
+        "name": "work",
+        "url": "https://example.com/work.ics",
+        "kind": "event",
+        "retry": {"attempts": 5, "cooldown": 30}
+    }))
+    .unwrap();
+    let retry = source.retry.clone().unwrap();
+    // Settings left out keep their defaults
+    assert_eq!(retry.attempts, 5);
+    assert_eq!(retry.cooldown, Duration::from_secs(30));
+    assert_eq!(retry.base_delay, RetryPolicy::default().base_delay);
+    assert!(retry.validate().is_ok());
+
+    let mut cfg = (*TestApp::new().await.cfg).clone();
+    cfg.calendar_sources = vec![source];
+    cfg.ha_retry = policy();
+    assert_eq!(cfg.retry("work"), retry);
+    assert_eq!(cfg.retry(cron::FORECAST_SOURCE), policy());
+
+    let invalid = [
+        json!({"attempts": 0}),
+        json!({"cooldown": 7200, "max_cooldown": 3600}),
+    ];
+    for v in invalid {
+        let retry: RetryPolicy = serde_json::from_value(v.clone()).unwrap();
+        assert!(retry.validate().is_err(), "{v} should be invalid");
+    }
+    assert!(serde_json::from_value::<RetryPolicy>(json!({"tries": 3})).is_err());
+}